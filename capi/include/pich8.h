@@ -0,0 +1,87 @@
+/*
+ * C interface of the pich8 CHIP-8, SUPER-CHIP and XO-CHIP interpreter.
+ *
+ * The interpreter runs as fast as it is ticked, the caller decides about the speed:
+ * usually a number of instructions is executed per 60 Hz frame, followed by pich8_update_timers().
+ * All functions accept a null handle and return an error or do nothing in that case.
+ * Internal errors of the interpreter are caught and reported through pich8_last_error().
+ */
+
+#ifndef PICH8_H
+#define PICH8_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define PICH8_OK 0
+#define PICH8_ERROR -1
+#define PICH8_INVALID_ARGUMENT -2
+
+#define PICH8_QUIRK_LOAD_STORE 0
+#define PICH8_QUIRK_SHIFT 1
+#define PICH8_QUIRK_DRAW 2
+#define PICH8_QUIRK_JUMP 3
+#define PICH8_QUIRK_VF_ORDER 4
+#define PICH8_QUIRK_PARTIAL_WRAP_H 5
+#define PICH8_QUIRK_PARTIAL_WRAP_V 6
+
+typedef struct Pich8 Pich8;
+
+/* Creates a new instance, which has to be freed with pich8_free(). */
+Pich8 *pich8_new(void);
+void pich8_free(Pich8 *pich8);
+
+/* Returns the message of the last PICH8_ERROR, valid until the next call with this handle. */
+const char *pich8_last_error(const Pich8 *pich8);
+
+/* Resets the CPU and loads the ROM, the quirks are kept. */
+int pich8_load_rom(Pich8 *pich8, const uint8_t *data, size_t len);
+
+/* Enables or disables one of the PICH8_QUIRK_* quirks, all except partial wrapping are enabled by default. */
+int pich8_set_quirk(Pich8 *pich8, int quirk, bool enabled);
+
+/* Sets the state of a key of the hex keypad (0x0 to 0xF). */
+int pich8_set_key(Pich8 *pich8, uint8_t key, bool pressed);
+
+/* Executes the given number of instructions, stops at the first error. */
+int pich8_tick(Pich8 *pich8, uint32_t instructions);
+
+/* Decrements the delay and sound timers, should be called at 60 Hz. */
+void pich8_update_timers(Pich8 *pich8);
+
+/* Returns true while the sound timer is active and a tone should be played. */
+bool pich8_sound_active(const Pich8 *pich8);
+
+/* Returns true if the screen changed since the last call. */
+bool pich8_take_draw_flag(Pich8 *pich8);
+
+/* Current screen size in pixels, 64x32, 64x64 or 128x64. */
+size_t pich8_width(const Pich8 *pich8);
+size_t pich8_height(const Pich8 *pich8);
+
+/*
+ * Copies the screen row by row into the buffer and returns the required size in bytes.
+ * Nothing is written if the buffer is null or too small, so the size can be queried first.
+ *
+ * The planes variant writes one byte per pixel with bit 0 for plane 1 and bit 1 for plane 2 (XO-CHIP).
+ * The RGB variant writes three bytes per pixel using the palette of 4 RGB colors
+ * (background, plane 1, plane 2, both planes) or the default palette of pich8 if null.
+ */
+size_t pich8_framebuffer_planes(const Pich8 *pich8, uint8_t *buf, size_t len);
+size_t pich8_framebuffer_rgb(const Pich8 *pich8, const uint8_t *palette, uint8_t *buf, size_t len);
+
+/* Serializes the CPU state, the returned buffer has to be freed with pich8_free_state(). */
+uint8_t *pich8_save_state(Pich8 *pich8, size_t *len);
+void pich8_free_state(uint8_t *state, size_t len);
+int pich8_load_state(Pich8 *pich8, const uint8_t *state, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PICH8_H */
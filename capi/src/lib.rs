@@ -0,0 +1,385 @@
+//! C ABI for embedding the interpreter, see `include/pich8.h` for the documentation of each function.
+//! All functions accept a null handle and return an error or do nothing in that case.
+//! Panics of the interpreter are caught and reported as errors instead of unwinding into C.
+
+use pich8_core::catch_panic;
+use pich8_core::cpu::CPU;
+use pich8_core::video_memory::Plane;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::slice;
+
+pub const PICH8_OK: c_int = 0;
+pub const PICH8_ERROR: c_int = -1;
+pub const PICH8_INVALID_ARGUMENT: c_int = -2;
+
+pub const PICH8_QUIRK_LOAD_STORE: c_int = 0;
+pub const PICH8_QUIRK_SHIFT: c_int = 1;
+pub const PICH8_QUIRK_DRAW: c_int = 2;
+pub const PICH8_QUIRK_JUMP: c_int = 3;
+pub const PICH8_QUIRK_VF_ORDER: c_int = 4;
+pub const PICH8_QUIRK_PARTIAL_WRAP_H: c_int = 5;
+pub const PICH8_QUIRK_PARTIAL_WRAP_V: c_int = 6;
+
+/// Background, plane 1, plane 2 and both planes.
+const DEFAULT_PALETTE: [u8; 12] = [0, 0, 0, 255, 255, 255, 85, 85, 85, 170, 170, 170];
+
+/// Opaque handle owning the CPU, the keypad state and the last error message.
+pub struct Pich8 {
+    cpu: CPU,
+    keys: [bool; 16],
+    error: CString,
+}
+
+impl Pich8 {
+    fn result<E: ToString>(&mut self, result: Result<(), E>) -> c_int {
+        match result {
+            Ok(_) => PICH8_OK,
+            Err(e) => {
+                self.error = CString::new(e.to_string()).unwrap_or_default();
+                PICH8_ERROR
+            }
+        }
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> usize {
+        let vmem = self.cpu.vmem();
+        vmem.get_plane(Plane::First, x, y) as usize
+            | (vmem.get_plane(Plane::Second, x, y) as usize) << 1
+    }
+
+    /// Writes the pixels using the given function if the buffer is large enough, returns the required size.
+    fn write_framebuffer<F>(&self, buf: *mut u8, len: usize, bytes_per_pixel: usize, f: F) -> usize
+    where
+        F: Fn(usize, &mut [u8]),
+    {
+        let (width, height) = (self.cpu.vmem().width(), self.cpu.vmem().height());
+        let size = width * height * bytes_per_pixel;
+        if !buf.is_null() && len >= size {
+            let buf = unsafe { slice::from_raw_parts_mut(buf, size) };
+            for (idx, chunk) in buf.chunks_mut(bytes_per_pixel).enumerate() {
+                f(self.pixel(idx % width, idx / width), chunk);
+            }
+        }
+        size
+    }
+}
+
+/// Creates a new instance, which has to be freed with `pich8_free`.
+#[no_mangle]
+pub extern "C" fn pich8_new() -> *mut Pich8 {
+    Box::into_raw(Box::new(Pich8 {
+        cpu: CPU::new(),
+        keys: [false; 16],
+        error: CString::default(),
+    }))
+}
+
+/// # Safety
+/// The handle must have been created by `pich8_new` and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn pich8_free(pich8: *mut Pich8) {
+    if !pich8.is_null() {
+        drop(Box::from_raw(pich8));
+    }
+}
+
+/// # Safety
+/// The handle must be valid or null, the string is valid until the next call with this handle.
+#[no_mangle]
+pub unsafe extern "C" fn pich8_last_error(pich8: *const Pich8) -> *const c_char {
+    match pich8.as_ref() {
+        Some(pich8) => pich8.error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// # Safety
+/// The handle must be valid or null, the data must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn pich8_load_rom(pich8: *mut Pich8, data: *const u8, len: usize) -> c_int {
+    match pich8.as_mut() {
+        Some(pich8) if !data.is_null() => {
+            let rom = slice::from_raw_parts(data, len);
+            let result = catch_panic(|| {
+                let mut cpu = CPU::new();
+                cpu.load_rom(rom).map(|_| cpu)
+            });
+            let result = result.map(|mut cpu| {
+                copy_quirks(&pich8.cpu, &mut cpu);
+                pich8.cpu = cpu;
+            });
+            pich8.result(result)
+        }
+        _ => PICH8_INVALID_ARGUMENT,
+    }
+}
+
+/// # Safety
+/// The handle must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn pich8_set_quirk(pich8: *mut Pich8, quirk: c_int, enabled: bool) -> c_int {
+    let cpu = match pich8.as_mut() {
+        Some(pich8) => &mut pich8.cpu,
+        None => return PICH8_INVALID_ARGUMENT,
+    };
+    match quirk {
+        PICH8_QUIRK_LOAD_STORE => cpu.quirk_load_store = enabled,
+        PICH8_QUIRK_SHIFT => cpu.quirk_shift = enabled,
+        PICH8_QUIRK_DRAW => cpu.quirk_draw = enabled,
+        PICH8_QUIRK_JUMP => cpu.quirk_jump = enabled,
+        PICH8_QUIRK_VF_ORDER => cpu.quirk_vf_order = enabled,
+        PICH8_QUIRK_PARTIAL_WRAP_H => cpu.quirk_partialwrap_h = enabled,
+        PICH8_QUIRK_PARTIAL_WRAP_V => cpu.quirk_partialwrap_v = enabled,
+        _ => return PICH8_INVALID_ARGUMENT,
+    }
+    PICH8_OK
+}
+
+/// # Safety
+/// The handle must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn pich8_set_key(pich8: *mut Pich8, key: u8, pressed: bool) -> c_int {
+    match pich8.as_mut() {
+        Some(pich8) if key < 16 => {
+            pich8.keys[key as usize] = pressed;
+            PICH8_OK
+        }
+        _ => PICH8_INVALID_ARGUMENT,
+    }
+}
+
+/// # Safety
+/// The handle must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn pich8_tick(pich8: *mut Pich8, instructions: u32) -> c_int {
+    match pich8.as_mut() {
+        Some(pich8) => {
+            let (cpu, keys) = (&mut pich8.cpu, pich8.keys);
+            let result = catch_panic(|| (0..instructions).try_for_each(|_| cpu.tick(&keys)));
+            pich8.result(result)
+        }
+        None => PICH8_INVALID_ARGUMENT,
+    }
+}
+
+/// # Safety
+/// The handle must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn pich8_update_timers(pich8: *mut Pich8) {
+    if let Some(pich8) = pich8.as_mut() {
+        let cpu = &mut pich8.cpu;
+        let result = catch_panic(|| {
+            cpu.update_timers();
+            Ok::<_, String>(())
+        });
+        pich8.result(result);
+    }
+}
+
+/// # Safety
+/// The handle must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn pich8_sound_active(pich8: *const Pich8) -> bool {
+    match pich8.as_ref() {
+        Some(pich8) => pich8.cpu.ST() > 0,
+        None => false,
+    }
+}
+
+/// # Safety
+/// The handle must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn pich8_take_draw_flag(pich8: *mut Pich8) -> bool {
+    match pich8.as_mut() {
+        Some(pich8) => std::mem::replace(&mut pich8.cpu.draw, false),
+        None => false,
+    }
+}
+
+/// # Safety
+/// The handle must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn pich8_width(pich8: *const Pich8) -> usize {
+    pich8.as_ref().map_or(0, |pich8| pich8.cpu.vmem().width())
+}
+
+/// # Safety
+/// The handle must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn pich8_height(pich8: *const Pich8) -> usize {
+    pich8.as_ref().map_or(0, |pich8| pich8.cpu.vmem().height())
+}
+
+/// # Safety
+/// The handle must be valid or null, the buffer must be null or point to `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn pich8_framebuffer_planes(
+    pich8: *const Pich8,
+    buf: *mut u8,
+    len: usize,
+) -> usize {
+    match pich8.as_ref() {
+        Some(pich8) => pich8.write_framebuffer(buf, len, 1, |pixel, out| out[0] = pixel as u8),
+        None => 0,
+    }
+}
+
+/// # Safety
+/// The handle must be valid or null, the palette must be null or point to 12 readable bytes
+/// and the buffer must be null or point to `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn pich8_framebuffer_rgb(
+    pich8: *const Pich8,
+    palette: *const u8,
+    buf: *mut u8,
+    len: usize,
+) -> usize {
+    let palette = if palette.is_null() {
+        &DEFAULT_PALETTE
+    } else {
+        slice::from_raw_parts(palette, DEFAULT_PALETTE.len())
+    };
+    match pich8.as_ref() {
+        Some(pich8) => pich8.write_framebuffer(buf, len, 3, |pixel, out| {
+            out.copy_from_slice(&palette[pixel * 3..pixel * 3 + 3])
+        }),
+        None => 0,
+    }
+}
+
+/// # Safety
+/// The handle must be valid or null, the length must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn pich8_save_state(pich8: *mut Pich8, len: *mut usize) -> *mut u8 {
+    let pich8 = match pich8.as_mut() {
+        Some(pich8) if !len.is_null() => pich8,
+        _ => return ptr::null_mut(),
+    };
+    let cpu = &pich8.cpu;
+    match catch_panic(|| cpu.save_state()) {
+        Ok(state) => {
+            let state = state.into_boxed_slice();
+            *len = state.len();
+            Box::into_raw(state) as *mut u8
+        }
+        Err(msg) => {
+            pich8.result(Err(msg));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+/// The state must have been returned by `pich8_save_state` together with its length.
+#[no_mangle]
+pub unsafe extern "C" fn pich8_free_state(state: *mut u8, len: usize) {
+    if !state.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(state, len)));
+    }
+}
+
+/// # Safety
+/// The handle must be valid or null, the state must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn pich8_load_state(
+    pich8: *mut Pich8,
+    state: *const u8,
+    len: usize,
+) -> c_int {
+    match pich8.as_mut() {
+        Some(pich8) if !state.is_null() => {
+            let state = slice::from_raw_parts(state, len);
+            match catch_panic(|| CPU::from_state(state)) {
+                Ok(cpu) => {
+                    pich8.cpu = cpu;
+                    PICH8_OK
+                }
+                Err(msg) => pich8.result(Err(msg)),
+            }
+        }
+        _ => PICH8_INVALID_ARGUMENT,
+    }
+}
+
+fn copy_quirks(from: &CPU, to: &mut CPU) {
+    to.quirk_load_store = from.quirk_load_store;
+    to.quirk_shift = from.quirk_shift;
+    to.quirk_draw = from.quirk_draw;
+    to.quirk_jump = from.quirk_jump;
+    to.quirk_vf_order = from.quirk_vf_order;
+    to.quirk_partialwrap_h = from.quirk_partialwrap_h;
+    to.quirk_partialwrap_v = from.quirk_partialwrap_v;
+}
+
+#[cfg(test)]
+mod capi_test {
+    use super::*;
+
+    #[test]
+    fn test_capi() {
+        unsafe {
+            let pich8 = pich8_new();
+            // Draw the font sprite for 0 at 0,0
+            let rom = [0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05];
+            assert_eq!(pich8_load_rom(pich8, rom.as_ptr(), rom.len()), PICH8_OK);
+            assert_eq!(pich8_set_key(pich8, 16, true), PICH8_INVALID_ARGUMENT);
+            assert_eq!(pich8_set_quirk(pich8, PICH8_QUIRK_SHIFT, false), PICH8_OK);
+            assert_eq!(pich8_tick(pich8, 3), PICH8_OK);
+            assert!(pich8_take_draw_flag(pich8));
+
+            assert_eq!(pich8_framebuffer_planes(pich8, ptr::null_mut(), 0), 64 * 32);
+            let mut planes = vec![0; 64 * 32];
+            pich8_framebuffer_planes(pich8, planes.as_mut_ptr(), planes.len());
+            assert_eq!(&planes[0..5], &[1, 1, 1, 1, 0]);
+            let mut rgb = vec![0; 64 * 32 * 3];
+            let size = pich8_framebuffer_rgb(pich8, ptr::null(), rgb.as_mut_ptr(), rgb.len());
+            assert_eq!(size, rgb.len());
+            assert_eq!(&rgb[9..15], &[255, 255, 255, 0, 0, 0]);
+
+            let mut len = 0;
+            let state = pich8_save_state(pich8, &mut len);
+            assert!(!state.is_null());
+            let other = pich8_new();
+            assert_eq!(pich8_load_state(other, state, len), PICH8_OK);
+            assert_eq!(pich8_framebuffer_planes(other, ptr::null_mut(), 0), 64 * 32);
+            pich8_free_state(state, len);
+
+            assert_eq!(pich8_load_state(other, rom.as_ptr(), 1), PICH8_ERROR);
+            assert!(!pich8_last_error(other).is_null());
+
+            pich8_free(other);
+            pich8_free(pich8);
+        }
+    }
+
+    #[test]
+    fn test_palette() {
+        unsafe {
+            let pich8 = pich8_new();
+            // Select plane 2 and draw the font sprite for 0 at 0,0
+            let rom = [0xF2, 0x01, 0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05];
+            assert_eq!(pich8_load_rom(pich8, rom.as_ptr(), rom.len()), PICH8_OK);
+            assert_eq!(pich8_tick(pich8, 4), PICH8_OK);
+            let mut rgb = vec![0; 64 * 32 * 3];
+            pich8_framebuffer_rgb(pich8, ptr::null(), rgb.as_mut_ptr(), rgb.len());
+            assert_eq!(&rgb[0..3], &[85, 85, 85]);
+            pich8_free(pich8);
+        }
+    }
+
+    #[test]
+    fn test_panic() {
+        unsafe {
+            let pich8 = pich8_new();
+            // Store V0 and V1 at I = 0xFFFF, past the end of the memory
+            let rom = [0xF0, 0x00, 0xFF, 0xFF, 0xF1, 0x55];
+            assert_eq!(pich8_load_rom(pich8, rom.as_ptr(), rom.len()), PICH8_OK);
+            assert_eq!(pich8_tick(pich8, 2), PICH8_ERROR);
+            let error = std::ffi::CStr::from_ptr(pich8_last_error(pich8));
+            assert!(error.to_string_lossy().starts_with("Internal error"));
+            pich8_free(pich8);
+        }
+    }
+}
@@ -0,0 +1,750 @@
+//! libretro core running the interpreter at 60 frames per second.
+//!
+//! Each `retro_run` executes the configured number of instructions, decrements the timers once
+//! and outputs the video memory with the configured palette as well as one frame of audio.
+
+mod libretro;
+
+use libretro::*;
+use pich8_core::catch_panic;
+use pich8_core::cpu::CPU;
+use pich8_core::video_memory::Plane;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::sync::Mutex;
+use std::{fs, ptr, slice};
+
+/// Creates a pointer to a static C string.
+macro_rules! cstr {
+    ($s:expr) => {
+        concat!($s, "\0").as_ptr() as *const c_char
+    };
+}
+
+const FPS: f64 = 60.0;
+const SAMPLE_RATE: f64 = 44100.0;
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FPS) as usize;
+const BEEP_FREQ: f64 = 440.0;
+const BUF_FREQ: f64 = 4000.0;
+const AMPLITUDE: f64 = 4000.0;
+const MAX_WIDTH: usize = 128;
+const MAX_HEIGHT: usize = 64;
+/// States are stored with their length in front and padded, as frontends expect a fixed size.
+/// MessagePack stores each byte of the memory >= 0x80 in two bytes and each pixel of both planes in
+/// one byte, the remaining fields fit into the last 4 KiB.
+const SERIALIZE_SIZE: usize = 4 + 2 * 0x10000 + 2 * MAX_WIDTH * MAX_HEIGHT + 4 * 1024;
+
+const OPTION_CYCLES_PER_FRAME: &str = "pich8_cycles_per_frame";
+const OPTION_PALETTE: &str = "pich8_palette";
+/// Keys of the quirk options in the order of the CPU quirks, see `Core::apply_quirks`.
+const OPTION_QUIRKS: [&str; 7] = [
+    "pich8_quirk_load_store",
+    "pich8_quirk_shift",
+    "pich8_quirk_draw",
+    "pich8_quirk_jump",
+    "pich8_quirk_vf_order",
+    "pich8_quirk_partial_wrap_h",
+    "pich8_quirk_partial_wrap_v",
+];
+
+/// The color presets of the GUI as XRGB8888 (background, plane 1, plane 2, both planes).
+const PALETTES: [(&str, [u32; 4]); 7] = [
+    ("Default", [0x000000, 0xFFFFFF, 0x555555, 0xAAAAAA]),
+    ("Octo Classic", [0x996600, 0xFFCC00, 0xFF6600, 0x662200]),
+    ("Octo LCD", [0xF9FFB3, 0x3D8026, 0xABCC47, 0x00131A]),
+    ("Octo Hotdog", [0x000000, 0xFF0000, 0xFFFF00, 0xFFFFFF]),
+    ("Octo Gray", [0xAAAAAA, 0x000000, 0xFFFFFF, 0x666666]),
+    ("Octo CGA 0", [0x000000, 0x00FF00, 0xFF0000, 0xFFFF00]),
+    ("Octo CGA 1", [0x000000, 0xFF00FF, 0x00FFFF, 0xFFFFFF]),
+];
+
+/// Maps the RetroPad to the hex keypad, the d-pad uses the keys WASD are mapped to in the GUI.
+const KEYMAP: [(c_uint, usize, &str); 16] = [
+    (RETRO_DEVICE_ID_JOYPAD_UP, 0x5, "Up (5)"),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, 0x8, "Down (8)"),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, 0x7, "Left (7)"),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, 0x9, "Right (9)"),
+    (RETRO_DEVICE_ID_JOYPAD_A, 0x6, "6"),
+    (RETRO_DEVICE_ID_JOYPAD_B, 0x4, "4"),
+    (RETRO_DEVICE_ID_JOYPAD_Y, 0x1, "1"),
+    (RETRO_DEVICE_ID_JOYPAD_X, 0x2, "2"),
+    (RETRO_DEVICE_ID_JOYPAD_L, 0x3, "3"),
+    (RETRO_DEVICE_ID_JOYPAD_R, 0xC, "C"),
+    (RETRO_DEVICE_ID_JOYPAD_L2, 0xD, "D"),
+    (RETRO_DEVICE_ID_JOYPAD_R2, 0xE, "E"),
+    (RETRO_DEVICE_ID_JOYPAD_L3, 0xA, "A"),
+    (RETRO_DEVICE_ID_JOYPAD_R3, 0xB, "B"),
+    (RETRO_DEVICE_ID_JOYPAD_SELECT, 0x0, "0"),
+    (RETRO_DEVICE_ID_JOYPAD_START, 0xF, "F"),
+];
+
+struct Callbacks {
+    environment: Option<RetroEnvironment>,
+    video_refresh: Option<RetroVideoRefresh>,
+    audio_sample_batch: Option<RetroAudioSampleBatch>,
+    input_poll: Option<RetroInputPoll>,
+    input_state: Option<RetroInputState>,
+}
+
+struct Core {
+    cpu: CPU,
+    rom: Vec<u8>,
+    cycles_per_frame: u32,
+    palette: [u32; 4],
+    quirks: [bool; 7],
+    audio_position: u64,
+    framebuffer: Vec<u32>,
+    audio: Vec<i16>,
+}
+
+struct State {
+    callbacks: Callbacks,
+    core: Option<Core>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    callbacks: Callbacks {
+        environment: None,
+        video_refresh: None,
+        audio_sample_batch: None,
+        input_poll: None,
+        input_state: None,
+    },
+    core: None,
+});
+
+fn state() -> std::sync::MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Callbacks {
+    fn environment(&self, cmd: c_uint, data: *mut c_void) -> bool {
+        match self.environment {
+            Some(environment) => environment(cmd, data),
+            None => false,
+        }
+    }
+
+    /// Returns the value of a core option if the frontend provides it.
+    fn variable(&self, key: &str) -> Option<String> {
+        let key = format!("{}\0", key);
+        let mut var = RetroVariable {
+            key: key.as_ptr() as *const c_char,
+            value: ptr::null(),
+        };
+        if self.environment(
+            RETRO_ENVIRONMENT_GET_VARIABLE,
+            &mut var as *mut _ as *mut c_void,
+        ) && !var.value.is_null()
+        {
+            let value = unsafe { CStr::from_ptr(var.value) };
+            Some(value.to_string_lossy().into_owned())
+        } else {
+            None
+        }
+    }
+}
+
+impl Core {
+    fn new(rom: Vec<u8>) -> Result<Self, String> {
+        let mut core = Self {
+            cpu: CPU::new(),
+            rom,
+            cycles_per_frame: 12,
+            palette: PALETTES[0].1,
+            quirks: [true, true, true, true, true, false, false],
+            audio_position: 0,
+            framebuffer: vec![0; MAX_WIDTH * MAX_HEIGHT],
+            audio: vec![0; SAMPLES_PER_FRAME * 2],
+        };
+        core.reset()?;
+        Ok(core)
+    }
+
+    fn reset(&mut self) -> Result<(), String> {
+        let mut cpu = CPU::new();
+        cpu.load_rom(&self.rom)?;
+        self.cpu = cpu;
+        Ok(())
+    }
+
+    fn update_options(&mut self, callbacks: &Callbacks) {
+        if let Some(cycles) = callbacks
+            .variable(OPTION_CYCLES_PER_FRAME)
+            .and_then(|value| value.parse().ok())
+        {
+            self.cycles_per_frame = cycles;
+        }
+        if let Some(palette) = callbacks
+            .variable(OPTION_PALETTE)
+            .and_then(|value| PALETTES.iter().find(|(name, _)| *name == value))
+        {
+            self.palette = palette.1;
+        }
+        for (quirk, key) in self.quirks.iter_mut().zip(OPTION_QUIRKS.iter()) {
+            if let Some(value) = callbacks.variable(key) {
+                *quirk = value == "enabled";
+            }
+        }
+    }
+
+    fn apply_quirks(&mut self) {
+        self.cpu.quirk_load_store = self.quirks[0];
+        self.cpu.quirk_shift = self.quirks[1];
+        self.cpu.quirk_draw = self.quirks[2];
+        self.cpu.quirk_jump = self.quirks[3];
+        self.cpu.quirk_vf_order = self.quirks[4];
+        self.cpu.quirk_partialwrap_h = self.quirks[5];
+        self.cpu.quirk_partialwrap_v = self.quirks[6];
+    }
+
+    fn run_frame(&mut self, keys: &[bool; 16]) {
+        self.apply_quirks();
+        for _ in 0..self.cycles_per_frame {
+            if self.cpu.tick(keys).is_err() {
+                break;
+            }
+        }
+        self.generate_audio();
+        self.cpu.update_timers();
+    }
+
+    /// Generates the XO-CHIP pattern if set or a beep while the sound timer is active.
+    fn generate_audio(&mut self) {
+        let active = self.cpu.ST() > 0;
+        let buffer = self.cpu.audio_buffer();
+        for frame in self.audio.chunks_mut(2) {
+            let time = self.audio_position as f64 / SAMPLE_RATE;
+            self.audio_position += 1;
+            let sample = match (active, buffer) {
+                (false, _) => 0.0,
+                (true, Some(buffer)) => {
+                    let bit = (time * BUF_FREQ) as usize % (buffer.len() * 8);
+                    if buffer[bit / 8] >> (7 - bit % 8) & 0b1 == 0b1 {
+                        AMPLITUDE
+                    } else {
+                        -AMPLITUDE
+                    }
+                }
+                (true, None) => (time * BEEP_FREQ * std::f64::consts::TAU).sin() * AMPLITUDE,
+            };
+            frame[0] = sample as i16;
+            frame[1] = sample as i16;
+        }
+    }
+
+    /// Renders the video memory and returns the size of the frame.
+    fn render(&mut self) -> (usize, usize) {
+        let vmem = self.cpu.vmem();
+        let (width, height) = (vmem.width(), vmem.height());
+        for y in 0..height {
+            for x in 0..width {
+                let color = vmem.get_plane(Plane::First, x, y) as usize
+                    | (vmem.get_plane(Plane::Second, x, y) as usize) << 1;
+                self.framebuffer[y * width + x] = self.palette[color];
+            }
+        }
+        (width, height)
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(environment: RetroEnvironment) {
+    let mut state = state();
+    state.callbacks.environment = Some(environment);
+
+    let palettes = PALETTES
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join("|");
+    let definitions = [
+        (
+            OPTION_CYCLES_PER_FRAME,
+            String::from("Instructions per frame; 12|7|10|15|20|30|50|100|200|500|1000"),
+        ),
+        (OPTION_PALETTE, format!("Color palette; {}", palettes)),
+        (
+            OPTION_QUIRKS[0],
+            String::from("Load/Store quirk; enabled|disabled"),
+        ),
+        (
+            OPTION_QUIRKS[1],
+            String::from("Shift quirk; enabled|disabled"),
+        ),
+        (
+            OPTION_QUIRKS[2],
+            String::from("Draw quirk; enabled|disabled"),
+        ),
+        (
+            OPTION_QUIRKS[3],
+            String::from("Jump quirk; enabled|disabled"),
+        ),
+        (
+            OPTION_QUIRKS[4],
+            String::from("VF order quirk; enabled|disabled"),
+        ),
+        (
+            OPTION_QUIRKS[5],
+            String::from("Partial wrapping - Horizontal; disabled|enabled"),
+        ),
+        (
+            OPTION_QUIRKS[6],
+            String::from("Partial wrapping - Vertical; disabled|enabled"),
+        ),
+    ];
+    let strings: Vec<(String, String)> = definitions
+        .iter()
+        .map(|(key, value)| (format!("{}\0", key), format!("{}\0", value)))
+        .collect();
+    let mut variables: Vec<RetroVariable> = strings
+        .iter()
+        .map(|(key, value)| RetroVariable {
+            key: key.as_ptr() as *const c_char,
+            value: value.as_ptr() as *const c_char,
+        })
+        .collect();
+    variables.push(RetroVariable {
+        key: ptr::null(),
+        value: ptr::null(),
+    });
+    state.callbacks.environment(
+        RETRO_ENVIRONMENT_SET_VARIABLES,
+        variables.as_mut_ptr() as *mut c_void,
+    );
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(video_refresh: RetroVideoRefresh) {
+    state().callbacks.video_refresh = Some(video_refresh);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_audio_sample: RetroAudioSample) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(audio_sample_batch: RetroAudioSampleBatch) {
+    state().callbacks.audio_sample_batch = Some(audio_sample_batch);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(input_poll: RetroInputPoll) {
+    state().callbacks.input_poll = Some(input_poll);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(input_state: RetroInputState) {
+    state().callbacks.input_state = Some(input_state);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    state().core = None;
+}
+
+/// # Safety
+/// The info must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: cstr!("pich8"),
+        library_version: cstr!(env!("CARGO_PKG_VERSION")),
+        valid_extensions: cstr!("ch8|c8|sc8|xo8"),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+/// The info must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: 64,
+            base_height: 32,
+            max_width: MAX_WIDTH as c_uint,
+            max_height: MAX_HEIGHT as c_uint,
+            aspect_ratio: 2.0,
+        },
+        timing: RetroSystemTiming {
+            fps: FPS,
+            sample_rate: SAMPLE_RATE,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = state().core.as_mut() {
+        let _ = catch_panic(|| core.reset());
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let mut state = state();
+    let State { callbacks, core } = &mut *state;
+    let core = match core {
+        Some(core) => core,
+        None => return,
+    };
+
+    let mut updated = false;
+    if callbacks.environment(
+        RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE,
+        &mut updated as *mut bool as *mut c_void,
+    ) && updated
+    {
+        core.update_options(callbacks);
+    }
+
+    let mut keys = [false; 16];
+    if let (Some(input_poll), Some(input_state)) = (callbacks.input_poll, callbacks.input_state) {
+        input_poll();
+        for (id, key, _) in KEYMAP.iter() {
+            keys[*key] = input_state(0, RETRO_DEVICE_JOYPAD, 0, *id) != 0;
+        }
+    }
+
+    // Panics must not unwind into the frontend
+    let (width, height) = match catch_panic(|| {
+        core.run_frame(&keys);
+        Ok::<_, String>(core.render())
+    }) {
+        Ok(size) => size,
+        Err(_) => return,
+    };
+    if let Some(video_refresh) = callbacks.video_refresh {
+        video_refresh(
+            core.framebuffer.as_ptr() as *const c_void,
+            width as c_uint,
+            height as c_uint,
+            width * 4,
+        );
+    }
+    if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+        audio_sample_batch(core.audio.as_ptr(), SAMPLES_PER_FRAME);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    SERIALIZE_SIZE
+}
+
+/// # Safety
+/// The data must point to `size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let state = match state()
+        .core
+        .as_ref()
+        .map(|core| catch_panic(|| core.cpu.save_state()))
+    {
+        Some(Ok(state)) => state,
+        _ => return false,
+    };
+    if data.is_null() || size < SERIALIZE_SIZE || state.len() + 4 > SERIALIZE_SIZE {
+        return false;
+    }
+    let data = slice::from_raw_parts_mut(data as *mut u8, SERIALIZE_SIZE);
+    data[..4].copy_from_slice(&(state.len() as u32).to_le_bytes());
+    data[4..4 + state.len()].copy_from_slice(&state);
+    data[4 + state.len()..].iter_mut().for_each(|b| *b = 0);
+    true
+}
+
+/// # Safety
+/// The data must point to `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() || size < 4 {
+        return false;
+    }
+    let data = slice::from_raw_parts(data as *const u8, size);
+    let len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    if len + 4 > size {
+        return false;
+    }
+    match (
+        state().core.as_mut(),
+        catch_panic(|| CPU::from_state(&data[4..4 + len])),
+    ) {
+        (Some(core), Ok(cpu)) => {
+            core.cpu = cpu;
+            true
+        }
+        _ => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+/// The game info must be null or valid.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    let game = match game.as_ref() {
+        Some(game) => game,
+        None => return false,
+    };
+    let rom = if !game.data.is_null() {
+        slice::from_raw_parts(game.data as *const u8, game.size).to_vec()
+    } else if !game.path.is_null() {
+        match fs::read(CStr::from_ptr(game.path).to_string_lossy().as_ref()) {
+            Ok(rom) => rom,
+            Err(_) => return false,
+        }
+    } else {
+        return false;
+    };
+
+    let mut state = state();
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !state.callbacks.environment(
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+        &mut format as *mut c_uint as *mut c_void,
+    ) {
+        return false;
+    }
+
+    let descriptions: Vec<String> = KEYMAP
+        .iter()
+        .map(|(_, _, description)| format!("{}\0", description))
+        .collect();
+    let mut descriptors: Vec<RetroInputDescriptor> = KEYMAP
+        .iter()
+        .zip(descriptions.iter())
+        .map(|((id, _, _), description)| RetroInputDescriptor {
+            port: 0,
+            device: RETRO_DEVICE_JOYPAD,
+            index: 0,
+            id: *id,
+            description: description.as_ptr() as *const c_char,
+        })
+        .collect();
+    descriptors.push(RetroInputDescriptor {
+        port: 0,
+        device: 0,
+        index: 0,
+        id: 0,
+        description: ptr::null(),
+    });
+    state.callbacks.environment(
+        RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS,
+        descriptors.as_mut_ptr() as *mut c_void,
+    );
+
+    match catch_panic(|| Core::new(rom)) {
+        Ok(mut core) => {
+            core.update_options(&state.callbacks);
+            state.core = Some(core);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const RetroGameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    state().core = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
+
+#[cfg(test)]
+mod libretro_test {
+    //! A minimal frontend driving the core through its exported functions.
+
+    use super::*;
+
+    struct Frontend {
+        variables: Vec<String>,
+        video: Vec<(usize, usize, u32)>,
+        audio_frames: usize,
+        audio_peak: i16,
+    }
+
+    static FRONTEND: Mutex<Frontend> = Mutex::new(Frontend {
+        variables: Vec::new(),
+        video: Vec::new(),
+        audio_frames: 0,
+        audio_peak: 0,
+    });
+
+    extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+        match cmd {
+            RETRO_ENVIRONMENT_SET_VARIABLES => {
+                let mut variable = data as *const RetroVariable;
+                unsafe {
+                    while !(*variable).key.is_null() {
+                        let key = CStr::from_ptr((*variable).key).to_string_lossy();
+                        FRONTEND.lock().unwrap().variables.push(key.into_owned());
+                        variable = variable.add(1);
+                    }
+                }
+                true
+            }
+            RETRO_ENVIRONMENT_GET_VARIABLE => {
+                let variable = unsafe { &mut *(data as *mut RetroVariable) };
+                let key = unsafe { CStr::from_ptr(variable.key) };
+                if key.to_bytes() == OPTION_PALETTE.as_bytes() {
+                    variable.value = cstr!("Octo Hotdog");
+                    true
+                } else {
+                    false
+                }
+            }
+            RETRO_ENVIRONMENT_SET_PIXEL_FORMAT | RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS => true,
+            _ => false,
+        }
+    }
+
+    extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+        assert_eq!(pitch, width as usize * 4);
+        let first = unsafe { *(data as *const u32) };
+        let mut frontend = FRONTEND.lock().unwrap();
+        frontend
+            .video
+            .push((width as usize, height as usize, first));
+    }
+
+    extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+        let samples = unsafe { slice::from_raw_parts(data, frames * 2) };
+        let mut frontend = FRONTEND.lock().unwrap();
+        frontend.audio_frames += frames;
+        frontend.audio_peak = samples.iter().copied().fold(0, i16::max);
+        frames
+    }
+
+    extern "C" fn input_poll() {}
+
+    extern "C" fn input_state(_port: c_uint, _device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+        (id == RETRO_DEVICE_ID_JOYPAD_UP) as i16
+    }
+
+    #[test]
+    fn test_frontend() {
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        retro_init();
+        assert_eq!(FRONTEND.lock().unwrap().variables.len(), 9);
+
+        // Wait for key 5 (up), then draw 0 at 0,0 and start the sound timer
+        let rom: [u8; 14] = [
+            0xF1, 0x0A, 0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x6A, 0x3C, 0xFA, 0x18, 0x12, 0x0C,
+        ];
+        let game = RetroGameInfo {
+            path: ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: ptr::null(),
+        };
+        unsafe {
+            assert!(retro_load_game(&game));
+        }
+        retro_run();
+        {
+            let frontend = FRONTEND.lock().unwrap();
+            assert_eq!(frontend.video, vec![(64, 32, 0xFF0000)]);
+            assert_eq!(frontend.audio_frames, SAMPLES_PER_FRAME);
+            assert!(frontend.audio_peak > 0);
+        }
+        assert_eq!(state().core.as_ref().unwrap().cpu.V()[1], 0x5);
+
+        let mut data = vec![0u8; retro_serialize_size()];
+        unsafe {
+            assert!(retro_serialize(
+                data.as_mut_ptr() as *mut c_void,
+                data.len()
+            ));
+            retro_reset();
+            assert_eq!(state().core.as_ref().unwrap().cpu.PC(), 0x200);
+            assert!(retro_unserialize(
+                data.as_ptr() as *const c_void,
+                data.len()
+            ));
+        }
+        assert_eq!(state().core.as_ref().unwrap().cpu.PC(), 0x20C);
+        retro_unload_game();
+
+        // Bytes >= 0x80 double in size, the state of a full-size ROM exceeds the size of the memory
+        let rom = vec![0xFFu8; 60000];
+        let game = RetroGameInfo {
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            ..game
+        };
+        unsafe {
+            assert!(retro_load_game(&game));
+            assert!(retro_serialize(
+                data.as_mut_ptr() as *mut c_void,
+                data.len()
+            ));
+            assert!(u32::from_le_bytes([data[0], data[1], data[2], data[3]]) > 0x10000);
+            retro_reset();
+            assert!(retro_unserialize(
+                data.as_ptr() as *const c_void,
+                data.len()
+            ));
+        }
+        assert_eq!(
+            state().core.as_ref().unwrap().cpu.mem()[0x200 + 59999],
+            0xFF
+        );
+
+        // A corrupted state is rejected and the core keeps running
+        data[4..8].copy_from_slice(&[0xFF; 4]);
+        unsafe {
+            assert!(!retro_unserialize(
+                data.as_ptr() as *const c_void,
+                data.len()
+            ));
+        }
+        retro_run();
+
+        retro_unload_game();
+        retro_deinit();
+    }
+}
@@ -0,0 +1,205 @@
+//! The emulation behind the Python `Env`, free of Python types so it can be tested without an interpreter.
+
+use pich8_core::cpu::CPU;
+use pich8_core::video_memory::Plane;
+
+/// Names of the quirks as used in the config file of pich8.
+pub const QUIRKS: [&str; 7] = [
+    "load_store",
+    "shift",
+    "draw",
+    "jump",
+    "vf_order",
+    "partial_wrap_h",
+    "partial_wrap_v",
+];
+
+pub struct Environment {
+    cpu: CPU,
+    rom: Vec<u8>,
+    /// Quirks applied to the CPU whenever it's recreated, in the order of `QUIRKS`
+    quirks: [bool; 7],
+    pub cycles_per_frame: u32,
+    pub frame: u64,
+}
+
+impl Environment {
+    pub fn new(cycles_per_frame: u32) -> Self {
+        let mut cpu = CPU::new();
+        let mut quirks = [false; 7];
+        for (quirk, name) in quirks.iter_mut().zip(QUIRKS.iter()) {
+            *quirk = *Self::quirk_mut(&mut cpu, name).unwrap();
+        }
+        Self {
+            cpu,
+            rom: Vec::new(),
+            quirks,
+            cycles_per_frame,
+            frame: 0,
+        }
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    /// Loads the given ROM, or restarts the current one.
+    pub fn reset(&mut self, rom: Option<Vec<u8>>) -> Result<(), String> {
+        if let Some(rom) = rom {
+            self.rom = rom;
+        }
+        let mut cpu = CPU::new();
+        cpu.load_rom(&self.rom)?;
+        self.cpu = cpu;
+        self.apply_quirks();
+        self.frame = 0;
+        Ok(())
+    }
+
+    /// Runs the given number of frames with the keys pressed as bitmask (bit n is key n).
+    pub fn run_frames(&mut self, keys: u16, frames: u32) -> Result<(), String> {
+        let keys: [bool; 16] = std::array::from_fn(|key| keys & (1 << key) > 0);
+        for _ in 0..frames {
+            for _ in 0..self.cycles_per_frame {
+                self.cpu.tick(&keys).map_err(|e| format!("{}", e))?;
+            }
+            self.cpu.update_timers();
+            self.frame += 1;
+        }
+        Ok(())
+    }
+
+    /// One byte per pixel holding the bits of both XO-CHIP planes.
+    pub fn framebuffer(&self) -> Vec<u8> {
+        let vmem = self.cpu.vmem();
+        (0..vmem.height())
+            .flat_map(|y| (0..vmem.width()).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                vmem.get_plane(Plane::First, x, y) as u8
+                    | (vmem.get_plane(Plane::Second, x, y) as u8) << 1
+            })
+            .collect()
+    }
+
+    pub fn state(&self) -> Result<Vec<u8>, String> {
+        self.cpu.save_state()
+    }
+
+    pub fn set_state(&mut self, state: &[u8]) -> Result<(), String> {
+        self.cpu = CPU::from_state(state)?;
+        self.apply_quirks();
+        Ok(())
+    }
+
+    /// Reads memory, addresses wrap around at the end of the memory.
+    pub fn peek(&self, address: u16, length: usize) -> Vec<u8> {
+        let mem = self.cpu.mem();
+        (0..length.min(mem.len()))
+            .map(|offset| mem[(address as usize + offset) % mem.len()])
+            .collect()
+    }
+
+    pub fn quirks(&self) -> impl Iterator<Item = (&'static str, bool)> + '_ {
+        QUIRKS.iter().copied().zip(self.quirks.iter().copied())
+    }
+
+    /// Sets a quirk, which is kept across resets.
+    pub fn set_quirk(&mut self, name: &str, enabled: bool) -> Result<(), String> {
+        let idx = QUIRKS
+            .iter()
+            .position(|quirk| *quirk == name)
+            .ok_or_else(|| format!("Unknown quirk: {}", name))?;
+        self.quirks[idx] = enabled;
+        self.apply_quirks();
+        Ok(())
+    }
+
+    fn apply_quirks(&mut self) {
+        for (name, enabled) in QUIRKS.iter().zip(self.quirks) {
+            *Self::quirk_mut(&mut self.cpu, name).unwrap() = enabled;
+        }
+    }
+
+    fn quirk_mut<'a>(cpu: &'a mut CPU, name: &str) -> Option<&'a mut bool> {
+        match name {
+            "load_store" => Some(&mut cpu.quirk_load_store),
+            "shift" => Some(&mut cpu.quirk_shift),
+            "draw" => Some(&mut cpu.quirk_draw),
+            "jump" => Some(&mut cpu.quirk_jump),
+            "vf_order" => Some(&mut cpu.quirk_vf_order),
+            "partial_wrap_h" => Some(&mut cpu.quirk_partialwrap_h),
+            "partial_wrap_v" => Some(&mut cpu.quirk_partialwrap_v),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod environment_test {
+    use super::*;
+
+    /// Counts V0 up and stores it at 0x300 every 7 instructions, V1 is set to 1 while key 5 is pressed.
+    const ROM: [u8; 16] = [
+        0x70, 0x01, // 200: ADD V0, 1
+        0xA3, 0x00, // 202: LD I, 300
+        0xF0, 0x55, // 204: LD [I], V0
+        0x61, 0x00, // 206: LD V1, 0
+        0x62, 0x05, // 208: LD V2, 5
+        0xE2, 0xA1, // 20A: SKNP V2
+        0x61, 0x01, // 20C: LD V1, 1
+        0x12, 0x00, // 20E: JP 200
+    ];
+
+    #[test]
+    fn test_step() {
+        let mut env = Environment::new(7);
+        env.reset(Some(ROM.to_vec())).unwrap();
+        assert_eq!(env.framebuffer().len(), 64 * 32);
+        assert!(env.framebuffer().iter().all(|pixel| *pixel == 0));
+
+        env.run_frames(0, 3).unwrap();
+        assert_eq!(env.frame, 3);
+        assert_eq!(env.peek(0x300, 1), [3]);
+        assert_eq!(env.cpu().V()[1], 0);
+        env.run_frames(1 << 5, 1).unwrap();
+        assert_eq!(env.cpu().V()[1], 1);
+
+        // Restarts the ROM
+        env.reset(None).unwrap();
+        assert_eq!(env.frame, 0);
+        assert_eq!(env.peek(0x300, 1), [0]);
+        assert_eq!(env.peek(0xFFFF, 2)[1..], env.peek(0, 1)[..]);
+    }
+
+    #[test]
+    fn test_state() {
+        let mut env = Environment::new(7);
+        env.reset(Some(ROM.to_vec())).unwrap();
+        env.run_frames(0, 2).unwrap();
+        let state = env.state().unwrap();
+        env.run_frames(0, 2).unwrap();
+        assert_eq!(env.peek(0x300, 1), [4]);
+        env.set_state(&state).unwrap();
+        assert_eq!(env.peek(0x300, 1), [2]);
+        assert!(env.set_state(b"invalid").is_err());
+    }
+
+    #[test]
+    fn test_quirks() {
+        let mut env = Environment::new(7);
+        assert!(env
+            .quirks()
+            .any(|(name, enabled)| name == "shift" && enabled));
+        env.set_quirk("shift", false).unwrap();
+        env.set_quirk("partial_wrap_h", true).unwrap();
+        assert!(env.set_quirk("unknown", true).is_err());
+
+        // Kept across resets
+        env.reset(Some(ROM.to_vec())).unwrap();
+        assert!(!env.cpu().quirk_shift);
+        assert!(env.cpu().quirk_partialwrap_h);
+        assert!(env
+            .quirks()
+            .any(|(name, enabled)| name == "shift" && !enabled));
+    }
+}
@@ -0,0 +1,148 @@
+mod environment;
+
+use environment::Environment;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+/// The result of a step: `(framebuffer, reward, terminated, truncated, info)`.
+type StepResult<'py> = (Bound<'py, PyBytes>, f64, bool, bool, Bound<'py, PyDict>);
+
+/// A headless CHIP-8 environment in the style of OpenAI Gym, running as fast as possible without a window.
+///
+/// A step runs whole 60 Hz frames, each consisting of `cycles_per_frame` instructions followed by a timer update.
+/// Observations are the framebuffer as bytes, one per pixel holding the bits of both XO-CHIP planes.
+#[pyclass(module = "pich8")]
+struct Env {
+    env: Environment,
+    reward_fn: Option<PyObject>,
+}
+
+#[pymethods]
+impl Env {
+    /// Creates the environment, the optional reward function is called with the environment after every step.
+    #[new]
+    #[pyo3(signature = (cycles_per_frame = 12, reward_fn = None))]
+    fn new(cycles_per_frame: u32, reward_fn: Option<PyObject>) -> Self {
+        Self {
+            env: Environment::new(cycles_per_frame),
+            reward_fn,
+        }
+    }
+
+    /// Loads the given ROM, or restarts the current one, and returns the first observation.
+    #[pyo3(signature = (rom = None))]
+    fn reset<'py>(
+        &mut self,
+        py: Python<'py>,
+        rom: Option<Vec<u8>>,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        self.env.reset(rom).map_err(PyValueError::new_err)?;
+        Ok(self.framebuffer(py))
+    }
+
+    /// Runs the given number of frames with the keys pressed as bitmask (bit n is key n).
+    /// The episode terminates if the CPU fails, the error is then included in the info.
+    #[pyo3(signature = (keys, frames = 1))]
+    fn step<'py>(slf: &Bound<'py, Self>, keys: u16, frames: u32) -> PyResult<StepResult<'py>> {
+        let py = slf.py();
+        let info = PyDict::new(py);
+        let (observation, terminated, reward_fn) = {
+            let mut env = slf.borrow_mut();
+            let result = env.env.run_frames(keys, frames);
+            if let Err(msg) = &result {
+                info.set_item("error", msg)?;
+            }
+            info.set_item("frame", env.env.frame)?;
+            let reward_fn = env.reward_fn.as_ref().map(|f| f.clone_ref(py));
+            (env.framebuffer(py), result.is_err(), reward_fn)
+        };
+        let reward = match reward_fn {
+            Some(reward_fn) => reward_fn.call1(py, (slf,))?.extract(py)?,
+            None => 0.0,
+        };
+        Ok((observation, reward, terminated, false, info))
+    }
+
+    /// Serializes the whole CPU state.
+    fn get_state<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let state = self.env.state().map_err(PyValueError::new_err)?;
+        Ok(PyBytes::new(py, &state))
+    }
+
+    fn set_state(&mut self, state: &[u8]) -> PyResult<()> {
+        self.env.set_state(state).map_err(PyValueError::new_err)
+    }
+
+    /// Reads memory, e.g. to compute rewards from the score of a game.
+    #[pyo3(signature = (address, length = 1))]
+    fn peek<'py>(&self, py: Python<'py>, address: u16, length: usize) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.env.peek(address, length))
+    }
+
+    fn registers<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let cpu = self.env.cpu();
+        let registers = PyDict::new(py);
+        registers.set_item("V", cpu.V().to_vec())?;
+        registers.set_item("I", cpu.I())?;
+        registers.set_item("PC", cpu.PC())?;
+        registers.set_item("SP", cpu.sp())?;
+        registers.set_item("DT", cpu.DT())?;
+        registers.set_item("ST", cpu.ST())?;
+        Ok(registers)
+    }
+
+    /// The quirks by name, e.g. `shift` or `load_store`.
+    fn quirks<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let quirks = PyDict::new(py);
+        for (name, enabled) in self.env.quirks() {
+            quirks.set_item(name, enabled)?;
+        }
+        Ok(quirks)
+    }
+
+    /// Enables or disables a quirk, which is kept across resets.
+    fn set_quirk(&mut self, name: &str, enabled: bool) -> PyResult<()> {
+        self.env
+            .set_quirk(name, enabled)
+            .map_err(PyValueError::new_err)
+    }
+
+    #[getter]
+    fn cycles_per_frame(&self) -> u32 {
+        self.env.cycles_per_frame
+    }
+
+    #[setter]
+    fn set_cycles_per_frame(&mut self, cycles_per_frame: u32) {
+        self.env.cycles_per_frame = cycles_per_frame;
+    }
+
+    #[getter]
+    fn frame(&self) -> u64 {
+        self.env.frame
+    }
+
+    #[getter]
+    fn width(&self) -> usize {
+        self.env.cpu().vmem().width()
+    }
+
+    #[getter]
+    fn height(&self) -> usize {
+        self.env.cpu().vmem().height()
+    }
+}
+
+impl Env {
+    fn framebuffer<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.env.framebuffer())
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "pich8")]
+fn pich8_python(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Env>()?;
+    Ok(())
+}
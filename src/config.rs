@@ -0,0 +1,439 @@
+use crate::cpu::CPU;
+use crate::emulator_core::Speed;
+use crate::file_type::Platform;
+use crate::octo_options::OctoOptions;
+use crate::rom_database::RomInfo;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Settings kept across launches, stored as `config.toml` in the platform config directory.
+/// Missing keys take their default value, so the file may only contain the changed settings.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    pub vsync: bool,
+    /// Instructions per second, unless frame timing is used
+    pub cpu_speed: u32,
+    pub frame_timing: bool,
+    pub instructions_per_frame: u32,
+    pub volume: f32,
+    pub mute: bool,
+    pub display_fps: bool,
+    pub debug: bool,
+    /// Window positions and sizes in the format of imgui
+    pub layout: String,
+    pub colors: Colors,
+    pub quirks: Quirks,
+}
+
+/// Colors as hex code like `#AABBCC`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Colors {
+    #[serde(with = "hex_color")]
+    pub background: [u8; 3],
+    #[serde(with = "hex_color")]
+    pub plane_1: [u8; 3],
+    #[serde(with = "hex_color")]
+    pub plane_2: [u8; 3],
+    #[serde(with = "hex_color")]
+    pub plane_both: [u8; 3],
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Quirks {
+    pub load_store: bool,
+    pub shift: bool,
+    pub draw: bool,
+    pub jump: bool,
+    pub vf_order: bool,
+    pub partial_wrap_h: bool,
+    pub partial_wrap_v: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            vsync: false,
+            cpu_speed: 720,
+            frame_timing: false,
+            instructions_per_frame: 15,
+            volume: 0.25,
+            mute: false,
+            display_fps: false,
+            debug: false,
+            layout: String::new(),
+            colors: Colors::default(),
+            quirks: Quirks::default(),
+        }
+    }
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Self {
+            background: [0x00; 3],
+            plane_1: [0xFF; 3],
+            plane_2: [0x55; 3],
+            plane_both: [0xAA; 3],
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            load_store: true,
+            shift: true,
+            draw: true,
+            jump: true,
+            vf_order: true,
+            partial_wrap_h: false,
+            partial_wrap_v: false,
+        }
+    }
+}
+
+impl Quirks {
+    /// Quirks of Octo, which XO-CHIP programs and Octo sources are written for.
+    pub fn octo() -> Self {
+        Self {
+            load_store: false,
+            shift: false,
+            draw: true,
+            jump: false,
+            vf_order: true,
+            partial_wrap_h: true,
+            partial_wrap_v: true,
+        }
+    }
+
+    /// Sets the quirks of the CPU.
+    pub fn apply(&self, cpu: &mut CPU) {
+        cpu.quirk_load_store = self.load_store;
+        cpu.quirk_shift = self.shift;
+        cpu.quirk_draw = self.draw;
+        cpu.quirk_jump = self.jump;
+        cpu.quirk_vf_order = self.vf_order;
+        cpu.quirk_partialwrap_h = self.partial_wrap_h;
+        cpu.quirk_partialwrap_v = self.partial_wrap_v;
+    }
+}
+
+impl Config {
+    const FILE_NAME: &'static str = "config.toml";
+
+    /// Location of the config file, `None` if the platform has no config directory.
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("pich8").join(Self::FILE_NAME))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| format!("Invalid config file: {}", e))
+    }
+
+    /// Loads the config file, a missing file results in the default settings.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("Failed to read config file: {}", e))?;
+        Self::parse(&text)
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text =
+            toml::to_string(self).map_err(|e| format!("Failed to serialize config: {}", e))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Failed to write config file: {}", e))?;
+        }
+        fs::write(path, text).map_err(|e| format!("Failed to write config file: {}", e))
+    }
+
+    pub fn speed(&self) -> Speed {
+        if self.frame_timing {
+            Speed::InstructionsPerFrame(self.instructions_per_frame)
+        } else {
+            Speed::Frequency(self.cpu_speed)
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        match speed {
+            Speed::Frequency(frequency) => {
+                self.cpu_speed = frequency;
+                self.frame_timing = false;
+            }
+            Speed::InstructionsPerFrame(instructions) => {
+                self.instructions_per_frame = instructions;
+                self.frame_timing = true;
+            }
+        }
+    }
+
+    /// The settings a ROM is run with: these settings with the ones of the platform suggested by the
+    /// file type, of the ROM database and of Octo options applied on top, in this order.
+    pub fn for_rom(
+        &self,
+        platform: Option<Platform>,
+        info: Option<&RomInfo>,
+        options: Option<&OctoOptions>,
+    ) -> Config {
+        let mut config = self.clone();
+        if let Some(platform) = platform {
+            platform.apply(&mut config);
+        }
+        if let Some(info) = info {
+            info.apply(&mut config);
+        }
+        if let Some(options) = options {
+            options.apply(&mut config);
+        }
+        config
+    }
+
+    /// Restores the speed, colors and quirks of the user's settings which are still set to the ones
+    /// of a ROM, so that the settings of a ROM aren't saved. Settings changed since then are kept.
+    #[cfg(feature = "gui")]
+    pub fn revert_rom_settings(&mut self, rom: &Config, user: &Config) {
+        macro_rules! revert {
+            ($($field:ident).+) => {
+                if self.$($field).+ == rom.$($field).+ {
+                    self.$($field).+ = user.$($field).+;
+                }
+            };
+        }
+        // The speed is a single setting, changing its value keeps the timing mode chosen with it
+        if self.speed() == rom.speed() {
+            self.frame_timing = user.frame_timing;
+            self.cpu_speed = user.cpu_speed;
+            self.instructions_per_frame = user.instructions_per_frame;
+        }
+        revert!(colors.background);
+        revert!(colors.plane_1);
+        revert!(colors.plane_2);
+        revert!(colors.plane_both);
+        revert!(quirks.load_store);
+        revert!(quirks.shift);
+        revert!(quirks.draw);
+        revert!(quirks.jump);
+        revert!(quirks.vf_order);
+        revert!(quirks.partial_wrap_h);
+        revert!(quirks.partial_wrap_v);
+    }
+}
+
+/// Settings given on the command line, which take precedence over the config file for this session only.
+#[derive(Default)]
+pub struct Overrides {
+    pub vsync: bool,
+    pub speed: Option<Speed>,
+    pub volume: Option<f32>,
+    pub mute: bool,
+}
+
+impl Overrides {
+    pub fn apply(&self, config: &mut Config) {
+        config.vsync |= self.vsync;
+        if let Some(speed) = self.speed {
+            config.set_speed(speed);
+        }
+        if let Some(volume) = self.volume {
+            config.volume = volume;
+        }
+        config.mute |= self.mute;
+    }
+
+    /// Restores the settings of the loaded config file which are still overridden,
+    /// so that the overrides aren't saved. Settings changed since then are kept.
+    #[cfg(feature = "gui")]
+    pub fn revert(&self, config: &mut Config, loaded: &Config) {
+        if self.vsync {
+            config.vsync = loaded.vsync;
+        }
+        if self.speed == Some(config.speed()) {
+            config.set_speed(loaded.speed());
+        }
+        if self.volume == Some(config.volume) {
+            config.volume = loaded.volume;
+        }
+        if self.mute && config.mute {
+            config.mute = loaded.mute;
+        }
+    }
+}
+
+/// Parses a hex color like `#AABBCC`, the `#` being optional.
+pub fn parse_hex_color(text: &str) -> Option<[u8; 3]> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 {
+        return None;
+    }
+    let rgb = u32::from_str_radix(hex, 16).ok()?;
+    Some([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+}
+
+mod hex_color {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(color: &[u8; 3], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!(
+            "#{:02X}{:02X}{:02X}",
+            color[0], color[1], color[2]
+        ))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 3], D::Error> {
+        let text = String::deserialize(deserializer)?;
+        super::parse_hex_color(&text)
+            .ok_or_else(|| D::Error::custom(format!("invalid color: {}", text)))
+    }
+}
+
+#[cfg(test)]
+mod config_test {
+    use super::*;
+    use crate::rom_database::PlatformQuirks;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+
+        let config = Config::parse(
+            "cpu_speed = 1000\n\
+             [colors]\n\
+             plane_1 = \"#FFCC00\"\n\
+             [quirks]\n\
+             shift = false\n",
+        )
+        .unwrap();
+        assert_eq!(config.speed(), Speed::Frequency(1000));
+        assert_eq!(config.colors.plane_1, [0xFF, 0xCC, 0x00]);
+        assert_eq!(config.colors.plane_2, [0x55; 3]);
+        assert!(!config.quirks.shift);
+        assert!(config.quirks.load_store);
+
+        assert!(Config::parse("cpu_speed = \"fast\"").is_err());
+        assert!(Config::parse("[colors]\nbackground = \"#12345\"").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut config = Config::default();
+        config.set_speed(Speed::InstructionsPerFrame(30));
+        config.colors.background = [0x12, 0x34, 0x56];
+        config.layout = String::from("[Window][Debug]\nPos=10,20\n");
+        let text = toml::to_string(&config).unwrap();
+        assert!(text.contains("background = \"#123456\""));
+        assert_eq!(Config::parse(&text).unwrap(), config);
+    }
+
+    #[cfg(feature = "gui")]
+    #[test]
+    fn test_overrides() {
+        let loaded = Config::default();
+        let overrides = Overrides {
+            speed: Some(Speed::InstructionsPerFrame(100)),
+            mute: true,
+            ..Overrides::default()
+        };
+        let mut config = loaded.clone();
+        overrides.apply(&mut config);
+        assert_eq!(config.speed(), Speed::InstructionsPerFrame(100));
+        assert!(config.mute);
+
+        // Overrides aren't saved, but later changes are
+        config.volume = 0.5;
+        overrides.revert(&mut config, &loaded);
+        assert_eq!(config.speed(), loaded.speed());
+        assert!(!config.mute);
+        assert_eq!(config.volume, 0.5);
+    }
+
+    #[cfg(feature = "gui")]
+    #[test]
+    fn test_rom_settings() {
+        let user = Config::default();
+        let mut rom = user.clone();
+        rom.set_speed(Speed::InstructionsPerFrame(30));
+        rom.colors.plane_1 = [0xFF, 0xCC, 0x00];
+        rom.quirks.shift = false;
+        rom.quirks.vf_order = false;
+
+        // The settings of the ROM aren't saved, but the user's changes are
+        let mut config = rom.clone();
+        config.quirks.vf_order = true;
+        config.quirks.jump = false;
+        config.volume = 0.5;
+        config.revert_rom_settings(&rom, &user);
+        assert_eq!(config.speed(), user.speed());
+        assert_eq!(config.instructions_per_frame, user.instructions_per_frame);
+        assert_eq!(config.colors, user.colors);
+        assert!(config.quirks.shift);
+        assert!(config.quirks.vf_order);
+        assert!(!config.quirks.jump);
+        assert_eq!(config.volume, 0.5);
+
+        // Changing the instructions per frame of the ROM keeps frame timing
+        let mut config = rom.clone();
+        config.set_speed(Speed::InstructionsPerFrame(15));
+        config.revert_rom_settings(&rom, &user);
+        assert_eq!(config.speed(), Speed::InstructionsPerFrame(15));
+        assert_eq!(config.cpu_speed, user.cpu_speed);
+    }
+
+    #[test]
+    fn test_for_rom() {
+        let mut user = Config::default();
+        user.quirks.draw = false;
+        user.quirks.vf_order = false;
+        user.colors.background = [0x12, 0x34, 0x56];
+        assert_eq!(user.for_rom(None, None, None), user);
+
+        let options = OctoOptions {
+            tickrate: Some(7),
+            fill_color: Some(String::from("#FFCC00")),
+            clip_quirks: Some(true),
+            ..OctoOptions::default()
+        };
+        let config = user.for_rom(Some(Platform::XoChip), None, Some(&options));
+        assert_eq!(config.speed(), Speed::InstructionsPerFrame(7));
+        assert_eq!(config.colors.background, [0x12, 0x34, 0x56]);
+        assert_eq!(config.colors.plane_1, [0xFF, 0xCC, 0x00]);
+        assert_eq!(
+            config.quirks,
+            Quirks {
+                partial_wrap_h: false,
+                partial_wrap_v: false,
+                ..Quirks::octo()
+            }
+        );
+
+        // Quirks not set by the ROM database are the user's ones, not those of a previous ROM
+        let info = RomInfo {
+            title: String::from("Loop"),
+            authors: Vec::new(),
+            release: None,
+            description: None,
+            platform: None,
+            quirks: Some(PlatformQuirks {
+                shift: true,
+                ..PlatformQuirks::default()
+            }),
+            speed: Some(Speed::InstructionsPerFrame(30)),
+            colors: vec![[0x00, 0x00, 0x00]],
+            keys: Vec::new(),
+        };
+        let config = user.for_rom(None, Some(&info), None);
+        assert_eq!(config.speed(), Speed::InstructionsPerFrame(30));
+        assert_eq!(config.colors.background, [0x00, 0x00, 0x00]);
+        assert!(config.quirks.shift);
+        assert!(!config.quirks.load_store);
+        assert!(!config.quirks.draw);
+        assert!(!config.quirks.vf_order);
+    }
+}
@@ -0,0 +1,342 @@
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::{Component, Path};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+#[cfg(unix)]
+use std::os::unix::{fs::FileTypeExt, net::UnixListener};
+
+pub enum ControlRequest {
+    LoadRom(String),
+    LoadState(String),
+    SaveState(String),
+    SetKey(usize, bool),
+    SetPaused(bool),
+    RunFrames(u32),
+    GetRegisters,
+    ReadMemory(u16, usize),
+    GetFramebuffer,
+}
+
+/// The result of a request, errors are reported to the client with their message.
+pub type ControlResponse = Result<Value, String>;
+
+/// A request together with the channel the response is sent to.
+pub type ControlMessage = (ControlRequest, Sender<ControlResponse>);
+
+/// Serves a small JSON-RPC 2.0 API to automate the emulator, e.g. from test bots.
+/// Requests and responses are sent as one JSON object per line over a Unix socket or a localhost TCP port.
+/// A line that isn't JSON closes the connection, so requests of other protocols like HTTP aren't run.
+///
+/// Methods:
+/// - `loadRom {path}`, `loadState {path}`, `saveState {path}`, state paths are restricted to `.p8s`
+///   files in the working directory
+/// - `pressKey {key}`, `releaseKey {key}` with the key from 0 to 15
+/// - `pause {paused}`, `runFrames {frames}`
+/// - `getRegisters`, `readMemory {address, length}`, `getFramebuffer`
+pub struct ControlServer;
+
+impl ControlServer {
+    const ERROR_PARSE: i32 = -32700;
+    const ERROR_INVALID_REQUEST: i32 = -32600;
+    const ERROR_METHOD_NOT_FOUND: i32 = -32601;
+    const ERROR_INVALID_PARAMS: i32 = -32602;
+    const ERROR_EMULATOR: i32 = -32000;
+    const MAX_FRAMES: u64 = 60 * 60;
+
+    /// Starts listening on a localhost port if the address is a number, otherwise on a Unix socket with the given path.
+    pub fn start(address: &str) -> Result<Receiver<ControlMessage>, String> {
+        let (chan_tx, chan_rx) = channel();
+        if let Ok(port) = address.parse::<u16>() {
+            let listener = TcpListener::bind(("127.0.0.1", port))
+                .map_err(|e| format!("Failed to listen on port {}: {}", port, e))?;
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    if let Ok(output) = stream.try_clone() {
+                        Self::spawn_client(stream, output, chan_tx.clone());
+                    }
+                }
+            });
+        } else {
+            Self::start_unix(address, chan_tx)?;
+        }
+        Ok(chan_rx)
+    }
+
+    #[cfg(unix)]
+    fn start_unix(path: &str, chan_tx: Sender<ControlMessage>) -> Result<(), String> {
+        // Remove a stale socket of a previous run, but nothing else
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(format!("Failed to listen on socket {}: Not a socket", path));
+            }
+            std::fs::remove_file(path)
+                .map_err(|e| format!("Failed to remove socket {}: {}", path, e))?;
+        }
+        let listener = UnixListener::bind(path)
+            .map_err(|e| format!("Failed to listen on socket {}: {}", path, e))?;
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Ok(output) = stream.try_clone() {
+                    Self::spawn_client(stream, output, chan_tx.clone());
+                }
+            }
+        });
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn start_unix(_path: &str, _chan_tx: Sender<ControlMessage>) -> Result<(), String> {
+        Err(String::from(
+            "Unix sockets are not supported on this platform, please specify a port",
+        ))
+    }
+
+    fn spawn_client<R, W>(input: R, mut output: W, chan_tx: Sender<ControlMessage>)
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        thread::spawn(move || {
+            for line in BufReader::new(input).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let (response, close) = match Self::handle_line(&line, &chan_tx) {
+                    Ok(response) => (response, false),
+                    Err(response) => (Some(response), true),
+                };
+                if let Some(response) = response {
+                    if writeln!(output, "{}", response).is_err() {
+                        break;
+                    }
+                }
+                if close {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Handles a single request, returns nothing for notifications.
+    /// Lines that aren't JSON result in an error, after which the connection is closed.
+    fn handle_line(line: &str, chan_tx: &Sender<ControlMessage>) -> Result<Option<Value>, Value> {
+        let message: Value = serde_json::from_str(line)
+            .map_err(|e| Self::error(Value::Null, Self::ERROR_PARSE, &e.to_string()))?;
+        let id = message.get("id").cloned();
+        let result = Self::parse_request(&message).and_then(|request| {
+            let (response_tx, response_rx) = channel();
+            chan_tx
+                .send((request, response_tx))
+                .map_err(|_| (Self::ERROR_EMULATOR, String::from("Emulator stopped")))?;
+            response_rx
+                .recv()
+                .map_err(|_| (Self::ERROR_EMULATOR, String::from("Emulator stopped")))?
+                .map_err(|msg| (Self::ERROR_EMULATOR, msg))
+        });
+
+        Ok(id.map(|id| match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+            Err((code, msg)) => Self::error(id, code, &msg),
+        }))
+    }
+
+    fn parse_request(message: &Value) -> Result<ControlRequest, (i32, String)> {
+        let method = match (&message["jsonrpc"], &message["method"]) {
+            (Value::String(version), Value::String(method)) if version == "2.0" => method,
+            _ => return Err((Self::ERROR_INVALID_REQUEST, String::from("Invalid request"))),
+        };
+        let params = &message["params"];
+        let request = match method.as_str() {
+            "loadRom" => ControlRequest::LoadRom(Self::param_str(params, "path")?),
+            "loadState" => ControlRequest::LoadState(Self::param_state_path(params)?),
+            "saveState" => ControlRequest::SaveState(Self::param_state_path(params)?),
+            "pressKey" => ControlRequest::SetKey(Self::param_key(params)?, true),
+            "releaseKey" => ControlRequest::SetKey(Self::param_key(params)?, false),
+            "pause" => ControlRequest::SetPaused(params["paused"].as_bool().unwrap_or(true)),
+            "runFrames" => {
+                ControlRequest::RunFrames(
+                    Self::param_int(params, "frames", 1, Self::MAX_FRAMES)? as u32
+                )
+            }
+            "getRegisters" => ControlRequest::GetRegisters,
+            "readMemory" => ControlRequest::ReadMemory(
+                Self::param_int(params, "address", 0, u16::MAX as u64)? as u16,
+                Self::param_int(params, "length", 0, u16::MAX as u64 + 1)? as usize,
+            ),
+            "getFramebuffer" => ControlRequest::GetFramebuffer,
+            _ => {
+                return Err((
+                    Self::ERROR_METHOD_NOT_FOUND,
+                    format!("Unknown method: {}", method),
+                ))
+            }
+        };
+        Ok(request)
+    }
+
+    fn param_str(params: &Value, name: &str) -> Result<String, (i32, String)> {
+        params[name]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| Self::invalid_param(name))
+    }
+
+    /// A `.p8s` file in the working directory or below, so clients can't overwrite other files.
+    fn param_state_path(params: &Value) -> Result<String, (i32, String)> {
+        let path = Self::param_str(params, "path")?;
+        let relative = Path::new(&path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if !relative || Path::new(&path).extension() != Some("p8s".as_ref()) {
+            return Err((
+                Self::ERROR_INVALID_PARAMS,
+                String::from("States can only be .p8s files in the working directory"),
+            ));
+        }
+        Ok(path)
+    }
+
+    fn param_int(params: &Value, name: &str, min: u64, max: u64) -> Result<u64, (i32, String)> {
+        params[name]
+            .as_u64()
+            .filter(|value| (min..=max).contains(value))
+            .ok_or_else(|| Self::invalid_param(name))
+    }
+
+    fn param_key(params: &Value) -> Result<usize, (i32, String)> {
+        Self::param_int(params, "key", 0, 0xF).map(|key| key as usize)
+    }
+
+    fn invalid_param(name: &str) -> (i32, String) {
+        (
+            Self::ERROR_INVALID_PARAMS,
+            format!("Missing or invalid parameter: {}", name),
+        )
+    }
+
+    fn error(id: Value, code: i32, msg: &str) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "error": { "code": code, "message": msg },
+            "id": id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod control_server_test {
+    use super::*;
+
+    #[test]
+    fn test_handle_line() {
+        let (chan_tx, chan_rx) = channel::<ControlMessage>();
+        thread::spawn(move || {
+            for (request, response_tx) in chan_rx {
+                let response = match request {
+                    ControlRequest::SetKey(key, pressed) => Ok(json!([key, pressed])),
+                    ControlRequest::ReadMemory(addr, len) => Ok(json!([addr, len])),
+                    ControlRequest::LoadRom(_) => Err(String::from("Not found")),
+                    _ => Ok(Value::Null),
+                };
+                let _ = response_tx.send(response);
+            }
+        });
+
+        let call = |line: &str| ControlServer::handle_line(line, &chan_tx).unwrap();
+        assert_eq!(
+            call(r#"{"jsonrpc":"2.0","method":"pressKey","params":{"key":10},"id":1}"#),
+            Some(json!({ "jsonrpc": "2.0", "result": [10, true], "id": 1 }))
+        );
+        assert_eq!(
+            call(
+                r#"{"jsonrpc":"2.0","method":"readMemory","params":{"address":512,"length":4},"id":"a"}"#
+            ),
+            Some(json!({ "jsonrpc": "2.0", "result": [512, 4], "id": "a" }))
+        );
+        assert_eq!(
+            call(r#"{"jsonrpc":"2.0","method":"releaseKey","params":{"key":1}}"#),
+            None
+        );
+
+        let error_code = |line: &str| call(line).unwrap()["error"]["code"].as_i64().unwrap();
+        assert_eq!(
+            ControlServer::handle_line("{", &chan_tx).unwrap_err()["error"]["code"],
+            -32700
+        );
+        assert!(ControlServer::handle_line("POST / HTTP/1.1", &chan_tx).is_err());
+        assert_eq!(error_code(r#"{"method":"pause","id":1}"#), -32600);
+        assert_eq!(
+            error_code(r#"{"jsonrpc":"2.0","method":"nope","id":1}"#),
+            -32601
+        );
+        assert_eq!(
+            error_code(r#"{"jsonrpc":"2.0","method":"pressKey","params":{"key":16},"id":1}"#),
+            -32602
+        );
+        assert_eq!(
+            error_code(r#"{"jsonrpc":"2.0","method":"loadRom","params":{"path":"x"},"id":1}"#),
+            -32000
+        );
+        assert_eq!(
+            call(
+                r#"{"jsonrpc":"2.0","method":"saveState","params":{"path":"states/a.p8s"},"id":1}"#
+            ),
+            Some(json!({ "jsonrpc": "2.0", "result": null, "id": 1 }))
+        );
+        for path in ["/home/user/.bashrc", "../a.p8s", "/tmp/a.p8s", "a.txt", "a"] {
+            assert_eq!(
+                error_code(&format!(
+                    r#"{{"jsonrpc":"2.0","method":"saveState","params":{{"path":"{}"}},"id":1}}"#,
+                    path
+                )),
+                -32602
+            );
+        }
+    }
+
+    #[test]
+    fn test_connection() {
+        let (chan_tx, chan_rx) = channel::<ControlMessage>();
+        thread::spawn(move || {
+            for (_, response_tx) in chan_rx {
+                let _ = response_tx.send(Ok(Value::Null));
+            }
+        });
+
+        // The request after the first invalid line isn't run
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let stream = listener.accept().unwrap().0;
+        ControlServer::spawn_client(stream.try_clone().unwrap(), stream, chan_tx);
+        client
+            .write_all(b"POST / HTTP/1.1\n\n{\"jsonrpc\":\"2.0\",\"method\":\"pause\",\"id\":1}\n")
+            .unwrap();
+        let mut responses = String::new();
+        client.read_to_string(&mut responses).unwrap();
+        assert_eq!(responses.lines().count(), 1);
+        assert!(responses.contains("-32700"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_start_unix() {
+        let path = std::env::temp_dir().join(format!("pich8-control-{}", std::process::id()));
+        std::fs::write(&path, "keep").unwrap();
+        let address = path.to_str().unwrap();
+        assert!(ControlServer::start(address).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep");
+        std::fs::remove_file(&path).unwrap();
+
+        // A socket of a previous run is replaced
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(ControlServer::start(address).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+use crate::symbols::SymbolTable;
+use std::ops::Range;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Access {
+    Code = 0b0001,   // Executed as opcode
+    Sprite = 0b0010, // Read by DXYN
+    Data = 0b0100,   // Read by FX65, 5XY3 or F002
+    Write = 0b1000,  // Written by FX33, FX55 or 5XY2
+}
+
+/// Records how every byte of the memory was accessed since the ROM was loaded.
+#[derive(Clone)]
+pub struct Coverage {
+    flags: Box<[u8]>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self {
+            flags: vec![0; u16::MAX as usize + 1].into_boxed_slice(),
+        }
+    }
+}
+
+impl Coverage {
+    const ACCESS: [(Access, char); 4] = [
+        (Access::Code, 'C'),
+        (Access::Sprite, 'S'),
+        (Access::Data, 'D'),
+        (Access::Write, 'W'),
+    ];
+    const BYTES_PER_LINE: usize = 8;
+
+    pub fn clear(&mut self) {
+        self.flags.iter_mut().for_each(|f| *f = 0);
+    }
+
+    pub fn mark(&mut self, addr: u16, len: usize, access: Access) {
+        for offset in 0..len {
+            let idx = (addr as usize + offset) % self.flags.len();
+            self.flags[idx] |= access as u8;
+        }
+    }
+
+    pub fn get(&self, addr: u16) -> u8 {
+        self.flags[addr as usize]
+    }
+
+    pub fn has(&self, addr: u16, access: Access) -> bool {
+        self.get(addr) & access as u8 > 0
+    }
+
+    /// Formats the flags of a byte, e.g. `C---` for code or `-S-W` for a written sprite.
+    pub fn format_flags(flags: u8) -> String {
+        Self::ACCESS
+            .iter()
+            .map(|(access, c)| if flags & *access as u8 > 0 { *c } else { '-' })
+            .collect()
+    }
+
+    /// Creates a listing of the given range with one opcode per line for executed code
+    /// and up to 8 bytes per line for everything else.
+    pub fn listing(&self, mem: &[u8], range: Range<usize>, symbols: &SymbolTable) -> String {
+        let mut counts = [0; 5];
+        for addr in range.clone() {
+            match self.flags[addr] {
+                0 => counts[4] += 1,
+                flags => {
+                    for (idx, (access, _)) in Self::ACCESS.iter().enumerate() {
+                        if flags & *access as u8 > 0 {
+                            counts[idx] += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut listing = format!(
+            "; C = code, S = sprite, D = data read, W = written, ---- = never touched\n\
+             ; {:04X}-{:04X}: {} code, {} sprite, {} data, {} written, {} untouched bytes\n",
+            range.start,
+            range.end - 1,
+            counts[0],
+            counts[1],
+            counts[2],
+            counts[3],
+            counts[4]
+        );
+
+        let mut addr = range.start;
+        while addr < range.end {
+            if let Some(label) = symbols.label(addr as u16) {
+                listing.push_str(&format!("{}:\n", label));
+            }
+
+            let flags = self.flags[addr];
+            let len = if flags & Access::Code as u8 > 0 {
+                let opcode = (mem[addr] as u16) << 8 | mem[(addr + 1) % mem.len()] as u16;
+                if opcode == 0xF000 {
+                    4
+                } else {
+                    2
+                }
+            } else {
+                // Group bytes with the same flags, labels start a new line
+                let mut len = 1;
+                while len < Self::BYTES_PER_LINE
+                    && addr + len < range.end
+                    && self.flags[addr + len] == flags
+                    && symbols.label((addr + len) as u16).is_none()
+                {
+                    len += 1;
+                }
+                len
+            };
+            let len = len.min(range.end - addr);
+
+            let bytes: Vec<String> = (addr..addr + len)
+                .map(|a| format!("{:02X}", mem[a]))
+                .collect();
+            let bytes = if flags & Access::Code as u8 > 0 {
+                bytes.concat()
+            } else {
+                bytes.join(" ")
+            };
+            listing.push_str(&format!(
+                "{:04X}  {}  {}\n",
+                addr,
+                Self::format_flags(flags),
+                bytes
+            ));
+            addr += len;
+        }
+
+        listing
+    }
+
+    /// Creates a LCOV tracefile using the addresses of the given range as line numbers.
+    /// A byte counts as hit if it was accessed in any way, labels are reported as functions.
+    pub fn lcov(&self, name: &str, range: Range<usize>, symbols: &SymbolTable) -> String {
+        let mut lcov = format!("TN:\nSF:{}\n", name);
+
+        let labels: Vec<(u16, &str)> = range
+            .clone()
+            .filter_map(|addr| symbols.label(addr as u16).map(|label| (addr as u16, label)))
+            .collect();
+        for (addr, label) in &labels {
+            lcov.push_str(&format!("FN:{},{}\n", addr, label));
+        }
+        for (addr, label) in &labels {
+            lcov.push_str(&format!(
+                "FNDA:{},{}\n",
+                self.has(*addr, Access::Code) as u8,
+                label
+            ));
+        }
+        lcov.push_str(&format!(
+            "FNF:{}\nFNH:{}\n",
+            labels.len(),
+            labels
+                .iter()
+                .filter(|(addr, _)| self.has(*addr, Access::Code))
+                .count()
+        ));
+
+        let mut hit = 0;
+        for addr in range.clone() {
+            let touched = self.flags[addr] > 0;
+            hit += touched as usize;
+            lcov.push_str(&format!("DA:{},{}\n", addr, touched as u8));
+        }
+        lcov.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", range.len(), hit));
+
+        lcov
+    }
+}
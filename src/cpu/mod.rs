@@ -2,15 +2,20 @@ use crate::video_memory::{Plane, VideoMemory, VideoMode};
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::ops::Range;
 
+mod coverage;
 mod opcodes;
 
+pub use coverage::{Access, Coverage};
+
 #[derive(Debug)]
 pub enum Error {
     SaveStateFailed(rmp_serde::encode::Error),
     LoadStateFailed(rmp_serde::decode::Error),
     ProgramCounterOverflow,
     StackOverflow,
+    InvalidRegister(Register),
 }
 
 impl fmt::Display for Error {
@@ -20,6 +25,7 @@ impl fmt::Display for Error {
             Error::LoadStateFailed(e) => write!(f, "Load state error: {}", e),
             Error::ProgramCounterOverflow => write!(f, "Program counter overflow!"),
             Error::StackOverflow => write!(f, "Stack overflow occurred! The ROM might be invalid or different quirk settings required."),
+            Error::InvalidRegister(register) => write!(f, "Invalid register: {}", register),
         }
     }
 }
@@ -35,14 +41,42 @@ impl From<rmp_serde::decode::Error> for Error {
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum Breakpoint {
     PC(u16),
     I(u16),
     Opcode(String),
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Register {
+    V(usize),
+    I,
+    PC,
+    DT,
+    ST,
+    SP,
+    RPL(usize),
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Register::V(idx) => write!(f, "V{:X}", idx),
+            Register::I => write!(f, "I"),
+            Register::PC => write!(f, "PC"),
+            Register::DT => write!(f, "DT"),
+            Register::ST => write!(f, "ST"),
+            Register::SP => write!(f, "SP"),
+            Register::RPL(idx) => write!(f, "RPL{:X}", idx),
+        }
+    }
+}
+
+// Fields missing in states of older versions keep the values of a new CPU
 #[allow(non_snake_case)]
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CPU {
     mem: Box<[u8]>,                 // Main memory
     vmem: VideoMemory,              // Graphics memory
@@ -77,6 +111,11 @@ pub struct CPU {
     pub quirk_draw: bool,          // Flag for draw quirk
     pub quirk_partialwrap_h: bool, // Flag for partial horizontal wrapping quirk
     pub quirk_partialwrap_v: bool, // Flag for partial vertical wrapping quirk
+
+    #[serde(skip)]
+    coverage: Coverage, // Memory access flags
+    #[serde(skip)]
+    rom_len: usize, // Size of the loaded ROM, unknown for loaded states
 }
 
 #[allow(non_snake_case)]
@@ -146,6 +185,9 @@ impl CPU {
             quirk_vf_order: true,
             quirk_partialwrap_h: false,
             quirk_partialwrap_v: false,
+
+            coverage: Coverage::default(),
+            rom_len: 0,
         };
 
         // Load fontsets
@@ -155,12 +197,24 @@ impl CPU {
         cpu
     }
 
+    /// Restores a state, either serialized with or without field names.
+    /// Restores a serialized state, rejecting corrupted ones which would index out of bounds.
     pub fn from_state(state: &[u8]) -> Result<Self, String> {
-        Ok(rmp_serde::decode::from_slice(state).map_err(|_| "Failed to deserialize state!")?)
+        let cpu: Self =
+            rmp_serde::decode::from_slice(state).map_err(|_| "Failed to deserialize state!")?;
+        if cpu.mem.len() != u16::MAX as usize + 1
+            || cpu.sp > cpu.stack.len()
+            || cpu.key_reg >= cpu.V.len()
+            || !cpu.vmem.is_valid()
+        {
+            return Err(String::from("Invalid state!"));
+        }
+        Ok(cpu)
     }
 
+    /// Serializes the state with field names, so it can still be restored after fields are added.
     pub fn save_state(&self) -> Result<Vec<u8>, String> {
-        Ok(rmp_serde::encode::to_vec(self).map_err(|_| "Failed to serialize state!")?)
+        Ok(rmp_serde::encode::to_vec_named(self).map_err(|_| "Failed to serialize state!")?)
     }
 
     pub fn load_bootrom(&mut self) {
@@ -171,6 +225,8 @@ impl CPU {
         if prog.len() <= self.mem.len() - 0x200 {
             self.vmem.video_mode = VideoMode::Default;
             self.mem[0x200..0x200 + prog.len()].copy_from_slice(prog);
+            self.coverage.clear();
+            self.rom_len = prog.len();
             self.PC = CPU::PC_INITIAL;
             self.sp = 0;
             self.prefetch_next_opcode().map_err(|e| format!("{}", e))
@@ -183,6 +239,12 @@ impl CPU {
     pub fn vmem(&self) -> &VideoMemory {
         &self.vmem
     }
+    pub fn mem(&self) -> &[u8] {
+        &self.mem
+    }
+    pub fn coverage(&self) -> &Coverage {
+        &self.coverage
+    }
     pub fn stack(&self) -> [u16; 16] {
         self.stack
     }
@@ -219,6 +281,114 @@ impl CPU {
     pub fn sp(&self) -> usize {
         self.sp
     }
+    pub fn RPL(&self) -> [u8; 8] {
+        self.RPL
+    }
+    pub fn key_wait(&self) -> bool {
+        self.key_wait
+    }
+    pub fn register(&self, register: Register) -> u16 {
+        match register {
+            Register::V(idx) => self.V[idx] as u16,
+            Register::I => self.I,
+            Register::PC => self.PC,
+            Register::DT => self.DT as u16,
+            Register::ST => self.ST as u16,
+            Register::SP => self.sp as u16,
+            Register::RPL(idx) => self.RPL[idx] as u16,
+        }
+    }
+
+    pub fn set_V(&mut self, idx: usize, value: u8) {
+        self.V[idx] = value;
+        self.refresh_next_opcode_description();
+    }
+    pub fn set_I(&mut self, value: u16) {
+        self.I = value;
+        self.refresh_next_opcode_description();
+    }
+    pub fn set_PC(&mut self, value: u16) -> Result<(), Error> {
+        if value as usize >= self.mem.len() - 2 {
+            return Err(Error::ProgramCounterOverflow);
+        }
+        self.PC = value;
+        self.prefetch_next_opcode()
+    }
+    pub fn set_DT(&mut self, value: u8) {
+        self.DT = value;
+        self.refresh_next_opcode_description();
+    }
+    pub fn set_ST(&mut self, value: u8) {
+        self.ST = value;
+        self.refresh_next_opcode_description();
+    }
+    pub fn set_sp(&mut self, value: usize) -> Result<(), Error> {
+        if value > self.stack.len() {
+            return Err(Error::StackOverflow);
+        }
+        self.sp = value;
+        Ok(())
+    }
+    pub fn set_RPL(&mut self, idx: usize, value: u8) {
+        self.RPL[idx] = value;
+    }
+
+    /// Sets any register, values are truncated to the register size.
+    pub fn set_register(&mut self, register: Register, value: u16) -> Result<(), Error> {
+        if !self.is_valid_register(register) {
+            return Err(Error::InvalidRegister(register));
+        }
+        match register {
+            Register::V(idx) => self.set_V(idx, value as u8),
+            Register::I => self.set_I(value),
+            Register::PC => self.set_PC(value)?,
+            Register::DT => self.set_DT(value as u8),
+            Register::ST => self.set_ST(value as u8),
+            Register::SP => self.set_sp(value as usize)?,
+            Register::RPL(idx) => self.set_RPL(idx, value as u8),
+        }
+        Ok(())
+    }
+
+    fn is_valid_register(&self, register: Register) -> bool {
+        match register {
+            Register::V(idx) => idx < self.V.len(),
+            Register::RPL(idx) => idx < self.RPL.len(),
+            _ => true,
+        }
+    }
+
+    /// Sets several registers at once, none of them is changed if any value is invalid.
+    pub fn set_registers(&mut self, values: &[(Register, u16)]) -> Result<(), Error> {
+        for (register, value) in values {
+            match register {
+                register if !self.is_valid_register(*register) => {
+                    return Err(Error::InvalidRegister(*register))
+                }
+                Register::PC if *value as usize >= self.mem.len() - 2 => {
+                    return Err(Error::ProgramCounterOverflow)
+                }
+                Register::SP if *value as usize > self.stack.len() => {
+                    return Err(Error::StackOverflow)
+                }
+                _ => (),
+            }
+        }
+        for (register, value) in values {
+            self.set_register(*register, *value)?;
+        }
+        Ok(())
+    }
+
+    /// Writes to memory, addresses wrap around at the end of the memory.
+    pub fn write_mem(&mut self, addr: u16, data: &[u8]) -> Result<(), Error> {
+        for (offset, value) in data.iter().enumerate() {
+            let idx = (addr as usize + offset) % self.mem.len();
+            self.mem[idx] = *value;
+        }
+        // The next opcode might have changed
+        self.prefetch_next_opcode()
+    }
 
     pub fn update_timers(&mut self) {
         if self.DT > 0 {
@@ -247,10 +417,10 @@ impl CPU {
         }
     }
 
-    pub fn check_breakpoint(&self, breakpoint: Breakpoint) -> bool {
+    pub fn check_breakpoint(&self, breakpoint: &Breakpoint) -> bool {
         match breakpoint {
-            Breakpoint::PC(val) => self.PC == val,
-            Breakpoint::I(val) => self.I == val,
+            Breakpoint::PC(val) => self.PC == *val,
+            Breakpoint::I(val) => self.I == *val,
             Breakpoint::Opcode(pattern) => {
                 if pattern.len() == 4 {
                     let val = format!("{:04X}", self.next_opcode);
@@ -267,6 +437,21 @@ impl CPU {
         }
     }
 
+    /// Returns the memory range occupied by the ROM.
+    /// States don't contain the ROM size, so everything up to the last non-zero byte is assumed.
+    pub fn rom_range(&self) -> Range<usize> {
+        let start = Self::PC_INITIAL as usize;
+        let end = if self.rom_len > 0 {
+            start + self.rom_len
+        } else {
+            self.mem
+                .iter()
+                .rposition(|b| *b != 0)
+                .map_or(start, |idx| (idx + 1).max(start))
+        };
+        start..end
+    }
+
     fn prefetch_next_opcode(&mut self) -> Result<(), Error> {
         if self.PC as usize >= self.mem.len() - 2 {
             self.load_bootrom();
@@ -282,10 +467,17 @@ impl CPU {
         Ok(())
     }
 
+    fn refresh_next_opcode_description(&mut self) {
+        // The description contains register values
+        self.next_opcode_description = self.get_next_opcode_description();
+    }
+
     fn emulate_cycle(&mut self) -> Result<(), Error> {
         // Fetch opcode
         self.opcode = self.next_opcode;
         self.opcode_description = self.next_opcode_description.clone();
+        let len = if self.opcode == 0xF000 { 4 } else { 2 };
+        self.coverage.mark(self.PC, len, Access::Code);
 
         // Decode opcode
         let h = (self.opcode & 0xF000) as usize >> 12;
@@ -376,11 +568,8 @@ impl CPU {
         let x = x % self.vmem.width();
         let y = y % self.vmem.height();
 
-        let big_sprite =
-            (self.vmem.video_mode == VideoMode::Extended || self.quirk_draw) && height == 0;
-        let step = if big_sprite { 2 } else { 1 };
-        let width = if big_sprite { 16 } else { 8 };
-        let height = if height == 0 { 16 } else { height };
+        let (width, height) = self.sprite_size(height);
+        let step = width / 8;
 
         let mut collision = false;
         let mut i = self.I as usize;
@@ -388,6 +577,7 @@ impl CPU {
 
         for plane in &[Plane::First, Plane::Second] {
             if self.vmem.current_plane() == *plane || self.vmem.current_plane() == Plane::Both {
+                self.coverage.mark(i as u16, len, Access::Sprite);
                 let sprite = &self.mem[i..i + len];
                 i += len;
 
@@ -412,11 +602,7 @@ impl CPU {
                         }
 
                         // Get bit
-                        let bit = if width == 16 {
-                            ((sprite[k] as u16) << 8 | sprite[k + 1] as u16) >> i & 0b1 > 0
-                        } else {
-                            sprite[k] >> i & 0b1 > 0
-                        };
+                        let bit = Self::sprite_bit(sprite, width, k, i);
 
                         // Detect collision and draw pixel
                         if bit && self.vmem.get_plane(*plane, x, y) {
@@ -432,6 +618,43 @@ impl CPU {
         self.V[0xF] = collision as u8;
     }
 
+    /// Returns width and height of a sprite drawn by DXYN with the given n.
+    pub fn sprite_size(&self, n: usize) -> (usize, usize) {
+        let big_sprite = (self.vmem.video_mode == VideoMode::Extended || self.quirk_draw) && n == 0;
+        let width = if big_sprite { 16 } else { 8 };
+        let height = if n == 0 { 16 } else { n };
+        (width, height)
+    }
+
+    /// Returns the pixels of the sprite at the given address row by row.
+    /// Each pixel holds one bit per plane, the data of the second plane directly follows the first one (XO-CHIP).
+    pub fn sprite_pixels(&self, addr: u16, width: usize, height: usize, planes: usize) -> Vec<u8> {
+        let len = width / 8 * height;
+        let mut pixels = vec![0; width * height];
+        for plane in 0..planes {
+            let sprite: Vec<u8> = (0..len)
+                .map(|j| self.mem[(addr as usize + plane * len + j) % self.mem.len()])
+                .collect();
+            for (row, k) in (0..height).zip((0..len).step_by(width / 8)) {
+                for (col, i) in (0..width).zip((0..width).rev()) {
+                    if Self::sprite_bit(&sprite, width, k, i) {
+                        pixels[row * width + col] |= 1 << plane;
+                    }
+                }
+            }
+        }
+        pixels
+    }
+
+    #[inline]
+    fn sprite_bit(sprite: &[u8], width: usize, k: usize, i: usize) -> bool {
+        if width == 16 {
+            ((sprite[k] as u16) << 8 | sprite[k + 1] as u16) >> i & 0b1 > 0
+        } else {
+            sprite[k] >> i & 0b1 > 0
+        }
+    }
+
     fn get_next_opcode_description(&self) -> String {
         let h = (self.next_opcode & 0xF000) as usize >> 12;
         let x = (self.next_opcode & 0x0F00) as usize >> 8;
@@ -570,6 +793,12 @@ impl CPU {
     }
 }
 
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 #[path = "test.rs"]
 mod cpu_test;
@@ -158,6 +158,7 @@ impl CPU {
     pub(super) fn opcode_xochip_0x5XY2(&mut self, x: usize, y: usize) {
         let first = std::cmp::min(x, y);
         let last = std::cmp::max(x, y);
+        self.coverage.mark(self.I, last - first + 1, Access::Write);
         self.mem[self.I as usize..self.I as usize + last - first + 1]
             .copy_from_slice(&self.V[first..=last]);
         self.PC += 2;
@@ -168,6 +169,7 @@ impl CPU {
     pub(super) fn opcode_xochip_0x5XY3(&mut self, x: usize, y: usize) {
         let first = std::cmp::min(x, y);
         let last = std::cmp::max(x, y);
+        self.coverage.mark(self.I, last - first + 1, Access::Data);
         self.V[first..=last]
             .copy_from_slice(&self.mem[self.I as usize..self.I as usize + last - first + 1]);
         self.PC += 2;
@@ -355,6 +357,7 @@ impl CPU {
     #[inline]
     pub(super) fn opcode_xochip_0xF002(&mut self) {
         use std::convert::TryInto;
+        self.coverage.mark(self.I, 16, Access::Data);
         if let Ok(buf) = self.mem[self.I as usize..self.I as usize + 16].try_into() {
             self.audio_buffer = Some(buf);
         }
@@ -417,6 +420,7 @@ impl CPU {
         let hundreds = self.V[x] / 100;
         let tens = (self.V[x] % 100) / 10;
         let ones = self.V[x] % 10;
+        self.coverage.mark(self.I, 3, Access::Write);
         self.mem[self.I as usize] = hundreds;
         self.mem[self.I as usize + 1] = tens;
         self.mem[self.I as usize + 2] = ones;
@@ -430,6 +434,7 @@ impl CPU {
     pub(super) fn opcode_0xFX55(&mut self, x: usize) {
         let start = self.I as usize;
         let end = self.I as usize + x;
+        self.coverage.mark(self.I, x + 1, Access::Write);
         self.mem[start..=end].copy_from_slice(&self.V[..=x]);
         if !self.quirk_load_store {
             self.I += x as u16 + 1;
@@ -444,6 +449,7 @@ impl CPU {
     pub(super) fn opcode_0xFX65(&mut self, x: usize) {
         let start = self.I as usize;
         let end = self.I as usize + x;
+        self.coverage.mark(self.I, x + 1, Access::Data);
         self.V[..=x].copy_from_slice(&self.mem[start..=end]);
         if !self.quirk_load_store {
             self.I += x as u16 + 1;
@@ -1,4 +1,5 @@
 use super::*;
+use crate::symbols::SymbolTable;
 
 #[test]
 fn test_initial_state() {
@@ -38,6 +39,111 @@ fn test_load_rom() {
     assert_eq!(cpu.PC, 0x200);
 }
 
+#[test]
+fn test_set_registers() {
+    let mut cpu = CPU::new();
+    let _ = cpu.load_rom(&[0x70, 0x01, 0x12, 0x00, 0xA3, 0x00]);
+    assert_eq!(cpu.next_opcode_description, "ADD V0 (00), 01");
+
+    cpu.set_V(0, 0x42);
+    assert_eq!(cpu.V[0], 0x42);
+    assert_eq!(cpu.next_opcode_description, "ADD V0 (42), 01");
+
+    cpu.set_register(Register::PC, 0x204).unwrap();
+    assert_eq!(cpu.PC, 0x204);
+    assert_eq!(cpu.next_opcode, 0xA300);
+    assert_eq!(cpu.next_opcode_description, "LD I, 300");
+    assert!(cpu.set_PC(0xFFFF).is_err());
+    assert_eq!(cpu.PC, 0x204);
+
+    cpu.set_register(Register::I, 0x123).unwrap();
+    cpu.set_register(Register::DT, 0x1FF).unwrap();
+    cpu.set_register(Register::ST, 0x10).unwrap();
+    cpu.set_register(Register::RPL(7), 0x99).unwrap();
+    assert_eq!(cpu.I, 0x123);
+    assert_eq!(cpu.DT, 0xFF);
+    assert_eq!(cpu.ST, 0x10);
+    assert_eq!(cpu.RPL[7], 0x99);
+
+    cpu.set_register(Register::SP, 16).unwrap();
+    assert_eq!(cpu.sp, 16);
+    assert!(cpu.set_register(Register::SP, 17).is_err());
+    assert_eq!(cpu.sp, 16);
+    assert!(matches!(
+        cpu.set_register(Register::V(16), 0x11),
+        Err(Error::InvalidRegister(Register::V(16)))
+    ));
+    assert!(cpu.set_register(Register::RPL(8), 0x11).is_err());
+
+    // Nothing is written if any value is invalid
+    assert!(cpu
+        .set_registers(&[(Register::V(1), 0x11), (Register::PC, 0xFFFF)])
+        .is_err());
+    assert_eq!(cpu.V[1], 0);
+    assert!(cpu
+        .set_registers(&[(Register::V(1), 0x11), (Register::RPL(8), 0x11)])
+        .is_err());
+    assert_eq!(cpu.V[1], 0);
+    cpu.set_registers(&[(Register::V(1), 0x11), (Register::PC, 0x200)])
+        .unwrap();
+    assert_eq!((cpu.V[1], cpu.PC), (0x11, 0x200));
+}
+
+#[test]
+fn test_read_register_and_write_mem() {
+    let mut cpu = CPU::new();
+    let _ = cpu.load_rom(&[0x70, 0x01]);
+    cpu.set_V(3, 0x42);
+    cpu.set_I(0x345);
+    assert_eq!(cpu.register(Register::V(3)), 0x42);
+    assert_eq!(cpu.register(Register::I), 0x345);
+    assert_eq!(cpu.register(Register::PC), 0x200);
+
+    cpu.write_mem(0x200, &[0xA1, 0x23]).unwrap();
+    assert_eq!(&cpu.mem[0x200..0x202], &[0xA1, 0x23]);
+    assert_eq!(cpu.next_opcode, 0xA123);
+
+    cpu.write_mem(0xFFFF, &[0x11, 0x22]).unwrap();
+    assert_eq!(cpu.mem[0xFFFF], 0x11);
+    assert_eq!(cpu.mem[0], 0x22);
+}
+
+#[test]
+fn test_sprite_pixels() {
+    let mut cpu = CPU::new();
+    cpu.mem[0x300..0x304].copy_from_slice(&[0b1000_0001, 0b0100_0010, 0b1100_0000, 0b0000_0011]);
+
+    // 8x2, one plane
+    let pixels = cpu.sprite_pixels(0x300, 8, 2, 1);
+    assert_eq!(pixels, vec![1, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0, 0, 1, 0]);
+
+    // 8x2, two planes
+    let pixels = cpu.sprite_pixels(0x300, 8, 2, 2);
+    assert_eq!(pixels, vec![3, 2, 0, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0, 0, 3, 2]);
+
+    // 16x2
+    let pixels = cpu.sprite_pixels(0x300, 16, 2, 1);
+    assert_eq!(
+        pixels[..16],
+        [1, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0, 0, 1, 0]
+    );
+    assert_eq!(
+        pixels[16..],
+        [1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1]
+    );
+}
+
+#[test]
+fn test_sprite_size() {
+    let mut cpu = CPU::new();
+    assert_eq!(cpu.sprite_size(5), (8, 5));
+    assert_eq!(cpu.sprite_size(0), (16, 16));
+    cpu.quirk_draw = false;
+    assert_eq!(cpu.sprite_size(0), (8, 16));
+    cpu.vmem.video_mode = VideoMode::Extended;
+    assert_eq!(cpu.sprite_size(0), (16, 16));
+}
+
 #[test]
 fn test_opcodes() {
     // 0x00E0
@@ -802,3 +908,59 @@ fn test_skipped_opcode_0x0NNN() {
     let _ = cpu.emulate_cycle();
     assert_eq!(cpu.PC, 0x202);
 }
+
+#[test]
+fn test_coverage() {
+    let mut cpu = CPU::new();
+    let _ = cpu.load_rom(&[
+        0xA2, 0x0A, 0xD0, 0x01, 0xF0, 0x65, 0x12, 0x06, 0x00, 0x00, 0xFF, 0x00,
+    ]);
+    for _ in 0..4 {
+        let _ = cpu.emulate_cycle();
+    }
+
+    let coverage = cpu.coverage();
+    assert!((0x200..0x208).all(|addr| coverage.get(addr) == Access::Code as u8));
+    assert_eq!(coverage.get(0x208), 0);
+    assert!(coverage.has(0x20A, Access::Sprite));
+    assert!(coverage.has(0x20A, Access::Data));
+    assert!(!coverage.has(0x20A, Access::Code));
+    assert_eq!(cpu.rom_range(), 0x200..0x20C);
+
+    let symbols = SymbolTable::parse("loop 0x206").unwrap();
+    assert_eq!(
+        coverage.listing(cpu.mem(), cpu.rom_range(), &symbols),
+        "; C = code, S = sprite, D = data read, W = written, ---- = never touched\n\
+         ; 0200-020B: 8 code, 1 sprite, 1 data, 0 written, 3 untouched bytes\n\
+         0200  C---  A20A\n\
+         0202  C---  D001\n\
+         0204  C---  F065\n\
+         loop:\n\
+         0206  C---  1206\n\
+         0208  ----  00 00\n\
+         020A  -SD-  FF\n\
+         020B  ----  00\n"
+    );
+    let lcov = coverage.lcov("test.ch8", cpu.rom_range(), &symbols);
+    assert!(lcov.starts_with("TN:\nSF:test.ch8\nFN:518,loop\nFNDA:1,loop\n"));
+    assert!(lcov.contains("DA:518,1\n"));
+    assert!(lcov.contains("DA:520,0\n"));
+    assert!(lcov.ends_with("LF:12\nLH:9\nend_of_record\n"));
+}
+
+#[test]
+fn test_from_state() {
+    let cpu = CPU::new();
+    assert!(CPU::from_state(&cpu.save_state().unwrap()).is_ok());
+
+    // States which would index out of bounds are rejected
+    let mut cpu = CPU::new();
+    cpu.sp = 17;
+    assert!(CPU::from_state(&cpu.save_state().unwrap()).is_err());
+    let mut cpu = CPU::new();
+    cpu.mem = vec![0; 0x200].into_boxed_slice();
+    assert!(CPU::from_state(&cpu.save_state().unwrap()).is_err());
+    let mut cpu = CPU::new();
+    cpu.key_reg = 16;
+    assert!(CPU::from_state(&cpu.save_state().unwrap()).is_err());
+}
@@ -0,0 +1,745 @@
+use crate::cpu::{Breakpoint, Register};
+use crate::octo_assembler::{self, Program};
+use crate::remote_debug::{DebugRequest, DebugResponse, RemoteRequest, StopReason};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+/// Implements the Debug Adapter Protocol over stdio or a localhost TCP port.
+/// Every request is forwarded to the emulator, there is a single thread representing the CPU.
+///
+/// Source breakpoints and stack frame lines are available for Octo programs (`.8o`), which are
+/// assembled to map lines to addresses. Otherwise breakpoints are set as function breakpoints
+/// (a label or expression for PC, `I=<expression>` or `opcode=<pattern>` like `D***`) or instruction breakpoints.
+pub struct DapServer<W: Write> {
+    output: W,
+    requests: Sender<RemoteRequest>,
+    seq: u64,
+    events: Vec<(&'static str, Value)>,
+    running: Option<Receiver<DebugResponse>>,
+    stop_on_entry: bool,
+    function_breakpoints: Vec<Breakpoint>,
+    instruction_breakpoints: Vec<Breakpoint>,
+    source_breakpoints: Vec<Breakpoint>,
+    step_out: Option<Breakpoint>,
+    /// Path and assembled program of the Octo source being debugged
+    source: Option<(String, Program)>,
+}
+
+#[derive(Clone, Copy)]
+enum BreakpointKind {
+    Function,
+    Instruction,
+    Source,
+}
+
+impl DapServer<io::Stdout> {
+    pub fn start_stdio(requests: Sender<RemoteRequest>) {
+        thread::spawn(move || {
+            let messages = Self::spawn_reader(io::stdin());
+            let _ = DapServer::new(io::stdout(), requests).run(messages);
+        });
+    }
+}
+
+impl DapServer<TcpStream> {
+    /// Starts listening on the given port, connections are handled one after another.
+    pub fn start_tcp(port: u16, requests: Sender<RemoteRequest>) -> Result<(), String> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .map_err(|e| format!("Failed to listen on port {}: {}", port, e))?;
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Ok(input) = stream.try_clone() {
+                    let messages = Self::spawn_reader(input);
+                    let _ = DapServer::new(stream, requests.clone()).run(messages);
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+impl<W: Write> DapServer<W> {
+    const THREAD_ID: u64 = 1;
+    const REGISTERS_REFERENCE: u64 = 1;
+    const REGISTERS: [Register; 21] = [
+        Register::V(0x0),
+        Register::V(0x1),
+        Register::V(0x2),
+        Register::V(0x3),
+        Register::V(0x4),
+        Register::V(0x5),
+        Register::V(0x6),
+        Register::V(0x7),
+        Register::V(0x8),
+        Register::V(0x9),
+        Register::V(0xA),
+        Register::V(0xB),
+        Register::V(0xC),
+        Register::V(0xD),
+        Register::V(0xE),
+        Register::V(0xF),
+        Register::I,
+        Register::PC,
+        Register::SP,
+        Register::DT,
+        Register::ST,
+    ];
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    fn new(output: W, requests: Sender<RemoteRequest>) -> Self {
+        Self {
+            output,
+            requests,
+            seq: 0,
+            events: Vec::new(),
+            running: None,
+            stop_on_entry: false,
+            function_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+            source_breakpoints: Vec::new(),
+            step_out: None,
+            source: None,
+        }
+    }
+
+    fn spawn_reader<R: Read + Send + 'static>(input: R) -> Receiver<Value> {
+        let (message_tx, message_rx) = channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(input);
+            while let Ok(Some(message)) = read_message(&mut reader) {
+                if message_tx.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        message_rx
+    }
+
+    fn run(&mut self, messages: Receiver<Value>) -> io::Result<()> {
+        loop {
+            match messages.recv_timeout(Self::POLL_INTERVAL) {
+                Ok(message) => {
+                    if message["type"] == "request" && !self.handle_request(&message)? {
+                        return Ok(());
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => {
+                    let _ = self.request(DebugRequest::Detach);
+                    return Ok(());
+                }
+            }
+            self.check_stopped()?;
+        }
+    }
+
+    /// Handles a request, returns false if the session ended.
+    fn handle_request(&mut self, request: &Value) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or("");
+        let args = &request["arguments"];
+        let result = match command {
+            "initialize" => {
+                self.events.push(("initialized", Value::Null));
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsFunctionBreakpoints": true,
+                    "supportsInstructionBreakpoints": true,
+                    "supportsSetVariable": true,
+                    "supportsEvaluateForHovers": true,
+                    "supportsTerminateRequest": true,
+                }))
+            }
+            "launch" => self.launch(args),
+            "attach" | "configurationDone" => self.configuration_done(command),
+            "setBreakpoints" => self.set_source_breakpoints(args),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "threads" => Ok(json!({ "threads": [{ "id": Self::THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({
+                "scopes": [{
+                    "name": "Registers",
+                    "variablesReference": Self::REGISTERS_REFERENCE,
+                    "expensive": false,
+                }]
+            })),
+            "variables" => self.variables(args),
+            "setVariable" => self.set_variable(args),
+            "evaluate" => self.evaluate(args),
+            "continue" => self.resume(),
+            "next" | "stepIn" => self.step(),
+            "stepOut" => self.step_out(),
+            "pause" => self.pause(),
+            "disconnect" | "terminate" => {
+                let _ = self.request(DebugRequest::Detach);
+                self.respond(request, Ok(Value::Null))?;
+                if command == "terminate" {
+                    self.event("terminated", Value::Null)?;
+                }
+                return Ok(command == "terminate");
+            }
+            _ => Err(format!("Unsupported request: {}", command)),
+        };
+
+        self.respond(request, result)?;
+        for (event, body) in std::mem::take(&mut self.events) {
+            self.event(event, body)?;
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"]
+            .as_str()
+            .ok_or_else(|| String::from("No program given"))?;
+        Self::expect_ok(self.request(DebugRequest::LoadRom(program.to_string()))?)?;
+        if program.to_lowercase().ends_with(".8o") {
+            self.load_source(program)?;
+        }
+        // Wait with the execution until all breakpoints are set
+        Self::expect_ok(self.request(DebugRequest::Halt)?)?;
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(Value::Null)
+    }
+
+    fn configuration_done(&mut self, command: &str) -> Result<Value, String> {
+        if command == "configurationDone" {
+            if self.stop_on_entry {
+                self.stopped("entry");
+            } else {
+                self.resume()?;
+            }
+        }
+        Ok(Value::Null)
+    }
+
+    /// Assembles an Octo source to map its lines to addresses.
+    fn load_source(&mut self, path: &str) -> Result<&Program, String> {
+        let reload = match &self.source {
+            Some((source_path, _)) => source_path != path,
+            None => true,
+        };
+        if reload {
+            let source =
+                fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            let program = octo_assembler::assemble(&source)?;
+            self.source = Some((path.to_string(), program));
+        }
+        Ok(&self.source.as_ref().unwrap().1)
+    }
+
+    fn set_source_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"].as_str().unwrap_or("");
+        let requested: Vec<usize> = args["breakpoints"]
+            .as_array()
+            .map(|bps| {
+                bps.iter()
+                    .map(|bp| bp["line"].as_u64().unwrap_or(0) as usize)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let lines: Vec<Result<(usize, u16), String>> = match self.load_source(path) {
+            Ok(program) => requested
+                .iter()
+                .map(|line| {
+                    program
+                        .address_of_line(*line)
+                        .ok_or_else(|| String::from("No code at or after this line"))
+                })
+                .collect(),
+            Err(msg) => requested
+                .iter()
+                .map(|_| {
+                    Err(format!(
+                        "Source breakpoints need an Octo program (.8o), please use function \
+                         or instruction breakpoints instead ({})",
+                        msg
+                    ))
+                })
+                .collect(),
+        };
+
+        let breakpoints = lines
+            .iter()
+            .map(|line| line.clone().map(|(_, addr)| Breakpoint::PC(addr)))
+            .collect();
+        let mut response = self.replace_breakpoints(breakpoints, BreakpointKind::Source)?;
+        for (result, line) in response["breakpoints"]
+            .as_array_mut()
+            .into_iter()
+            .flatten()
+            .zip(&lines)
+        {
+            if let Ok((line, _)) = line {
+                result["line"] = json!(line);
+            }
+        }
+        Ok(response)
+    }
+
+    fn set_function_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let names: Vec<String> = args["breakpoints"]
+            .as_array()
+            .map(|bps| {
+                bps.iter()
+                    .map(|bp| bp["name"].as_str().unwrap_or("").to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let breakpoints: Vec<Result<Breakpoint, String>> = names
+            .iter()
+            .map(|name| self.parse_breakpoint(name))
+            .collect();
+        self.replace_breakpoints(breakpoints, BreakpointKind::Function)
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let breakpoints: Vec<Result<Breakpoint, String>> = args["breakpoints"]
+            .as_array()
+            .map(|bps| {
+                bps.iter()
+                    .map(|bp| {
+                        let reference = bp["instructionReference"].as_str().unwrap_or("");
+                        let offset = bp["offset"].as_i64().unwrap_or(0);
+                        parse_address(reference)
+                            .map(|addr| Breakpoint::PC((addr as i64 + offset) as u16))
+                            .ok_or_else(|| format!("Invalid instruction reference: {}", reference))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.replace_breakpoints(breakpoints, BreakpointKind::Instruction)
+    }
+
+    fn breakpoints_mut(&mut self, kind: BreakpointKind) -> &mut Vec<Breakpoint> {
+        match kind {
+            BreakpointKind::Function => &mut self.function_breakpoints,
+            BreakpointKind::Instruction => &mut self.instruction_breakpoints,
+            BreakpointKind::Source => &mut self.source_breakpoints,
+        }
+    }
+
+    /// Replaces the breakpoints of one kind, all kinds are managed independently.
+    fn replace_breakpoints(
+        &mut self,
+        breakpoints: Vec<Result<Breakpoint, String>>,
+        kind: BreakpointKind,
+    ) -> Result<Value, String> {
+        let old = std::mem::take(self.breakpoints_mut(kind));
+        for breakpoint in old {
+            if !self.has_breakpoint(&breakpoint) {
+                Self::expect_ok(self.request(DebugRequest::RemoveBreakpoint(breakpoint))?)?;
+            }
+        }
+
+        let mut results = Vec::new();
+        let mut set = Vec::new();
+        for breakpoint in breakpoints {
+            match breakpoint {
+                Ok(breakpoint) => {
+                    Self::expect_ok(
+                        self.request(DebugRequest::SetBreakpoint(breakpoint.clone()))?,
+                    )?;
+                    set.push(breakpoint);
+                    results.push(json!({ "verified": true }));
+                }
+                Err(msg) => results.push(json!({ "verified": false, "message": msg })),
+            }
+        }
+        *self.breakpoints_mut(kind) = set;
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn has_breakpoint(&self, breakpoint: &Breakpoint) -> bool {
+        self.function_breakpoints.contains(breakpoint)
+            || self.instruction_breakpoints.contains(breakpoint)
+            || self.source_breakpoints.contains(breakpoint)
+    }
+
+    fn parse_breakpoint(&self, name: &str) -> Result<Breakpoint, String> {
+        let name = name.trim();
+        let lower = name.to_lowercase();
+        if let Some(pattern) = lower.strip_prefix("opcode=") {
+            let pattern = pattern.trim().to_uppercase();
+            if pattern.len() == 4 && pattern.chars().all(|c| c == '*' || c.is_ascii_hexdigit()) {
+                Ok(Breakpoint::Opcode(pattern))
+            } else {
+                Err(format!("Invalid opcode pattern: {}", pattern))
+            }
+        } else if lower.starts_with("i=") {
+            Ok(Breakpoint::I(self.evaluate_value(&name[2..])?))
+        } else {
+            Ok(Breakpoint::PC(self.evaluate_value(name)?))
+        }
+    }
+
+    fn stack_trace(&mut self) -> Result<Value, String> {
+        match self.request(DebugRequest::StackTrace)? {
+            DebugResponse::StackTrace(frames) => {
+                let frames: Vec<Value> = frames
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, frame)| {
+                        let mut frame_json = json!({
+                            "id": idx,
+                            "name": frame.name,
+                            "line": 0,
+                            "column": 0,
+                            "instructionPointerReference": format!("0x{:04X}", frame.address),
+                        });
+                        if let Some((path, program)) = &self.source {
+                            if let Some(line) = program.line_of_address(frame.address) {
+                                frame_json["source"] = json!({ "path": path });
+                                frame_json["line"] = json!(line);
+                            }
+                        }
+                        frame_json
+                    })
+                    .collect();
+                Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+            }
+            response => Err(Self::error_message(response)),
+        }
+    }
+
+    fn variables(&mut self, args: &Value) -> Result<Value, String> {
+        if args["variablesReference"] != Self::REGISTERS_REFERENCE {
+            return Ok(json!({ "variables": [] }));
+        }
+        match self.request(DebugRequest::ReadRegisters(Self::REGISTERS.to_vec()))? {
+            DebugResponse::Registers(values) => {
+                let variables: Vec<Value> = Self::REGISTERS
+                    .iter()
+                    .zip(values)
+                    .map(|(register, value)| {
+                        json!({
+                            "name": register.to_string(),
+                            "value": Self::format_register(*register, value),
+                            "variablesReference": 0,
+                        })
+                    })
+                    .collect();
+                Ok(json!({ "variables": variables }))
+            }
+            response => Err(Self::error_message(response)),
+        }
+    }
+
+    fn set_variable(&mut self, args: &Value) -> Result<Value, String> {
+        let name = args["name"].as_str().unwrap_or("");
+        let register = Self::REGISTERS
+            .iter()
+            .find(|register| register.to_string().eq_ignore_ascii_case(name))
+            .copied()
+            .ok_or_else(|| format!("Unknown register: {}", name))?;
+        let value = self.evaluate_value(args["value"].as_str().unwrap_or(""))?;
+        Self::expect_ok(self.request(DebugRequest::WriteRegister(register, value))?)?;
+        match self.request(DebugRequest::ReadRegisters(vec![register]))? {
+            DebugResponse::Registers(values) => {
+                Ok(json!({ "value": Self::format_register(register, values[0]) }))
+            }
+            response => Err(Self::error_message(response)),
+        }
+    }
+
+    fn evaluate(&mut self, args: &Value) -> Result<Value, String> {
+        let value = self.evaluate_value(args["expression"].as_str().unwrap_or(""))?;
+        Ok(json!({
+            "result": format!("0x{:04X} ({})", value, value),
+            "variablesReference": 0,
+        }))
+    }
+
+    fn evaluate_value(&self, expression: &str) -> Result<u16, String> {
+        match self.request(DebugRequest::Evaluate(expression.to_string()))? {
+            DebugResponse::Value(value) => Ok(value),
+            response => Err(Self::error_message(response)),
+        }
+    }
+
+    fn resume(&mut self) -> Result<Value, String> {
+        let (response_tx, response_rx) = channel();
+        self.requests
+            .send((DebugRequest::Continue, response_tx))
+            .map_err(|_| String::from("Emulator stopped"))?;
+        self.running = Some(response_rx);
+        Ok(json!({ "allThreadsContinued": true }))
+    }
+
+    fn step(&mut self) -> Result<Value, String> {
+        match self.request(DebugRequest::Step)? {
+            DebugResponse::Stopped(_) => {
+                self.stopped("step");
+                Ok(Value::Null)
+            }
+            response => Err(Self::error_message(response)),
+        }
+    }
+
+    /// Continues until the current subroutine returns.
+    fn step_out(&mut self) -> Result<Value, String> {
+        let return_addr = match self.request(DebugRequest::StackTrace)? {
+            DebugResponse::StackTrace(frames) => frames.get(1).map(|f| f.address + 2),
+            response => return Err(Self::error_message(response)),
+        };
+        match return_addr {
+            Some(addr) => {
+                let breakpoint = Breakpoint::PC(addr);
+                Self::expect_ok(self.request(DebugRequest::SetBreakpoint(breakpoint.clone()))?)?;
+                self.step_out = Some(breakpoint);
+                self.resume()?;
+                Ok(Value::Null)
+            }
+            None => self.step(),
+        }
+    }
+
+    fn pause(&mut self) -> Result<Value, String> {
+        Self::expect_ok(self.request(DebugRequest::Halt)?)?;
+        // A running emulation reports the stop on its own
+        if self.running.is_none() {
+            self.stopped("pause");
+        }
+        Ok(Value::Null)
+    }
+
+    fn check_stopped(&mut self) -> io::Result<()> {
+        let response = match &self.running {
+            Some(response_rx) => match response_rx.try_recv() {
+                Ok(response) => response,
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => {
+                    self.running = None;
+                    return Ok(());
+                }
+            },
+            None => return Ok(()),
+        };
+        self.running = None;
+
+        let step_out = self.step_out.take();
+        if let Some(breakpoint) = &step_out {
+            if !self.has_breakpoint(breakpoint) {
+                let _ = self.request(DebugRequest::RemoveBreakpoint(breakpoint.clone()));
+            }
+        }
+
+        match response {
+            DebugResponse::Stopped(reason) => {
+                let reason = match reason {
+                    StopReason::Breakpoint if step_out.is_some() => "step",
+                    StopReason::Breakpoint => "breakpoint",
+                    StopReason::Step => "step",
+                    StopReason::Halt => "pause",
+                };
+                self.stopped(reason);
+            }
+            response => self.events.push((
+                "output",
+                json!({ "category": "stderr", "output": Self::error_message(response) }),
+            )),
+        }
+        for (event, body) in std::mem::take(&mut self.events) {
+            self.event(event, body)?;
+        }
+        Ok(())
+    }
+
+    fn stopped(&mut self, reason: &str) {
+        self.events.push((
+            "stopped",
+            json!({ "reason": reason, "threadId": Self::THREAD_ID, "allThreadsStopped": true }),
+        ));
+    }
+
+    fn request(&self, request: DebugRequest) -> Result<DebugResponse, String> {
+        let (response_tx, response_rx) = channel();
+        self.requests
+            .send((request, response_tx))
+            .map_err(|_| String::from("Emulator stopped"))?;
+        response_rx
+            .recv()
+            .map_err(|_| String::from("Emulator stopped"))
+    }
+
+    fn expect_ok(response: DebugResponse) -> Result<(), String> {
+        match response {
+            DebugResponse::Ok => Ok(()),
+            response => Err(Self::error_message(response)),
+        }
+    }
+
+    fn error_message(response: DebugResponse) -> String {
+        match response {
+            DebugResponse::Error(msg) => msg,
+            _ => String::from("Unexpected response"),
+        }
+    }
+
+    fn format_register(register: Register, value: u16) -> String {
+        match register {
+            Register::I | Register::PC => format!("0x{:04X}", value),
+            _ => format!("0x{:02X}", value),
+        }
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => (),
+            Ok(body) => response["body"] = body,
+            Err(msg) => response["message"] = json!(msg),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.output, &message)
+    }
+}
+
+fn parse_address(reference: &str) -> Option<u16> {
+    let hex = reference
+        .strip_prefix("0x")
+        .or_else(|| reference.strip_prefix("0X"))
+        .unwrap_or(reference);
+    u16::from_str_radix(hex, 16).ok()
+}
+
+/// Reads a message with its `Content-Length` header, returns `None` at the end of the input.
+fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            if content_length.is_some() {
+                break;
+            }
+            continue;
+        }
+        let mut parts = line.splitn(2, ':');
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let mut content = vec![0; content_length.unwrap_or(0)];
+    reader.read_exact(&mut content)?;
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(
+        output,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod dap_server_test {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        let mut output = Vec::new();
+        write_message(&mut output, &json!({ "seq": 1, "type": "event" })).unwrap();
+        write_message(&mut output, &json!({ "seq": 2 })).unwrap();
+        let text = String::from_utf8(output.clone()).unwrap();
+        assert!(text.starts_with("Content-Length: 24\r\n\r\n{\"seq\":1,\"type\":\"event\"}"));
+
+        let mut reader = io::Cursor::new(output);
+        assert_eq!(
+            read_message(&mut reader).unwrap(),
+            Some(json!({ "seq": 1, "type": "event" }))
+        );
+        assert_eq!(
+            read_message(&mut reader).unwrap(),
+            Some(json!({ "seq": 2 }))
+        );
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_source_breakpoints() {
+        let dir = std::env::temp_dir().join(format!("pich8-dap-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("loop.8o");
+        fs::write(&path, ": main\n  v0 := 1\n\n  loop\n    v0 += 1\n  again\n").unwrap();
+
+        let (requests_tx, requests_rx) = channel::<RemoteRequest>();
+        let emulator = thread::spawn(move || {
+            let mut breakpoints = Vec::new();
+            for (request, response_tx) in requests_rx {
+                if let DebugRequest::SetBreakpoint(breakpoint) = request {
+                    breakpoints.push(breakpoint);
+                }
+                let _ = response_tx.send(DebugResponse::Ok);
+            }
+            breakpoints
+        });
+
+        let mut server = DapServer::new(Vec::new(), requests_tx);
+        let response = server
+            .set_source_breakpoints(&json!({
+                "source": { "path": path.to_str().unwrap() },
+                "breakpoints": [{ "line": 3 }, { "line": 7 }],
+            }))
+            .unwrap();
+        assert_eq!(
+            response["breakpoints"][0],
+            json!({ "verified": true, "line": 5 })
+        );
+        assert_eq!(response["breakpoints"][1]["verified"], false);
+
+        let response = server
+            .set_source_breakpoints(&json!({
+                "source": { "path": dir.join("missing.8o").to_str().unwrap() },
+                "breakpoints": [{ "line": 1 }],
+            }))
+            .unwrap();
+        assert_eq!(response["breakpoints"][0]["verified"], false);
+
+        drop(server);
+        assert_eq!(emulator.join().unwrap(), [Breakpoint::PC(0x202)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(parse_address("0x02A4"), Some(0x2A4));
+        assert_eq!(parse_address("2a4"), Some(0x2A4));
+        assert_eq!(parse_address("main"), None);
+    }
+}
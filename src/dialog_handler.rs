@@ -3,6 +3,8 @@ use std::sync::mpsc::Receiver;
 pub enum FileDialogType {
     OpenRom,
     SaveState,
+    ExportProfile,
+    ExportCoverage,
 
     #[cfg(feature = "rom-download")]
     InputUrl,
@@ -12,6 +14,8 @@ pub enum FileDialogResult {
     None,
     OpenRom(String),
     SaveState(String),
+    ExportProfile(String),
+    ExportCoverage(String),
 
     #[cfg(feature = "rom-download")]
     InputUrl(String),
@@ -28,6 +32,10 @@ pub struct DialogHandler {
 impl DialogHandler {
     const STATE_FILTER_PATT: &'static [&'static str] = &["*.p8s"];
     const STATE_FILTER_DESC: &'static str = "pich8 State (*.p8s)";
+    const PROFILE_FILTER_PATT: &'static [&'static str] = &["*.csv", "*.json"];
+    const PROFILE_FILTER_DESC: &'static str = "Profile (*.csv, *.json)";
+    const COVERAGE_FILTER_PATT: &'static [&'static str] = &["*.txt", "*.lcov", "*.info"];
+    const COVERAGE_FILTER_DESC: &'static str = "Listing (*.txt) or LCOV (*.lcov, *.info)";
 
     pub fn new() -> Self {
         Self {
@@ -59,6 +67,16 @@ impl DialogHandler {
                         result = FileDialogResult::SaveState(if file_path.contains('.') { file_path } else { format!("{}.p8s", file_path) });
                     }
                 },
+                FileDialogType::ExportProfile => {
+                    if let Some(file_path) = tinyfiledialogs::save_file_dialog_with_filter("Export Profile", "", DialogHandler::PROFILE_FILTER_PATT, DialogHandler::PROFILE_FILTER_DESC) {
+                        result = FileDialogResult::ExportProfile(if file_path.contains('.') { file_path } else { format!("{}.csv", file_path) });
+                    }
+                },
+                FileDialogType::ExportCoverage => {
+                    if let Some(file_path) = tinyfiledialogs::save_file_dialog_with_filter("Export Coverage", "", DialogHandler::COVERAGE_FILTER_PATT, DialogHandler::COVERAGE_FILTER_DESC) {
+                        result = FileDialogResult::ExportCoverage(if file_path.contains('.') { file_path } else { format!("{}.txt", file_path) });
+                    }
+                },
 
                 #[cfg(feature = "rom-download")]
                 FileDialogType::InputUrl => {
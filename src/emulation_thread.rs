@@ -0,0 +1,342 @@
+use crate::control_server::ControlResponse;
+use crate::cpu::{self, Breakpoint, CPU};
+use crate::emulator_core::{EmulatorCore, Hooks};
+use crate::profiler::Profiler;
+use crate::video_memory::VideoMemory;
+use serde_json::Value;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+pub enum Command {
+    SetKey(usize, bool),
+    SetPaused(bool),
+    Step,
+    StepTimers,
+    /// Runs the instructions of one 60 Hz frame at the current speed and updates the timers once.
+    AdvanceFrame,
+    /// Runs frames for the control server instead of the real-time emulation, one per slice so the
+    /// render thread isn't blocked meanwhile. The result is sent once all frames ran.
+    RunFrames(u32, Sender<ControlResponse>),
+}
+
+/// Events published by the emulation thread to the render thread.
+pub enum Notification {
+    Error(String),
+    /// A breakpoint was hit, the emulation is paused already.
+    Breakpoint,
+    PlayBeep,
+    PlayBuffer([u8; 16]),
+}
+
+/// The emulation state shared with the render thread, which locks it to inspect or modify it e.g. for debugging.
+pub struct Machine {
+    pub core: EmulatorCore,
+    pub profiler: Profiler,
+    /// Breakpoints of the debug window and remote debuggers, checked while running in real time.
+    pub breakpoints: Vec<Breakpoint>,
+    notifications: Sender<Notification>,
+}
+
+impl Machine {
+    /// Runs the core with hooks for the profiler and, if running in real time, breakpoints and sound.
+    /// Sound is left out while fast-forwarding.
+    /// Returns whether a breakpoint was hit besides the result.
+    pub fn run<F>(&mut self, realtime: bool, run: F) -> (Result<(), cpu::Error>, bool)
+    where
+        F: FnOnce(&mut EmulatorCore, &mut MachineHooks) -> Result<(), cpu::Error>,
+    {
+        let mut hooks = MachineHooks {
+            profiler: &mut self.profiler,
+            breakpoints: &self.breakpoints,
+            notifications: if realtime {
+                Some(&self.notifications)
+            } else {
+                None
+            },
+            sound: realtime && !self.core.time_scale().is_fast_forward(),
+            last: (0, 0, false),
+            breakpoint: false,
+        };
+        let result = run(&mut self.core, &mut hooks);
+        (result, hooks.breakpoint)
+    }
+}
+
+/// The last finished frame, swapped with the back buffer of the emulation thread.
+pub struct Frame {
+    pub vmem: VideoMemory,
+    /// Whether the frame changed since the render thread took it.
+    pub new: bool,
+}
+
+/// Runs the emulation on its own thread at its own cadence, independent of rendering and the GUI.
+/// Input and commands are sent over a channel, finished frames and sound are published back.
+pub struct EmulationThread {
+    machine: Arc<Mutex<Machine>>,
+    frame: Arc<Mutex<Frame>>,
+    tx_command: Sender<Command>,
+    rx_notification: Receiver<Notification>,
+}
+
+impl EmulationThread {
+    /// Interval in which the emulation catches up with the elapsed time.
+    const CADENCE: Duration = Duration::from_millis(1);
+
+    pub fn new(core: EmulatorCore) -> Self {
+        let (tx_command, rx_command) = channel();
+        let (tx_notification, rx_notification) = channel();
+        let machine = Arc::new(Mutex::new(Machine {
+            core,
+            profiler: Profiler::new(),
+            breakpoints: Vec::new(),
+            notifications: tx_notification,
+        }));
+        let frame = Arc::new(Mutex::new(Frame {
+            vmem: VideoMemory::new(),
+            new: false,
+        }));
+
+        let (thread_machine, thread_frame) = (machine.clone(), frame.clone());
+        std::thread::spawn(move || Self::run(thread_machine, thread_frame, rx_command));
+
+        Self {
+            machine,
+            frame,
+            tx_command,
+            rx_notification,
+        }
+    }
+
+    /// Locks the emulation state, the emulation doesn't continue until the guard is dropped.
+    pub fn machine(&self) -> MutexGuard<'_, Machine> {
+        self.machine.lock().expect("Emulation thread panicked")
+    }
+
+    pub fn frame(&self) -> MutexGuard<'_, Frame> {
+        self.frame.lock().expect("Emulation thread panicked")
+    }
+
+    pub fn send(&self, command: Command) {
+        // Ignore if something went wrong
+        let _ = self.tx_command.send(command);
+    }
+
+    pub fn set_key(&self, key: usize, pressed: bool) {
+        self.send(Command::SetKey(key, pressed));
+    }
+
+    pub fn try_recv(&self) -> Option<Notification> {
+        self.rx_notification.try_recv().ok()
+    }
+
+    fn run(machine: Arc<Mutex<Machine>>, frame: Arc<Mutex<Frame>>, rx: Receiver<Command>) {
+        let mut back = VideoMemory::new();
+        let mut run_frames: Option<(u32, Sender<ControlResponse>)> = None;
+        loop {
+            // Wait for commands until the next slice is due, stop once the emulator is gone
+            let mut commands = Vec::new();
+            if run_frames.is_none() {
+                match rx.recv_timeout(Self::CADENCE) {
+                    Ok(command) => commands.push(command),
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+            commands.extend(rx.try_iter());
+
+            let mut machine = match machine.lock() {
+                Ok(machine) => machine,
+                Err(_) => return,
+            };
+            for command in commands {
+                match command {
+                    Command::RunFrames(_, response) if run_frames.is_some() => {
+                        let _ = response.send(Err(String::from("Already running frames")));
+                    }
+                    Command::RunFrames(frames, response) => run_frames = Some((frames, response)),
+                    command => Self::handle_command(&mut machine, command),
+                }
+            }
+
+            if let Some((frames, response)) = run_frames.take() {
+                let (result, _) = machine.run(false, |core, hooks| core.run_frame(hooks));
+                machine.core.cpu.draw = true;
+                match result {
+                    Err(e) => {
+                        let _ = response.send(Err(format!("{}", e)));
+                    }
+                    Ok(()) if frames > 1 => run_frames = Some((frames - 1, response)),
+                    Ok(()) => {
+                        let _ = response.send(Ok(Value::Null));
+                    }
+                }
+            } else {
+                let (result, breakpoint) = machine.run(true, |core, hooks| core.update(hooks));
+                if let Err(e) = result {
+                    let _ = machine
+                        .notifications
+                        .send(Notification::Error(format!("Error: {}", e)));
+                }
+                if breakpoint {
+                    machine.core.set_paused(true);
+                    let _ = machine.notifications.send(Notification::Breakpoint);
+                }
+            }
+
+            // Publish the frame by swapping buffers, so the render thread never waits for a copy
+            if machine.core.cpu.draw {
+                machine.core.cpu.draw = false;
+                back.clone_from(machine.core.cpu.vmem());
+                drop(machine);
+                if let Ok(mut frame) = frame.lock() {
+                    std::mem::swap(&mut frame.vmem, &mut back);
+                    frame.new = true;
+                }
+            }
+        }
+    }
+
+    fn handle_command(machine: &mut Machine, command: Command) {
+        match command {
+            Command::SetKey(key, pressed) => machine.core.input[key] = pressed,
+            Command::SetPaused(paused) => machine.core.set_paused(paused),
+            Command::Step => {
+                if let (Err(e), _) = machine.run(false, |core, hooks| core.tick(hooks).map(|_| ()))
+                {
+                    let _ = machine
+                        .notifications
+                        .send(Notification::Error(format!("Error: {}", e)));
+                }
+            }
+            Command::StepTimers => machine.core.cpu.update_timers(),
+            Command::AdvanceFrame => {
+                if let (Err(e), _) = machine.run(false, |core, hooks| core.run_frame(hooks)) {
+                    let _ = machine
+                        .notifications
+                        .send(Notification::Error(format!("Error: {}", e)));
+                }
+                // Publish the frame even if nothing was drawn
+                machine.core.cpu.draw = true;
+            }
+            Command::RunFrames(..) => unreachable!("Frames are run by the emulation loop"),
+        }
+    }
+}
+
+/// Connects the emulation loop to the profiler, breakpoints and sound output.
+pub struct MachineHooks<'a> {
+    profiler: &'a mut Profiler,
+    breakpoints: &'a [Breakpoint],
+    notifications: Option<&'a Sender<Notification>>,
+    sound: bool,
+    /// PC, opcode and key wait state before the current instruction
+    last: (u16, u16, bool),
+    breakpoint: bool,
+}
+
+impl Hooks for MachineHooks<'_> {
+    fn before_tick(&mut self, cpu: &CPU) -> bool {
+        if self.profiler.enabled {
+            self.last = (cpu.PC(), cpu.next_opcode(), cpu.key_wait());
+        }
+        true
+    }
+
+    fn after_tick(&mut self, cpu: &CPU) -> bool {
+        if self.profiler.enabled {
+            let (pc, opcode, key_wait) = self.last;
+            if key_wait && cpu.key_wait() {
+                self.profiler.record_key_wait();
+            } else {
+                self.profiler.record(pc, opcode);
+            }
+        }
+
+        if self.notifications.is_some()
+            && self.breakpoints.iter().any(|bp| cpu.check_breakpoint(bp))
+        {
+            self.breakpoint = true;
+            return false;
+        }
+        true
+    }
+
+    fn before_timers(&mut self, cpu: &CPU) {
+        if let (Some(notifications), true) = (self.notifications, self.sound && cpu.ST() > 0) {
+            let _ = notifications.send(match cpu.audio_buffer() {
+                Some(buffer) => Notification::PlayBuffer(buffer),
+                None => Notification::PlayBeep,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod emulation_thread_test {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_emulation_thread() {
+        let mut core = EmulatorCore::new();
+        // Clear the screen, then loop endlessly at 202
+        core.cpu.load_rom(&[0x00, 0xE0, 0x12, 0x02]).unwrap();
+        let emulation = EmulationThread::new(core);
+        emulation.machine().breakpoints = vec![Breakpoint::PC(0x202)];
+
+        let start = Instant::now();
+        let notification = loop {
+            assert!(start.elapsed() < Duration::from_secs(5), "No notification");
+            if let Some(notification) = emulation.try_recv() {
+                break notification;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        assert!(matches!(notification, Notification::Breakpoint));
+        assert!(emulation.machine().core.paused());
+        assert_eq!(emulation.machine().core.cpu.PC(), 0x202);
+
+        // The cleared screen is published eventually
+        while !emulation.frame().new {
+            assert!(start.elapsed() < Duration::from_secs(5), "No frame");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        emulation.set_key(5, true);
+        emulation.send(Command::Step);
+        while !emulation.machine().core.input[5] {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "Command not handled"
+            );
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // Advancing a frame while paused publishes it again
+        emulation.frame().new = false;
+        emulation.send(Command::AdvanceFrame);
+        while !emulation.frame().new {
+            assert!(start.elapsed() < Duration::from_secs(5), "No frame");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(emulation.machine().core.paused());
+    }
+
+    #[test]
+    fn test_run_frames() {
+        let mut core = EmulatorCore::new();
+        // Increment V0 once per frame of two instructions
+        core.cpu.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        core.set_speed(crate::emulator_core::Speed::InstructionsPerFrame(2));
+        core.set_paused(true);
+        let emulation = EmulationThread::new(core);
+
+        let (response_tx, response_rx) = channel();
+        emulation.send(Command::RunFrames(5, response_tx));
+        let response = response_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(response, Ok(Value::Null));
+        assert_eq!(emulation.machine().core.cpu.V()[0], 5);
+        assert!(emulation.machine().core.paused());
+    }
+}
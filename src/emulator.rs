@@ -1,15 +1,38 @@
+use crate::config::{Config, Overrides};
+use crate::control_server::{ControlMessage, ControlRequest, ControlServer};
 use crate::cpu::{Breakpoint, CPU};
+use crate::dap_server::DapServer;
 use crate::dialog_handler::{DialogHandler, FileDialogResult, FileDialogType};
 use crate::display::WindowDisplay;
+use crate::emulation_thread::{Command, EmulationThread, Notification};
+use crate::emulator_core::EmulatorCore;
+use crate::expression;
+use crate::file_type::{self, FileType, Platform};
 use crate::fps_counter::FpsCounter;
+use crate::gdb_stub::GdbStub;
 use crate::gui::GUI;
 use crate::gui::{Color, Quirk};
+use crate::octo_assembler::{self, Program};
+use crate::octo_cartridge::Cartridge;
+use crate::octo_options::OctoOptions;
+use crate::remote_debug::{DebugRequest, DebugResponse, RemoteDebug, StackFrame, StopReason};
+use crate::rom_database::RomDatabase;
+use crate::save_slots::{self, SaveSlots};
 use crate::sound::AudioPlayer;
+use crate::state_file::{self, StateFile, StateHeader};
+use crate::symbols::SymbolTable;
+use crate::video_memory::Plane;
 use glium::glutin::{
     event::{ElementState, Event, KeyboardInput, ModifiersState, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
 };
-use std::{fs, time::Instant};
+use serde_json::{json, Value};
+use std::sync::mpsc::Receiver;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
 #[cfg(feature = "rom-download")]
 use crate::rom_downloader::{DownloadResult, RomDownloader};
@@ -21,77 +44,95 @@ enum LoadedType {
 }
 
 pub struct Emulator {
-    cpu: CPU,
-    cpu_speed: u32,
+    emulation: EmulationThread,
     display: WindowDisplay,
     gui: GUI,
     sound: AudioPlayer,
     fps_counter: FpsCounter,
     mute: bool,
-    input: [bool; 16],
     loaded: LoadedType,
+    rom_path: Option<String>,
+    symbols: SymbolTable,
+    slots: SaveSlots,
+    rom_database: RomDatabase,
+    remote_debug: RemoteDebug,
+    control: Option<Receiver<ControlMessage>>,
     pause: bool,
-    step: bool,
-    step_timers: bool,
     frame_time: Instant,
-    last_timer: Instant,
-    last_cycle: Instant,
-    pause_time: Instant,
     dialog_handler: DialogHandler,
     modifiers_state: ModifiersState,
-    last_correction_cpu: Instant,
-    counter_cpu: u32,
-    last_correction_timer: Instant,
-    counter_timer: u32,
     force_redraw: bool,
+    /// Config file to save the settings to on exit, `None` if they aren't persisted
+    config_path: Option<PathBuf>,
+    loaded_config: Config,
+    overrides: Overrides,
+    /// The user's settings and the settings after the ones of the loaded ROM were applied,
+    /// `None` if no ROM was opened since the settings were loaded
+    rom_settings: Option<(Config, Config)>,
 
     #[cfg(feature = "rom-download")]
     rom_downloader: RomDownloader,
 }
 
 impl Emulator {
-    const CPU_FREQUENCY: u16 = 720;
-    const TIMER_FREQUENCY: u8 = 60;
-    const NANOS_PER_TIMER: u64 = 1_000_000_000 / Emulator::TIMER_FREQUENCY as u64;
-    const MAX_FILE_SIZE: u32 = u16::MAX as u32 + 10000;
-
-    pub fn new(event_loop: &EventLoop<()>, vsync: bool) -> Result<Self, String> {
-        let display = WindowDisplay::new(&event_loop, vsync)?;
-        let mut cpu = CPU::new();
-        cpu.load_bootrom();
-        cpu.draw = true;
-        let cpu_speed = Emulator::CPU_FREQUENCY as u32;
+    /// Creates the emulator with the settings of the config file, if any, and the overrides applied.
+    pub fn new(
+        event_loop: &EventLoop<()>,
+        config_path: Option<PathBuf>,
+        overrides: Overrides,
+    ) -> Result<Self, String> {
+        // Don't overwrite an invalid config file with the defaults
+        let (config_path, loaded_config, config_error) = match config_path {
+            Some(path) => match Config::load(&path) {
+                Ok(config) => (Some(path), config, None),
+                Err(msg) => (None, Config::default(), Some(msg)),
+            },
+            None => (None, Config::default(), None),
+        };
+        let mut config = loaded_config.clone();
+        overrides.apply(&mut config);
+
+        let display = WindowDisplay::new(event_loop, config.vsync)?;
+        let mut core = EmulatorCore::new();
+        core.cpu.load_bootrom();
+        core.cpu.draw = true;
+        core.set_speed(config.speed());
 
         // Initialize GUI
         let mut gui = GUI::new(display.display());
-        gui.cpu_speed = cpu_speed;
-        gui.volume = 0.25;
+        gui.apply_config(&config);
+        if let Some(msg) = config_error {
+            gui.display_error(&msg);
+        }
+
+        let mut rom_database = RomDatabase::bundled();
+        if let Some(Err(msg)) = RomDatabase::user_dir().map(|dir| rom_database.add_dir(&dir)) {
+            gui.display_error(&msg);
+        }
 
-        let now = Instant::now();
         Ok(Self {
-            cpu,
-            cpu_speed,
+            emulation: EmulationThread::new(core),
             display,
             gui,
             sound: AudioPlayer::new().expect("Failed to create sound output device"),
             mute: false,
-            input: [false; 16],
             loaded: LoadedType::Nothing,
+            rom_path: None,
+            symbols: SymbolTable::new(),
+            slots: SaveSlots::none(),
+            rom_database,
+            remote_debug: RemoteDebug::new(),
+            control: None,
             pause: false,
-            step: false,
-            step_timers: false,
-            frame_time: now,
-            last_timer: now,
-            last_cycle: now,
-            pause_time: now,
+            frame_time: Instant::now(),
             dialog_handler: DialogHandler::new(),
             fps_counter: FpsCounter::new(),
             modifiers_state: ModifiersState::empty(),
-            last_correction_cpu: Instant::now(),
-            counter_cpu: 0,
-            last_correction_timer: Instant::now(),
-            counter_timer: 0,
             force_redraw: true,
+            config_path,
+            loaded_config,
+            overrides,
+            rom_settings: None,
 
             #[cfg(feature = "rom-download")]
             rom_downloader: RomDownloader::new(),
@@ -99,10 +140,12 @@ impl Emulator {
     }
 
     fn reset(&mut self) {
+        let mut machine = self.emulation.machine();
+        machine.profiler.reset();
         match &self.loaded {
             LoadedType::Rom(rom) => {
-                self.cpu = CPU::new();
-                match self.cpu.load_rom(&rom) {
+                machine.core.set_cpu(CPU::new());
+                match machine.core.cpu.load_rom(rom) {
                     Ok(_) => {
                         if !self.gui.flag_debug {
                             self.gui.flag_pause = false;
@@ -112,8 +155,8 @@ impl Emulator {
                 }
             }
             LoadedType::State(state) => {
-                match CPU::from_state(&state) {
-                    Ok(cpu) => self.cpu = cpu,
+                match StateFile::parse(state) {
+                    Ok(state) => machine.core.set_cpu(state.cpu),
                     Err(msg) => self.gui.display_error(&msg),
                 }
                 self.gui.flag_pause = false;
@@ -124,24 +167,250 @@ impl Emulator {
 
     pub fn load_rom(&mut self, rom: &[u8]) {
         self.loaded = LoadedType::Rom(rom.to_vec());
+        self.rom_path = None;
+        self.symbols = SymbolTable::new();
+        self.slots = SaveSlots::for_rom(rom);
         self.reset();
     }
 
+    /// Loads a ROM with the settings of its platform, the ROM database and Octo options applied
+    /// on top of the user's settings, which are remembered to save them instead.
+    fn load_rom_with_settings(
+        &mut self,
+        rom: &[u8],
+        platform: Option<Platform>,
+        options: Option<&OctoOptions>,
+    ) {
+        let user = self.user_config();
+        let info = self.rom_database.lookup(rom);
+        self.gui
+            .apply_rom_settings(&user.for_rom(platform, info.as_ref(), options));
+        self.rom_settings = Some((user, self.gui.config()));
+        if let Some(options) = options.filter(|options| !options.unsupported.is_empty()) {
+            self.gui.display_error(&format!(
+                "Octo options not supported by pich8 are ignored: {}",
+                options.unsupported.join(", ")
+            ));
+        }
+        self.load_rom(rom);
+        self.gui.set_rom_info(info);
+    }
+
+    /// Loads a state file, warning if it was saved for another ROM than the loaded one.
     pub fn load_state(&mut self, state: &[u8]) {
+        if let (LoadedType::Rom(rom), Ok(file)) = (&self.loaded, StateFile::parse(state)) {
+            if let Some(warning) = file.header.check_rom(rom) {
+                self.gui.display_error(&warning);
+            }
+        }
         self.loaded = LoadedType::State(state.to_vec());
+        self.rom_path = None;
+        self.symbols = SymbolTable::new();
+        self.slots = SaveSlots::none();
+        self.gui.set_rom_info(None);
         self.reset();
     }
 
-    fn set_pause(&mut self, pause: bool) {
-        self.pause = pause;
-        if pause {
-            // Store timestamp
-            self.pause_time = Instant::now();
-        } else {
-            // "Subtract" paused time so the simulation doesn't jump
-            let diff = Instant::now() - self.pause_time;
-            self.last_cycle += diff;
+    /// Opens a ROM, Octo source, Octo cartridge, state file or a ZIP archive containing one.
+    fn open_file(&mut self, file_path: String) -> Result<(), String> {
+        let (file_type, path, file) = file_type::read(Path::new(&file_path))?;
+        match file_type {
+            FileType::Cartridge => {
+                self.load_cartridge(&file)?;
+                self.rom_path = Some(path.to_string_lossy().into_owned());
+            }
+            FileType::State => self.load_state(&file),
+            FileType::OctoSource => {
+                let source = String::from_utf8(file)
+                    .map_err(|_| String::from("The Octo source is not valid UTF-8!"))?;
+                let program = octo_assembler::assemble(&source)
+                    .map_err(|msg| format!("Failed to assemble the program: {}", msg))?;
+                self.load_program(&program, &path);
+            }
+            FileType::Rom(platform) => {
+                let options = self.load_options(&path);
+                self.load_rom_with_settings(&file, platform, options.as_ref());
+                self.load_symbols(&path);
+                self.rom_path = Some(path.to_string_lossy().into_owned());
+            }
+            FileType::Zip => unreachable!("ZIP archives are extracted when read"),
+        }
+        Ok(())
+    }
+
+    /// The settings chosen by the user, without the ones of the loaded ROM.
+    fn user_config(&mut self) -> Config {
+        let mut config = self.gui.config();
+        if let Some((user, rom)) = &self.rom_settings {
+            config.revert_rom_settings(rom, user);
+        }
+        config
+    }
+
+    /// Opens a state file only, other files are rejected.
+    fn open_state_file(&mut self, file_path: &str) -> Result<(), String> {
+        let metadata = fs::metadata(file_path).map_err(|err| format!("Error: {}", err))?;
+        if metadata.len() > file_type::MAX_ROM_SIZE as u64 {
+            return Err(String::from("File is too big!"));
+        }
+        let file = fs::read(file_path).map_err(|err| format!("Error: {}", err))?;
+        if FileType::detect(Path::new(file_path), &file)? != FileType::State {
+            return Err(String::from("Not a state file!"));
+        }
+        StateFile::parse(&file)?;
+        self.load_state(&file);
+        Ok(())
+    }
+
+    fn save_state_file(&mut self, file_path: String) -> Result<(), String> {
+        let state = self.state_file()?;
+        fs::write(file_path, state).map_err(|_| String::from("Failed to write to file!"))
+    }
+
+    /// Saves the state of the CPU in the format of a state file.
+    fn state_file(&mut self) -> Result<Vec<u8>, String> {
+        let header = self.state_header();
+        StateFile::to_bytes(&header, &self.emulation.machine().core.cpu)
+    }
+
+    /// Describes the loaded ROM and the settings it's running with.
+    fn state_header(&mut self) -> StateHeader {
+        let mut header = StateHeader::new();
+        match &self.loaded {
+            LoadedType::Rom(rom) => {
+                header.rom_hash = Some(state_file::rom_hash(rom));
+                header.rom_name = self
+                    .rom_path
+                    .as_deref()
+                    .and_then(|path| Path::new(path).file_name())
+                    .map(|name| name.to_string_lossy().into_owned());
+            }
+            LoadedType::State(state) => {
+                // Keep referring to the ROM the loaded state was made for
+                if let Ok(state) = StateFile::parse(state) {
+                    header.rom_hash = state.header.rom_hash;
+                    header.rom_name = state.header.rom_name;
+                }
+            }
+            LoadedType::Nothing => (),
+        }
+        header.platform = self.gui.quirks_preset_name().map(String::from);
+        header.speed = Some(self.gui.speed());
+        header.palette = Some(self.palette());
+        header
+    }
+
+    /// Colors of background, first plane, second plane and both planes.
+    fn palette(&self) -> [[u8; 3]; 4] {
+        [
+            self.display.color_bg,
+            self.display.color_plane_1,
+            self.display.color_plane_2,
+            self.display.color_plane_both,
+        ]
+    }
+
+    fn save_slot(&mut self, slot: usize) -> Result<(), String> {
+        let state = self.state_file()?;
+        let thumbnail =
+            save_slots::thumbnail_png(self.emulation.machine().core.cpu.vmem(), self.palette())?;
+        self.slots.save(slot, state, thumbnail)
+    }
+
+    /// Restores the state of a slot, the ROM stays loaded for resets.
+    fn load_slot(&mut self, slot: usize) -> Result<(), String> {
+        let state = StateFile::parse(&self.slots.load(slot)?)?;
+        if let LoadedType::Rom(rom) = &self.loaded {
+            if let Some(warning) = state.header.check_rom(rom) {
+                self.gui.display_error(&warning);
+            }
         }
+        let mut machine = self.emulation.machine();
+        machine.core.set_cpu(state.cpu);
+        machine.core.cpu.draw = true;
+        Ok(())
+    }
+
+    fn load_symbols(&mut self, rom_path: &Path) {
+        match SymbolTable::load_for_rom(rom_path) {
+            Some(Ok(symbols)) => self.symbols = symbols,
+            Some(Err(msg)) => self.gui.display_error(&msg),
+            None => (),
+        }
+    }
+
+    /// Loads the options exported by Octo along with the ROM, if any.
+    fn load_options(&mut self, rom_path: &Path) -> Option<OctoOptions> {
+        match OctoOptions::load_for_rom(rom_path) {
+            Some(Ok(options)) => Some(options),
+            Some(Err(msg)) => {
+                self.gui.display_error(&msg);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Assembles the program of an Octo cartridge and runs it with the options it was saved with.
+    fn load_cartridge(&mut self, data: &[u8]) -> Result<(), String> {
+        let cartridge = Cartridge::parse(data)?;
+        let program = octo_assembler::assemble(&cartridge.program)
+            .map_err(|msg| format!("Failed to assemble the cartridge: {}", msg))?;
+        self.load_rom_with_settings(&program.rom, Some(Platform::Octo), Some(&cartridge.options));
+        self.insert_labels(&program);
+        Ok(())
+    }
+
+    /// Runs an assembled Octo source file with the options and symbols next to it, if any.
+    fn load_program(&mut self, program: &Program, source_path: &Path) {
+        let options = self.load_options(source_path);
+        self.load_rom_with_settings(&program.rom, Some(Platform::Octo), options.as_ref());
+        self.load_symbols(source_path);
+        self.insert_labels(program);
+        self.rom_path = Some(source_path.to_string_lossy().into_owned());
+    }
+
+    fn insert_labels(&mut self, program: &Program) {
+        for (name, addr) in &program.labels {
+            self.symbols.insert(name, *addr);
+        }
+    }
+
+    /// Saves the settings to the config file, leaving out the overrides and the settings of the ROM.
+    fn save_config(&mut self) -> Result<(), String> {
+        let mut config = self.user_config();
+        let path = match &self.config_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        config.vsync = self.loaded_config.vsync;
+        self.overrides.revert(&mut config, &self.loaded_config);
+        config.save(path)
+    }
+
+    fn restore_defaults(&mut self) {
+        let config = Config::default();
+        self.gui.apply_config(&config);
+        self.loaded_config = config;
+        self.overrides = Overrides::default();
+        self.rom_settings = None;
+    }
+
+    pub fn start_gdb_stub(&mut self, port: u16) -> Result<(), String> {
+        GdbStub::start(port, self.remote_debug.sender())
+    }
+
+    pub fn start_dap_stdio(&mut self) {
+        DapServer::start_stdio(self.remote_debug.sender());
+    }
+
+    pub fn start_dap_server(&mut self, port: u16) -> Result<(), String> {
+        DapServer::start_tcp(port, self.remote_debug.sender())
+    }
+
+    pub fn start_control_server(&mut self, address: &str) -> Result<(), String> {
+        self.control = Some(ControlServer::start(address)?);
+        Ok(())
     }
 
     #[cfg(feature = "rom-download")]
@@ -150,7 +419,7 @@ impl Emulator {
             match self.rom_downloader.check_result() {
                 DownloadResult::Success(data) => {
                     self.gui.flag_downloading = false;
-                    self.load_rom(&data);
+                    self.load_rom_with_settings(&data, None, None);
                 }
                 DownloadResult::Fail(msg) => {
                     self.gui.flag_downloading = false;
@@ -161,41 +430,252 @@ impl Emulator {
         }
     }
 
+    fn handle_control_requests(&mut self) {
+        while let Some((request, response_tx)) = self
+            .control
+            .as_ref()
+            .and_then(|chan_rx| chan_rx.try_recv().ok())
+        {
+            let response = match request {
+                ControlRequest::LoadRom(file_path) => {
+                    self.open_file(file_path).map(|_| Value::Null)
+                }
+                ControlRequest::LoadState(file_path) => {
+                    self.open_state_file(&file_path).map(|_| Value::Null)
+                }
+                ControlRequest::SaveState(file_path) => {
+                    self.save_state_file(file_path).map(|_| Value::Null)
+                }
+                ControlRequest::SetKey(key, pressed) => {
+                    // Set directly instead of queued, so it applies to frames run right after
+                    self.emulation.machine().core.input[key] = pressed;
+                    Ok(Value::Null)
+                }
+                ControlRequest::SetPaused(paused) => {
+                    self.gui.flag_pause = paused;
+                    Ok(Value::Null)
+                }
+                ControlRequest::RunFrames(_) if !self.gui.flag_pause => {
+                    Err(String::from("Pause the emulator before running frames"))
+                }
+                ControlRequest::RunFrames(frames) => {
+                    // Answered by the emulation thread once the frames ran
+                    self.emulation.send(Command::SetPaused(true));
+                    self.emulation.send(Command::RunFrames(frames, response_tx));
+                    continue;
+                }
+                ControlRequest::GetRegisters => {
+                    let cpu = &self.emulation.machine().core.cpu;
+                    Ok(json!({
+                        "V": cpu.V(),
+                        "I": cpu.I(),
+                        "PC": cpu.PC(),
+                        "SP": cpu.sp(),
+                        "DT": cpu.DT(),
+                        "ST": cpu.ST(),
+                    }))
+                }
+                ControlRequest::ReadMemory(addr, len) => {
+                    let machine = self.emulation.machine();
+                    let mem = machine.core.cpu.mem();
+                    let data: Vec<u8> = (0..len.min(mem.len()))
+                        .map(|offset| mem[(addr as usize + offset) % mem.len()])
+                        .collect();
+                    Ok(json!(data))
+                }
+                ControlRequest::GetFramebuffer => {
+                    // Each pixel holds the bits of both planes
+                    let machine = self.emulation.machine();
+                    let vmem = machine.core.cpu.vmem();
+                    let (width, height) = (vmem.width(), vmem.height());
+                    let pixels: Vec<u8> = (0..height)
+                        .flat_map(|y| (0..width).map(move |x| (x, y)))
+                        .map(|(x, y)| {
+                            vmem.get_plane(Plane::First, x, y) as u8
+                                | (vmem.get_plane(Plane::Second, x, y) as u8) << 1
+                        })
+                        .collect();
+                    Ok(json!({ "width": width, "height": height, "pixels": pixels }))
+                }
+            };
+            let _ = response_tx.send(response);
+        }
+    }
+
+    fn handle_remote_debug(&mut self) {
+        while let Some((request, response_tx)) = self.remote_debug.try_recv() {
+            let response = match request {
+                DebugRequest::ReadRegisters(registers) => {
+                    let cpu = &self.emulation.machine().core.cpu;
+                    DebugResponse::Registers(
+                        registers
+                            .into_iter()
+                            .map(|register| cpu.register(register))
+                            .collect(),
+                    )
+                }
+                DebugRequest::WriteRegister(register, value) => {
+                    match self
+                        .emulation
+                        .machine()
+                        .core
+                        .cpu
+                        .set_register(register, value)
+                    {
+                        Ok(_) => DebugResponse::Ok,
+                        Err(e) => DebugResponse::Error(format!("{}", e)),
+                    }
+                }
+                DebugRequest::WriteRegisters(values) => {
+                    match self.emulation.machine().core.cpu.set_registers(&values) {
+                        Ok(_) => DebugResponse::Ok,
+                        Err(e) => DebugResponse::Error(format!("{}", e)),
+                    }
+                }
+                DebugRequest::ReadMemory(addr, len) => {
+                    let machine = self.emulation.machine();
+                    let mem = machine.core.cpu.mem();
+                    DebugResponse::Memory(
+                        (0..len.min(mem.len()))
+                            .map(|offset| mem[(addr as usize + offset) % mem.len()])
+                            .collect(),
+                    )
+                }
+                DebugRequest::WriteMemory(addr, data) => {
+                    match self.emulation.machine().core.cpu.write_mem(addr, &data) {
+                        Ok(_) => DebugResponse::Ok,
+                        Err(e) => DebugResponse::Error(format!("{}", e)),
+                    }
+                }
+                DebugRequest::SetBreakpoint(breakpoint) => {
+                    self.remote_debug.set_breakpoint(breakpoint);
+                    DebugResponse::Ok
+                }
+                DebugRequest::RemoveBreakpoint(breakpoint) => {
+                    self.remote_debug.remove_breakpoint(&breakpoint);
+                    DebugResponse::Ok
+                }
+                DebugRequest::Evaluate(expr) => {
+                    let cpu = &self.emulation.machine().core.cpu;
+                    match expression::evaluate(&expr, cpu, &self.symbols) {
+                        Ok(value) => DebugResponse::Value(value),
+                        Err(msg) => DebugResponse::Error(msg),
+                    }
+                }
+                DebugRequest::StackTrace => {
+                    let cpu = &self.emulation.machine().core.cpu;
+                    let stack = cpu.stack();
+                    let frames = std::iter::once(cpu.PC())
+                        .chain(stack[..cpu.sp()].iter().rev().copied())
+                        .map(|address| StackFrame {
+                            address,
+                            name: self
+                                .symbols
+                                .format_routine(address)
+                                .unwrap_or_else(|| format!("{:04X}", address)),
+                        })
+                        .collect();
+                    DebugResponse::StackTrace(frames)
+                }
+                DebugRequest::LoadRom(file_path) => match self.open_file(file_path) {
+                    Ok(_) => DebugResponse::Ok,
+                    Err(msg) => DebugResponse::Error(msg),
+                },
+                DebugRequest::Halt => {
+                    self.gui.flag_pause = true;
+                    self.emulation.machine().core.set_paused(true);
+                    DebugResponse::Ok
+                }
+                DebugRequest::Step => {
+                    self.gui.flag_pause = true;
+                    let mut machine = self.emulation.machine();
+                    machine.core.set_paused(true);
+                    match machine
+                        .run(false, |core, hooks| core.tick(hooks).map(|_| ()))
+                        .0
+                    {
+                        Ok(_) => DebugResponse::Stopped(StopReason::Step),
+                        Err(e) => DebugResponse::Error(format!("{}", e)),
+                    }
+                }
+                DebugRequest::Continue => {
+                    // The response is sent once the emulation stops again
+                    self.gui.flag_pause = false;
+                    self.remote_debug.wait_for_stop(response_tx);
+                    continue;
+                }
+                DebugRequest::Detach => {
+                    self.remote_debug.clear();
+                    self.gui.flag_pause = false;
+                    DebugResponse::Ok
+                }
+            };
+            let _ = response_tx.send(response);
+        }
+
+        if self.remote_debug.is_waiting_for_stop() && self.gui.flag_pause {
+            let cpu = &self.emulation.machine().core.cpu;
+            let reason = if self.remote_debug.check_breakpoints(cpu) {
+                StopReason::Breakpoint
+            } else {
+                StopReason::Halt
+            };
+            self.remote_debug.notify_stop(reason);
+        }
+    }
+
     pub fn handle_event(&mut self, event: Event<()>, ctrl_flow: &mut ControlFlow) {
+        if let Event::LoopDestroyed = event {
+            if let Err(msg) = self.save_config() {
+                eprintln!("{}", msg);
+            }
+            return;
+        }
+
         // Handle file dialogs
         if self.dialog_handler.is_open() {
             match self.dialog_handler.check_result() {
                 FileDialogResult::OpenRom(file_path) => {
-                    match fs::metadata(&file_path) {
-                        Ok(metadata) => {
-                            if metadata.len() <= Self::MAX_FILE_SIZE as u64 {
-                                match fs::read(&file_path) {
-                                    Ok(file) => {
-                                        // Check if it's a p8s state file, otherwise expect ROM
-                                        if &file[0..3] == b"p8s" {
-                                            self.load_state(&file[3..]);
-                                        } else {
-                                            self.load_rom(&file);
-                                        }
-                                    }
-                                    Err(err) => self.gui.display_error(&format!("Error: {}", err)),
-                                }
-                            } else {
-                                self.gui.display_error("File is too big!");
-                            }
-                        }
-                        Err(err) => self.gui.display_error(&format!("Error: {}", err)),
+                    if let Err(msg) = self.open_file(file_path) {
+                        self.gui.display_error(&msg);
                     }
                 }
-                FileDialogResult::SaveState(file_path) => match self.cpu.save_state().as_mut() {
-                    Ok(state) => {
-                        state.splice(0..0, b"p8s".iter().cloned());
-                        if fs::write(file_path, state).is_err() {
-                            self.gui.display_error("Failed to write to file!");
+                FileDialogResult::SaveState(file_path) => {
+                    if let Err(msg) = self.save_state_file(file_path) {
+                        self.gui.display_error(&msg);
+                    }
+                }
+                FileDialogResult::ExportCoverage(file_path) => {
+                    let machine = self.emulation.machine();
+                    let coverage = machine.core.cpu.coverage();
+                    let range = machine.core.cpu.rom_range();
+                    let lower = file_path.to_lowercase();
+                    let export = if lower.ends_with(".lcov") || lower.ends_with(".info") {
+                        let name = self.rom_path.as_deref().unwrap_or("rom.ch8");
+                        coverage.lcov(name, range, &self.symbols)
+                    } else {
+                        coverage.listing(machine.core.cpu.mem(), range, &self.symbols)
+                    };
+                    if fs::write(file_path, export).is_err() {
+                        self.gui.display_error("Failed to write to file!");
+                    }
+                }
+                FileDialogResult::ExportProfile(file_path) => {
+                    let profiler = &self.emulation.machine().profiler;
+                    let profile = if file_path.to_lowercase().ends_with(".json") {
+                        profiler.to_json(&self.symbols)
+                    } else {
+                        Ok(profiler.to_csv(&self.symbols))
+                    };
+                    match profile {
+                        Ok(profile) => {
+                            if fs::write(file_path, profile).is_err() {
+                                self.gui.display_error("Failed to write to file!");
+                            }
                         }
+                        Err(msg) => self.gui.display_error(&msg),
                     }
-                    Err(msg) => self.gui.display_error(&msg),
-                },
+                }
 
                 #[cfg(feature = "rom-download")]
                 FileDialogResult::InputUrl(url) => {
@@ -214,6 +694,13 @@ impl Emulator {
         #[cfg(feature = "rom-download")]
         self.handle_downloads();
 
+        // Handle errors, breakpoints and sound of the emulation thread
+        self.handle_notifications();
+
+        // Handle requests of remote debuggers and automation clients
+        self.handle_remote_debug();
+        self.handle_control_requests();
+
         // Handle events
         if !self.dialog_handler.is_open() {
             self.gui.handle_event(self.display.display(), &event);
@@ -222,75 +709,6 @@ impl Emulator {
                     self.handle_gui_flags(ctrl_flow);
                 }
                 Event::MainEventsCleared => {
-                    if !self.pause {
-                        // Perform emulation
-                        let nanos_per_cycle = 1_000_000_000 / self.cpu_speed as u64;
-                        if self.last_cycle.elapsed().as_nanos() as u64 >= nanos_per_cycle * 10 {
-                            let mut cycles = (self.last_cycle.elapsed().as_nanos() as f64
-                                / nanos_per_cycle as f64)
-                                as u32;
-                            self.last_cycle = Instant::now();
-
-                            // Check if additional cycles are needed
-                            if self.last_correction_cpu.elapsed().as_secs_f64() >= 0.25 {
-                                let target = self.cpu_speed / 4;
-                                if self.counter_cpu < target {
-                                    cycles += target - self.counter_cpu;
-                                }
-                                self.last_correction_cpu = Instant::now();
-                                self.counter_cpu = 0;
-                            } else {
-                                self.counter_cpu += cycles;
-                            }
-
-                            for _ in 0..cycles {
-                                if let Err(e) = self.cpu.tick(&self.input) {
-                                    self.gui.display_error(&format!("Error: {}", e));
-                                    continue;
-                                }
-                                if self.gui.flag_debug && self.check_breakpoints() {
-                                    self.gui.flag_pause = true;
-                                    break;
-                                }
-                            }
-                        }
-                        // Update CPU timers
-                        if self.last_timer.elapsed().as_nanos() as u64 >= Emulator::NANOS_PER_TIMER
-                        {
-                            self.last_timer = Instant::now();
-                            let mut reps = 1;
-
-                            // Check and correct frequency regularly
-                            if self.last_correction_timer.elapsed().as_secs_f64() >= 0.25 {
-                                let target = Self::TIMER_FREQUENCY as u32 / 4;
-                                if self.counter_timer + 1 < target {
-                                    reps += target - self.counter_timer - 1;
-                                }
-                                self.last_correction_timer = Instant::now();
-                                self.counter_timer = 0;
-                            } else {
-                                self.counter_timer += reps;
-                            }
-
-                            for _ in 0..reps {
-                                if self.cpu.ST() > 0 && !self.mute {
-                                    if self.cpu.audio_buffer().is_some() {
-                                        self.sound.play_buffer(self.cpu.audio_buffer().unwrap());
-                                    } else {
-                                        self.sound.beep();
-                                    }
-                                }
-                                self.cpu.update_timers();
-                            }
-                        }
-                    } else if self.step {
-                        if let Err(e) = self.cpu.tick(&self.input) {
-                            self.gui.display_error(&format!("Error: {}", e));
-                        }
-                    } else if self.step_timers {
-                        self.cpu.update_timers();
-                    }
-
                     // Always request redrawing to keep the GUI updated
                     self.gui
                         .prepare_frame(self.display.display())
@@ -308,9 +726,10 @@ impl Emulator {
                     } else {
                         self.gui.menu_height()
                     };
-                    let vmem = if self.force_redraw || self.cpu.draw {
-                        self.cpu.draw = false;
-                        Some(self.cpu.vmem())
+                    let mut emulation_frame = self.emulation.frame();
+                    let vmem = if self.force_redraw || emulation_frame.new {
+                        emulation_frame.new = false;
+                        Some(&emulation_frame.vmem)
                     } else {
                         None
                     };
@@ -318,14 +737,23 @@ impl Emulator {
                         .display
                         .prepare(vmem, height)
                         .expect("Failed to prepare frame");
+                    drop(emulation_frame);
                     if !is_fullscreen {
+                        // Render a snapshot, so the emulation thread isn't blocked by the GUI
+                        let (cpu, profiler) = {
+                            let machine = self.emulation.machine();
+                            (machine.core.cpu.clone(), machine.profiler.clone())
+                        };
                         self.gui
                             .render(
                                 frame_duration,
                                 self.display.display(),
                                 &mut frame,
                                 fps,
-                                &self.cpu,
+                                &cpu,
+                                &self.symbols,
+                                &profiler,
+                                &self.slots,
                             )
                             .expect("Failed to render GUI");
                     }
@@ -376,6 +804,30 @@ impl Emulator {
                 .open_file_dialog(FileDialogType::SaveState);
             self.gui.flag_save_state = false;
         }
+        if self.gui.flag_export_coverage {
+            self.dialog_handler
+                .open_file_dialog(FileDialogType::ExportCoverage);
+            self.gui.flag_export_coverage = false;
+        }
+        if self.gui.flag_export_profile {
+            self.dialog_handler
+                .open_file_dialog(FileDialogType::ExportProfile);
+            self.gui.flag_export_profile = false;
+        }
+        if let Some(slot) = self.gui.flag_save_slot.take() {
+            if let Err(msg) = self.save_slot(slot) {
+                self.gui.display_error(&msg);
+            }
+        }
+        if let Some(slot) = self.gui.flag_load_slot.take() {
+            if let Err(msg) = self.load_slot(slot) {
+                self.gui.display_error(&msg);
+            }
+        }
+        if self.gui.flag_restore_defaults {
+            self.restore_defaults();
+            self.gui.flag_restore_defaults = false;
+        }
         if self.gui.flag_reset {
             self.reset();
             self.gui.flag_reset = false;
@@ -422,55 +874,88 @@ impl Emulator {
             ];
         }
 
-        self.cpu_speed = self.gui.cpu_speed as u32;
         self.mute = self.gui.flag_mute;
         self.sound.set_volume(self.gui.volume);
 
+        let breakpoints = self.breakpoints();
+        let mut machine = self.emulation.machine();
+        machine.core.set_speed(self.gui.speed());
+        machine.core.set_time_scale(self.gui.time_scale());
+        machine.breakpoints = breakpoints;
+
         let quirks = self.gui.quirks_settings();
-        self.cpu.quirk_load_store = quirks.get(Quirk::LoadStore);
-        self.cpu.quirk_shift = quirks.get(Quirk::Shift);
-        self.cpu.quirk_draw = quirks.get(Quirk::Draw);
-        self.cpu.quirk_jump = quirks.get(Quirk::Jump);
-        self.cpu.quirk_vf_order = quirks.get(Quirk::VfOrder);
-        self.cpu.quirk_partialwrap_h = quirks.get(Quirk::PartialWrapH);
-        self.cpu.quirk_partialwrap_v = quirks.get(Quirk::PartialWrapV);
-
-        self.step = self.gui.flag_step;
+        machine.core.cpu.quirk_load_store = quirks.get(Quirk::LoadStore);
+        machine.core.cpu.quirk_shift = quirks.get(Quirk::Shift);
+        machine.core.cpu.quirk_draw = quirks.get(Quirk::Draw);
+        machine.core.cpu.quirk_jump = quirks.get(Quirk::Jump);
+        machine.core.cpu.quirk_vf_order = quirks.get(Quirk::VfOrder);
+        machine.core.cpu.quirk_partialwrap_h = quirks.get(Quirk::PartialWrapH);
+        machine.core.cpu.quirk_partialwrap_v = quirks.get(Quirk::PartialWrapV);
+
+        for (register, value) in self.gui.take_register_edits() {
+            if let Err(e) = machine.core.cpu.set_register(register, value) {
+                self.gui.display_error(&format!("Error: {}", e));
+            }
+        }
+
+        machine.profiler.enabled = self.gui.profiling();
+        if self.gui.flag_reset_profile {
+            machine.profiler.reset();
+            self.gui.flag_reset_profile = false;
+        }
+        drop(machine);
+
+        if pause != self.pause {
+            self.pause = pause;
+            self.emulation.send(Command::SetPaused(pause));
+        }
+
+        // Stepping is only possible while paused
+        if self.gui.flag_step && pause {
+            self.emulation.send(Command::Step);
+        }
         self.gui.flag_step = false;
-        self.step_timers = self.gui.flag_step_timers;
+        if self.gui.flag_step_timers && pause {
+            self.emulation.send(Command::StepTimers);
+        }
         self.gui.flag_step_timers = false;
+        if self.gui.flag_advance_frame && pause {
+            self.emulation.send(Command::AdvanceFrame);
+        }
+        self.gui.flag_advance_frame = false;
+    }
 
-        if pause != self.pause {
-            self.set_pause(pause);
+    fn handle_notifications(&mut self) {
+        while let Some(notification) = self.emulation.try_recv() {
+            match notification {
+                Notification::Error(msg) => self.gui.display_error(&msg),
+                Notification::Breakpoint => self.gui.flag_pause = true,
+                Notification::PlayBeep if !self.mute => self.sound.beep(),
+                Notification::PlayBuffer(buffer) if !self.mute => self.sound.play_buffer(buffer),
+                _ => (),
+            }
         }
     }
 
-    #[inline]
-    fn check_breakpoints(&mut self) -> bool {
-        // Check breakpoints
-        use std::u16;
-        if self.gui.flag_breakpoint_pc() {
-            if let Ok(bp) = u16::from_str_radix(self.gui.breakpoint_pc(), 16) {
-                if self.cpu.check_breakpoint(Breakpoint::PC(bp)) {
-                    return true;
+    /// Collects the breakpoints of the debug window and the remote debuggers.
+    fn breakpoints(&self) -> Vec<Breakpoint> {
+        let mut breakpoints = self.remote_debug.breakpoints().to_vec();
+        if self.gui.flag_debug {
+            if self.gui.flag_breakpoint_pc() {
+                if let Some(bp) = self.symbols.resolve(self.gui.breakpoint_pc()) {
+                    breakpoints.push(Breakpoint::PC(bp));
                 }
             }
-        }
-        if self.gui.flag_breakpoint_i() {
-            if let Ok(bp) = u16::from_str_radix(self.gui.breakpoint_i(), 16) {
-                if self.cpu.check_breakpoint(Breakpoint::I(bp)) {
-                    return true;
+            if self.gui.flag_breakpoint_i() {
+                if let Some(bp) = self.symbols.resolve(self.gui.breakpoint_i()) {
+                    breakpoints.push(Breakpoint::I(bp));
                 }
             }
+            if self.gui.flag_breakpoint_opcode() {
+                breakpoints.push(Breakpoint::Opcode(self.gui.breakpoint_opcode().to_string()));
+            }
         }
-        if self.gui.flag_breakpoint_opcode()
-            && self
-                .cpu
-                .check_breakpoint(Breakpoint::Opcode(self.gui.breakpoint_opcode().to_string()))
-        {
-            return true;
-        }
-        false
+        breakpoints
     }
 
     #[inline]
@@ -506,6 +991,9 @@ impl Emulator {
         if let Some(keycode) = virtual_keycode {
             let ctrl = self.modifiers_state.ctrl();
             let shift = self.modifiers_state.shift();
+            let slot = [F1, F2, F3, F4, F5, F6, F7, F8]
+                .iter()
+                .position(|&key| key == keycode);
             match (scancode, keycode, state, ctrl, shift) {
                 // Command keys
                 #[cfg(feature = "rom-download")]
@@ -520,6 +1008,14 @@ impl Emulator {
                         *ctrl_flow = ControlFlow::Exit;
                     }
                 }
+                // Save slots, shift saves and ctrl loads
+                (_, _, Pressed, true, false) | (_, _, Pressed, false, true) if slot.is_some() => {
+                    if shift {
+                        self.gui.flag_save_slot = slot;
+                    } else {
+                        self.gui.flag_load_slot = slot;
+                    }
+                }
                 (_, F1, Pressed, _, _) => {
                     self.gui.flag_display_fps = !self.gui.flag_display_fps;
                 }
@@ -535,12 +1031,21 @@ impl Emulator {
                 (_, F9, Pressed, _, _) => {
                     self.gui.flag_step_timers = true;
                 }
+                (_, F10, Pressed, _, _) => {
+                    self.gui.flag_advance_frame = true;
+                }
                 (_, F11, Pressed, _, _) => {
                     self.gui.flag_fullscreen = !self.gui.flag_fullscreen;
                 }
                 (_, P, Pressed, _, _) => {
                     self.gui.flag_pause = !self.gui.flag_pause;
                 }
+                (_, Tab, _, _, _) => {
+                    self.gui.flag_fast_forward = state == Pressed;
+                }
+                (_, L, Pressed, _, _) => {
+                    self.gui.flag_slow_motion = !self.gui.flag_slow_motion;
+                }
                 (_, M, Pressed, _, _) => {
                     self.gui.flag_mute = !self.gui.flag_mute;
                 }
@@ -552,38 +1057,38 @@ impl Emulator {
                 }
 
                 // Chip8 keys - using scancode instead of VirtualKeyCode to account for different keyboard layouts
-                (SCANCODE_1, _, Pressed, _, _) => self.input[1] = true,
-                (SCANCODE_1, _, Released, _, _) => self.input[1] = false,
-                (SCANCODE_2, _, Pressed, _, _) => self.input[2] = true,
-                (SCANCODE_2, _, Released, _, _) => self.input[2] = false,
-                (SCANCODE_3, _, Pressed, _, _) => self.input[3] = true,
-                (SCANCODE_3, _, Released, _, _) => self.input[3] = false,
-                (SCANCODE_4, _, Pressed, _, _) => self.input[0xC] = true,
-                (SCANCODE_4, _, Released, _, _) => self.input[0xC] = false,
-                (SCANCODE_Q, _, Pressed, _, _) => self.input[4] = true,
-                (SCANCODE_Q, _, Released, _, _) => self.input[4] = false,
-                (SCANCODE_W, _, Pressed, _, _) => self.input[5] = true,
-                (SCANCODE_W, _, Released, _, _) => self.input[5] = false,
-                (SCANCODE_E, _, Pressed, _, _) => self.input[6] = true,
-                (SCANCODE_E, _, Released, _, _) => self.input[6] = false,
-                (SCANCODE_R, _, Pressed, _, _) => self.input[0xD] = true,
-                (SCANCODE_R, _, Released, _, _) => self.input[0xD] = false,
-                (SCANCODE_A, _, Pressed, _, _) => self.input[7] = true,
-                (SCANCODE_A, _, Released, _, _) => self.input[7] = false,
-                (SCANCODE_S, _, Pressed, _, _) => self.input[8] = true,
-                (SCANCODE_S, _, Released, _, _) => self.input[8] = false,
-                (SCANCODE_D, _, Pressed, _, _) => self.input[9] = true,
-                (SCANCODE_D, _, Released, _, _) => self.input[9] = false,
-                (SCANCODE_F, _, Pressed, _, _) => self.input[0xE] = true,
-                (SCANCODE_F, _, Released, _, _) => self.input[0xE] = false,
-                (SCANCODE_Z, _, Pressed, _, _) => self.input[0xA] = true,
-                (SCANCODE_Z, _, Released, _, _) => self.input[0xA] = false,
-                (SCANCODE_X, _, Pressed, _, _) => self.input[0] = true,
-                (SCANCODE_X, _, Released, _, _) => self.input[0] = false,
-                (SCANCODE_C, _, Pressed, _, _) => self.input[0xB] = true,
-                (SCANCODE_C, _, Released, _, _) => self.input[0xB] = false,
-                (SCANCODE_V, _, Pressed, _, _) => self.input[0xF] = true,
-                (SCANCODE_V, _, Released, _, _) => self.input[0xF] = false,
+                (SCANCODE_1, _, Pressed, _, _) => self.emulation.set_key(1, true),
+                (SCANCODE_1, _, Released, _, _) => self.emulation.set_key(1, false),
+                (SCANCODE_2, _, Pressed, _, _) => self.emulation.set_key(2, true),
+                (SCANCODE_2, _, Released, _, _) => self.emulation.set_key(2, false),
+                (SCANCODE_3, _, Pressed, _, _) => self.emulation.set_key(3, true),
+                (SCANCODE_3, _, Released, _, _) => self.emulation.set_key(3, false),
+                (SCANCODE_4, _, Pressed, _, _) => self.emulation.set_key(0xC, true),
+                (SCANCODE_4, _, Released, _, _) => self.emulation.set_key(0xC, false),
+                (SCANCODE_Q, _, Pressed, _, _) => self.emulation.set_key(4, true),
+                (SCANCODE_Q, _, Released, _, _) => self.emulation.set_key(4, false),
+                (SCANCODE_W, _, Pressed, _, _) => self.emulation.set_key(5, true),
+                (SCANCODE_W, _, Released, _, _) => self.emulation.set_key(5, false),
+                (SCANCODE_E, _, Pressed, _, _) => self.emulation.set_key(6, true),
+                (SCANCODE_E, _, Released, _, _) => self.emulation.set_key(6, false),
+                (SCANCODE_R, _, Pressed, _, _) => self.emulation.set_key(0xD, true),
+                (SCANCODE_R, _, Released, _, _) => self.emulation.set_key(0xD, false),
+                (SCANCODE_A, _, Pressed, _, _) => self.emulation.set_key(7, true),
+                (SCANCODE_A, _, Released, _, _) => self.emulation.set_key(7, false),
+                (SCANCODE_S, _, Pressed, _, _) => self.emulation.set_key(8, true),
+                (SCANCODE_S, _, Released, _, _) => self.emulation.set_key(8, false),
+                (SCANCODE_D, _, Pressed, _, _) => self.emulation.set_key(9, true),
+                (SCANCODE_D, _, Released, _, _) => self.emulation.set_key(9, false),
+                (SCANCODE_F, _, Pressed, _, _) => self.emulation.set_key(0xE, true),
+                (SCANCODE_F, _, Released, _, _) => self.emulation.set_key(0xE, false),
+                (SCANCODE_Z, _, Pressed, _, _) => self.emulation.set_key(0xA, true),
+                (SCANCODE_Z, _, Released, _, _) => self.emulation.set_key(0xA, false),
+                (SCANCODE_X, _, Pressed, _, _) => self.emulation.set_key(0, true),
+                (SCANCODE_X, _, Released, _, _) => self.emulation.set_key(0, false),
+                (SCANCODE_C, _, Pressed, _, _) => self.emulation.set_key(0xB, true),
+                (SCANCODE_C, _, Released, _, _) => self.emulation.set_key(0xB, false),
+                (SCANCODE_V, _, Pressed, _, _) => self.emulation.set_key(0xF, true),
+                (SCANCODE_V, _, Released, _, _) => self.emulation.set_key(0xF, false),
 
                 _ => (),
             }
@@ -0,0 +1,587 @@
+//! The emulation loop shared by all frontends, advancing the CPU and its timers by elapsed time or by whole frames.
+
+use crate::cpu::{Error, CPU};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Source of the current time, replaceable to test the timing or to run independent of the real time.
+pub trait Clock {
+    /// Returns the time elapsed since an arbitrary but fixed point.
+    fn now(&self) -> Duration;
+}
+
+/// Clock following the real time.
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Clock which only moves forward when advanced explicitly.
+#[derive(Default)]
+pub struct ManualClock {
+    now: Cell<Duration>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+}
+
+impl<C: Clock> Clock for &C {
+    fn now(&self) -> Duration {
+        (*self).now()
+    }
+}
+
+pub const DEFAULT_SPEED: Speed = Speed::Frequency(720);
+pub const TIMER_FREQUENCY: u32 = 60;
+/// Longest time caught up at once, so the emulation doesn't race after the frontend was blocked.
+pub const MAX_CATCH_UP: Duration = Duration::from_millis(250);
+/// Longest time spent in a single uncapped update, so others locking the core aren't blocked.
+const MAX_UNCAPPED_SLICE: Duration = Duration::from_millis(5);
+/// Most frames run in a single uncapped update, ending it even if the clock doesn't advance.
+pub const MAX_UNCAPPED_FRAMES: u32 = 1000;
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+/// How fast instructions are executed.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Speed {
+    /// Instructions per second, spread evenly over time.
+    Frequency(u32),
+    /// Exactly this many instructions per 60 Hz frame, followed by the timer update.
+    /// This is how Octo and most ROM metadata specify the speed.
+    InstructionsPerFrame(u32),
+}
+
+impl Speed {
+    /// Instructions per second.
+    pub fn frequency(&self) -> u32 {
+        match *self {
+            Speed::Frequency(frequency) => frequency,
+            Speed::InstructionsPerFrame(instructions) => instructions * TIMER_FREQUENCY,
+        }
+    }
+
+    /// Instructions per 60 Hz frame, rounded down but at least one.
+    pub fn instructions_per_frame(&self) -> u32 {
+        match *self {
+            Speed::Frequency(frequency) => (frequency / TIMER_FREQUENCY).max(1),
+            Speed::InstructionsPerFrame(instructions) => instructions,
+        }
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Speed::Frequency(frequency) => write!(f, "{} Hz", frequency),
+            Speed::InstructionsPerFrame(instructions) => {
+                write!(f, "{} instructions per frame", instructions)
+            }
+        }
+    }
+}
+
+/// How fast the emulated time passes compared to the real time.
+/// Instructions and timers are scaled together, so games behave the same, only faster or slower.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TimeScale {
+    /// Percentage of the real time, e.g. 25 for slow-motion or 400 for fast-forward.
+    Percent(u32),
+    /// Whole frames as fast as possible.
+    Uncapped,
+}
+
+impl TimeScale {
+    pub const REAL_TIME: TimeScale = TimeScale::Percent(100);
+
+    pub fn is_fast_forward(&self) -> bool {
+        match *self {
+            TimeScale::Percent(percent) => percent > 100,
+            TimeScale::Uncapped => true,
+        }
+    }
+}
+
+impl fmt::Display for TimeScale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeScale::Percent(percent) => write!(f, "{}%", percent),
+            TimeScale::Uncapped => write!(f, "Uncapped"),
+        }
+    }
+}
+
+/// Callbacks of the emulation loop, e.g. for breakpoints, profiling or sound.
+pub trait Hooks {
+    /// Called before every instruction, returning false stops the run without executing it.
+    fn before_tick(&mut self, _cpu: &CPU) -> bool {
+        true
+    }
+
+    /// Called after every successfully executed instruction, returning false stops the run.
+    fn after_tick(&mut self, _cpu: &CPU) -> bool {
+        true
+    }
+
+    /// Called before the delay and sound timers are decremented.
+    fn before_timers(&mut self, _cpu: &CPU) {}
+}
+
+/// No callbacks at all.
+impl Hooks for () {}
+
+/// Owns the CPU together with its speed and input and runs it at the configured speed.
+///
+/// Elapsed time is converted into instructions and timer ticks exactly, the fractions left over
+/// are carried to the next run, so the speed doesn't drift no matter how the runs are split up.
+/// With a speed in instructions per frame, only whole frames are run.
+pub struct EmulatorCore<C: Clock = SystemClock> {
+    pub cpu: CPU,
+    pub input: [bool; 16],
+    speed: Speed,
+    time_scale: TimeScale,
+    paused: bool,
+    clock: C,
+    last_update: Duration,
+    /// Elapsed nanoseconds multiplied by the frequency which weren't enough for another cycle yet.
+    cycle_remainder: u128,
+    timer_remainder: u128,
+    /// Instructions already executed of a frame which was stopped by a hook.
+    frame_progress: u32,
+}
+
+impl EmulatorCore<SystemClock> {
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock::new())
+    }
+}
+
+impl Default for EmulatorCore<SystemClock> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Clock> EmulatorCore<C> {
+    pub fn with_clock(clock: C) -> Self {
+        let last_update = clock.now();
+        Self {
+            cpu: CPU::new(),
+            input: [false; 16],
+            speed: DEFAULT_SPEED,
+            time_scale: TimeScale::REAL_TIME,
+            paused: false,
+            clock,
+            last_update,
+            cycle_remainder: 0,
+            timer_remainder: 0,
+            frame_progress: 0,
+        }
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = match speed {
+            Speed::Frequency(frequency) => Speed::Frequency(frequency.max(1)),
+            Speed::InstructionsPerFrame(instructions) => {
+                Speed::InstructionsPerFrame(instructions.max(1))
+            }
+        };
+    }
+
+    pub fn time_scale(&self) -> TimeScale {
+        self.time_scale
+    }
+
+    /// Changes how fast the time on the clock passes for the emulation, used for fast-forward and slow-motion.
+    pub fn set_time_scale(&mut self, time_scale: TimeScale) {
+        self.time_scale = time_scale;
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Pauses or resumes, the time spent paused is skipped instead of caught up.
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.paused && !paused {
+            self.last_update = self.clock.now();
+        }
+        self.paused = paused;
+    }
+
+    /// Replaces the CPU, e.g. after loading a ROM or state, and drops pending fractions of cycles.
+    pub fn set_cpu(&mut self, cpu: CPU) {
+        self.cpu = cpu;
+        self.cycle_remainder = 0;
+        self.timer_remainder = 0;
+        self.frame_progress = 0;
+        self.last_update = self.clock.now();
+    }
+
+    /// Runs the time elapsed on the clock since the last update, at most `MAX_CATCH_UP`, scaled by the time scale.
+    /// Uncapped, whole frames are run for about the elapsed time instead.
+    pub fn update<H: Hooks>(&mut self, hooks: &mut H) -> Result<(), Error> {
+        let now = self.clock.now();
+        let elapsed = now.saturating_sub(self.last_update);
+        self.last_update = now;
+        if self.paused {
+            return Ok(());
+        }
+        match self.time_scale {
+            TimeScale::Percent(percent) => {
+                self.run_for(elapsed.min(MAX_CATCH_UP) * percent / 100, hooks)
+            }
+            TimeScale::Uncapped => self.run_uncapped(now + elapsed.min(MAX_UNCAPPED_SLICE), hooks),
+        }
+    }
+
+    /// Runs at least one frame and more until the deadline or `MAX_UNCAPPED_FRAMES` is reached.
+    fn run_uncapped<H: Hooks>(&mut self, deadline: Duration, hooks: &mut H) -> Result<(), Error> {
+        for _ in 0..MAX_UNCAPPED_FRAMES {
+            if !self.run_frame_checked(hooks)? || self.clock.now() >= deadline {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the instructions and timer ticks falling into the given duration, regardless of the clock.
+    /// The timers are updated after the instructions, as with a frame.
+    pub fn run_for<H: Hooks>(&mut self, duration: Duration, hooks: &mut H) -> Result<(), Error> {
+        let nanos = duration.as_nanos();
+        self.timer_remainder += nanos * TIMER_FREQUENCY as u128;
+        let timer_ticks = self.timer_remainder / NANOS_PER_SECOND;
+        self.timer_remainder %= NANOS_PER_SECOND;
+
+        let frequency = match self.speed {
+            Speed::Frequency(frequency) => frequency,
+            Speed::InstructionsPerFrame(_) => {
+                for frame in 0..timer_ticks {
+                    if !self.run_frame_checked(hooks)? {
+                        // Stopped by a hook, the frames left are owed to the next run
+                        self.timer_remainder += (timer_ticks - frame) * NANOS_PER_SECOND;
+                        return Ok(());
+                    }
+                }
+                return Ok(());
+            }
+        };
+        self.cycle_remainder += nanos * frequency as u128;
+        let cycles = self.cycle_remainder / NANOS_PER_SECOND;
+        self.cycle_remainder %= NANOS_PER_SECOND;
+
+        for _ in 0..cycles {
+            if !self.tick(hooks)? {
+                // Stopped by a hook, the remaining instructions are dropped but the timer ticks
+                // are owed to the next run
+                self.timer_remainder += timer_ticks * NANOS_PER_SECOND;
+                return Ok(());
+            }
+        }
+        for _ in 0..timer_ticks {
+            self.update_timers(hooks);
+        }
+        Ok(())
+    }
+
+    /// Runs the instructions of a single 60 Hz frame and updates the timers once.
+    pub fn run_frame<H: Hooks>(&mut self, hooks: &mut H) -> Result<(), Error> {
+        self.run_frame_checked(hooks).map(|_| ())
+    }
+
+    /// Runs a frame, returns false if a hook requested to stop.
+    /// A stopped frame is continued by the next one instead of starting over.
+    fn run_frame_checked<H: Hooks>(&mut self, hooks: &mut H) -> Result<bool, Error> {
+        while self.frame_progress < self.speed.instructions_per_frame() {
+            if !hooks.before_tick(&self.cpu) {
+                return Ok(false);
+            }
+            self.cpu.tick(&self.input)?;
+            self.frame_progress += 1;
+            if !hooks.after_tick(&self.cpu) {
+                return Ok(false);
+            }
+        }
+        self.frame_progress = 0;
+        self.update_timers(hooks);
+        Ok(true)
+    }
+
+    /// Executes a single instruction, independent of the pause state.
+    /// Returns false if a hook requested to stop.
+    pub fn tick<H: Hooks>(&mut self, hooks: &mut H) -> Result<bool, Error> {
+        if !hooks.before_tick(&self.cpu) {
+            return Ok(false);
+        }
+        self.cpu.tick(&self.input)?;
+        Ok(hooks.after_tick(&self.cpu))
+    }
+
+    pub fn update_timers<H: Hooks>(&mut self, hooks: &mut H) {
+        hooks.before_timers(&self.cpu);
+        self.cpu.update_timers();
+    }
+}
+
+#[cfg(test)]
+mod emulator_core_test {
+    use super::*;
+
+    /// Counts the executed instructions and timer ticks, optionally stopping after a number of instructions.
+    #[derive(Default)]
+    struct Counter {
+        cycles: u32,
+        timer_ticks: u32,
+        stop_after: Option<u32>,
+    }
+
+    impl Hooks for Counter {
+        fn after_tick(&mut self, _cpu: &CPU) -> bool {
+            self.cycles += 1;
+            self.stop_after != Some(self.cycles)
+        }
+
+        fn before_timers(&mut self, _cpu: &CPU) {
+            self.timer_ticks += 1;
+        }
+    }
+
+    fn core(clock: &ManualClock) -> EmulatorCore<&ManualClock> {
+        let mut core = EmulatorCore::with_clock(clock);
+        // Endless loop: 1200 jumps to itself
+        core.cpu.load_rom(&[0x12, 0x00]).unwrap();
+        core
+    }
+
+    #[test]
+    fn test_run_for() {
+        let clock = ManualClock::new();
+        let mut core = core(&clock);
+        let mut counter = Counter::default();
+        core.run_for(Duration::from_secs(1), &mut counter).unwrap();
+        assert_eq!(counter.cycles, 720);
+        assert_eq!(counter.timer_ticks, 60);
+
+        // Fractions are carried over instead of lost
+        let mut counter = Counter::default();
+        for _ in 0..1000 {
+            core.run_for(Duration::from_millis(1), &mut counter)
+                .unwrap();
+        }
+        assert_eq!(counter.cycles, 720);
+        assert_eq!(counter.timer_ticks, 60);
+
+        let mut counter = Counter::default();
+        core.set_speed(Speed::Frequency(1000));
+        core.run_for(Duration::from_millis(2500), &mut counter)
+            .unwrap();
+        assert_eq!(counter.cycles, 2500);
+        assert_eq!(counter.timer_ticks, 150);
+    }
+
+    #[test]
+    fn test_run_for_stopped() {
+        let clock = ManualClock::new();
+        let mut core = core(&clock);
+        let mut counter = Counter {
+            stop_after: Some(10),
+            ..Counter::default()
+        };
+        core.run_for(Duration::from_secs(1), &mut counter).unwrap();
+        assert_eq!(counter.cycles, 10);
+        assert_eq!(counter.timer_ticks, 0);
+        // The timer ticks are kept when stopped by a hook
+        counter.stop_after = None;
+        core.run_for(Duration::ZERO, &mut counter).unwrap();
+        assert_eq!(counter.timer_ticks, 60);
+
+        // Stopped in the third frame
+        let mut counter = Counter {
+            stop_after: Some(30),
+            ..Counter::default()
+        };
+        core.set_speed(Speed::InstructionsPerFrame(12));
+        core.run_for(Duration::from_secs(1), &mut counter).unwrap();
+        assert_eq!(counter.timer_ticks, 2);
+        counter.stop_after = None;
+        core.run_for(Duration::ZERO, &mut counter).unwrap();
+        assert_eq!(counter.timer_ticks, 60);
+        // The stopped frame is finished instead of run again
+        assert_eq!(counter.cycles, 60 * 12);
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let clock = ManualClock::new();
+        let mut core = core(&clock);
+        core.cpu.set_DT(10);
+        let mut counter = Counter::default();
+        core.run_frame(&mut counter).unwrap();
+        assert_eq!(counter.cycles, 12);
+        assert_eq!(counter.timer_ticks, 1);
+        assert_eq!(core.cpu.DT(), 9);
+    }
+
+    #[test]
+    fn test_update() {
+        let clock = ManualClock::new();
+        let mut core = core(&clock);
+        let mut counter = Counter::default();
+        clock.advance(Duration::from_millis(100));
+        core.update(&mut counter).unwrap();
+        assert_eq!(counter.cycles, 72);
+        assert_eq!(counter.timer_ticks, 6);
+
+        // Paused time is skipped
+        let mut counter = Counter::default();
+        core.set_paused(true);
+        clock.advance(Duration::from_secs(5));
+        core.update(&mut counter).unwrap();
+        core.set_paused(false);
+        core.update(&mut counter).unwrap();
+        assert_eq!(counter.cycles, 0);
+
+        // Long stalls are only caught up partially
+        clock.advance(Duration::from_secs(5));
+        core.update(&mut counter).unwrap();
+        assert_eq!(counter.cycles, 180);
+        assert_eq!(counter.timer_ticks, 15);
+    }
+
+    #[test]
+    fn test_time_scale() {
+        let clock = ManualClock::new();
+        let mut core = core(&clock);
+        core.set_time_scale(TimeScale::Percent(25));
+        let mut counter = Counter::default();
+        clock.advance(Duration::from_millis(200));
+        core.update(&mut counter).unwrap();
+        assert_eq!(counter.cycles, 36);
+        assert_eq!(counter.timer_ticks, 3);
+
+        core.set_time_scale(TimeScale::Percent(400));
+        let mut counter = Counter::default();
+        clock.advance(Duration::from_millis(100));
+        core.update(&mut counter).unwrap();
+        assert_eq!(counter.cycles, 288);
+        assert_eq!(counter.timer_ticks, 24);
+
+        // Uncapped, the clock doesn't advance here, so the update ends after the most frames
+        core.set_time_scale(TimeScale::Uncapped);
+        let mut counter = Counter::default();
+        clock.advance(Duration::from_millis(1));
+        core.update(&mut counter).unwrap();
+        assert_eq!(counter.cycles, 12 * MAX_UNCAPPED_FRAMES);
+        assert_eq!(counter.timer_ticks, MAX_UNCAPPED_FRAMES);
+    }
+
+    #[test]
+    fn test_hooks_stop() {
+        let clock = ManualClock::new();
+        let mut core = core(&clock);
+        let mut counter = Counter {
+            stop_after: Some(5),
+            ..Counter::default()
+        };
+        core.run_for(Duration::from_secs(1), &mut counter).unwrap();
+        assert_eq!(counter.cycles, 5);
+        assert_eq!(counter.timer_ticks, 0);
+    }
+
+    #[test]
+    fn test_instructions_per_frame() {
+        let clock = ManualClock::new();
+        let mut core = core(&clock);
+        core.set_speed(Speed::InstructionsPerFrame(15));
+        let mut counter = Counter::default();
+        core.run_frame(&mut counter).unwrap();
+        assert_eq!(counter.cycles, 15);
+        assert_eq!(counter.timer_ticks, 1);
+
+        // Only whole frames are run
+        let mut counter = Counter::default();
+        for _ in 0..10 {
+            clock.advance(Duration::from_millis(10));
+            core.update(&mut counter).unwrap();
+            assert_eq!(counter.cycles, counter.timer_ticks * 15);
+        }
+        assert_eq!(counter.timer_ticks, 6);
+
+        assert_eq!(core.speed().frequency(), 900);
+        assert_eq!(Speed::Frequency(720).instructions_per_frame(), 12);
+        assert_eq!(Speed::Frequency(30).instructions_per_frame(), 1);
+    }
+
+    #[test]
+    fn test_instructions_per_frame_stopped() {
+        let clock = ManualClock::new();
+        let mut core = core(&clock);
+        core.set_speed(Speed::InstructionsPerFrame(10));
+        let mut counter = Counter {
+            stop_after: Some(4),
+            ..Counter::default()
+        };
+        clock.advance(Duration::from_millis(50));
+        core.update(&mut counter).unwrap();
+        assert_eq!(counter.cycles, 4);
+        assert_eq!(counter.timer_ticks, 0);
+
+        // Resuming runs the rest of the stopped frame and the frames owed
+        counter.stop_after = None;
+        core.update(&mut counter).unwrap();
+        assert_eq!(counter.cycles, 30);
+        assert_eq!(counter.timer_ticks, 3);
+
+        // A single frame continues a stopped one as well
+        let mut counter = Counter {
+            stop_after: Some(7),
+            ..Counter::default()
+        };
+        core.run_frame(&mut counter).unwrap();
+        counter.stop_after = None;
+        core.run_frame(&mut counter).unwrap();
+        assert_eq!(counter.cycles, 10);
+        assert_eq!(counter.timer_ticks, 1);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::cpu::{Register, CPU};
+use crate::symbols::SymbolTable;
+
+/// Evaluates simple expressions against the CPU state, e.g. `i + v2`, `[main-loop + 2]` or `pc - 0x200`.
+///
+/// Supported are registers (`v0`-`vf`, `i`, `pc`, `sp`, `dt`, `st`), labels, numbers (decimal, `0x` or `$` hex, `0b` binary),
+/// memory bytes (`[address]`), parentheses as well as `+` and `-`. Labels may contain `-`,
+/// so operators have to be surrounded by whitespace. The result wraps around like a 16 bit register.
+pub fn evaluate(expression: &str, cpu: &CPU, symbols: &SymbolTable) -> Result<u16, String> {
+    let mut parser = Parser {
+        input: expression.trim(),
+        cpu,
+        symbols,
+    };
+    let value = parser.expression()?;
+    if parser.input.is_empty() {
+        Ok(value)
+    } else {
+        Err(format!("Unexpected input: {}", parser.input))
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    cpu: &'a CPU,
+    symbols: &'a SymbolTable,
+}
+
+impl<'a> Parser<'a> {
+    fn expression(&mut self) -> Result<u16, String> {
+        let mut value = self.atom()?;
+        loop {
+            if self.consume('+') {
+                value = value.wrapping_add(self.atom()?);
+            } else if self.consume('-') {
+                value = value.wrapping_sub(self.atom()?);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn atom(&mut self) -> Result<u16, String> {
+        if self.consume('(') {
+            let value = self.expression()?;
+            self.expect(')')?;
+            Ok(value)
+        } else if self.consume('[') {
+            let addr = self.expression()?;
+            self.expect(']')?;
+            Ok(self.cpu.mem()[addr as usize] as u16)
+        } else if self.consume('-') {
+            Ok(0u16.wrapping_sub(self.atom()?))
+        } else {
+            let end = self
+                .input
+                .find(|c: char| !(c.is_alphanumeric() || "_-.$".contains(c)))
+                .unwrap_or(self.input.len());
+            let (token, rest) = self.input.split_at(end);
+            if token.is_empty() {
+                return Err(String::from("Expected value"));
+            }
+            self.input = rest.trim_start();
+            self.value(token)
+        }
+    }
+
+    fn value(&self, token: &str) -> Result<u16, String> {
+        let lower = token.to_lowercase();
+        let register = match lower.as_str() {
+            "i" => Some(Register::I),
+            "pc" => Some(Register::PC),
+            "sp" => Some(Register::SP),
+            "dt" => Some(Register::DT),
+            "st" => Some(Register::ST),
+            reg if reg.len() == 2 && reg.starts_with('v') => {
+                usize::from_str_radix(&reg[1..], 16).ok().map(Register::V)
+            }
+            _ => None,
+        };
+
+        if let Some(register) = register {
+            Ok(self.cpu.register(register))
+        } else if let Some(addr) = self.symbols.address(token) {
+            Ok(addr)
+        } else if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('$')) {
+            u16::from_str_radix(hex, 16).map_err(|_| format!("Invalid number: {}", token))
+        } else if let Some(bin) = lower.strip_prefix("0b") {
+            u16::from_str_radix(bin, 2).map_err(|_| format!("Invalid number: {}", token))
+        } else if token.chars().all(|c| c.is_ascii_digit()) {
+            token
+                .parse()
+                .map_err(|_| format!("Invalid number: {}", token))
+        } else {
+            Err(format!("Unknown register or label: {}", token))
+        }
+    }
+
+    fn consume(&mut self, c: char) -> bool {
+        match self.input.strip_prefix(c) {
+            Some(rest) => {
+                self.input = rest.trim_start();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.consume(c) {
+            Ok(())
+        } else {
+            Err(format!("Expected '{}'", c))
+        }
+    }
+}
+
+#[cfg(test)]
+mod expression_test {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0x12, 0x34, 0x56]).unwrap();
+        cpu.set_V(0xA, 0x10);
+        cpu.set_I(0x201);
+        let symbols = SymbolTable::parse("main-loop 0x202").unwrap();
+
+        assert_eq!(evaluate("42", &cpu, &symbols), Ok(42));
+        assert_eq!(evaluate("0x2A4", &cpu, &symbols), Ok(0x2A4));
+        assert_eq!(evaluate("$ff - 0b1", &cpu, &symbols), Ok(0xFE));
+        assert_eq!(evaluate("vA + I", &cpu, &symbols), Ok(0x211));
+        assert_eq!(evaluate("pc", &cpu, &symbols), Ok(0x200));
+        assert_eq!(evaluate("main-loop", &cpu, &symbols), Ok(0x202));
+        assert_eq!(evaluate("[main-loop]", &cpu, &symbols), Ok(0x56));
+        assert_eq!(evaluate("[i] + (1 - 2)", &cpu, &symbols), Ok(0x33));
+        assert_eq!(evaluate("-1", &cpu, &symbols), Ok(0xFFFF));
+
+        assert!(evaluate("", &cpu, &symbols).is_err());
+        assert!(evaluate("nope", &cpu, &symbols).is_err());
+        assert!(evaluate("0xZZ", &cpu, &symbols).is_err());
+        assert!(evaluate("(1 + 2", &cpu, &symbols).is_err());
+        assert!(evaluate("1 2", &cpu, &symbols).is_err());
+    }
+}
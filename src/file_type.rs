@@ -0,0 +1,278 @@
+//! Detection of the type of an opened file by its content and extension.
+//!
+//! State files, Octo cartridges and ZIP archives are recognized by their magic bytes, everything
+//! else by the extension, which also hints at the platform a ROM was written for.
+
+use crate::config::{Config, Quirks};
+use crate::emulator_core::Speed;
+use crate::octo_cartridge::Cartridge;
+use crate::state_file::StateFile;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+/// Largest ROM or state file
+pub const MAX_ROM_SIZE: usize = u16::MAX as usize + 10000;
+/// Largest file opened, cartridges contain the source of the program, which is much larger than the ROM
+pub const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileType {
+    /// Raw ROM, with the platform suggested by the extension if any
+    Rom(Option<Platform>),
+    /// Octo source code
+    OctoSource,
+    Cartridge,
+    State,
+    Zip,
+}
+
+/// Platform suggested by the extension of a ROM, used unless the ROM database or Octo options
+/// say otherwise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Platform {
+    SuperChip,
+    XoChip,
+    /// Programs written in Octo, which runs them with its own quirks
+    Octo,
+}
+
+impl Platform {
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::SuperChip => Quirks::default(),
+            Platform::XoChip | Platform::Octo => Quirks::octo(),
+        }
+    }
+
+    pub fn speed(self) -> Speed {
+        match self {
+            Platform::SuperChip => Speed::InstructionsPerFrame(30),
+            Platform::XoChip => Speed::InstructionsPerFrame(1000),
+            // Default tickrate of Octo
+            Platform::Octo => Speed::InstructionsPerFrame(20),
+        }
+    }
+
+    /// Uses the quirks and speed of the platform.
+    pub fn apply(self, config: &mut Config) {
+        config.quirks = self.quirks();
+        config.set_speed(self.speed());
+    }
+}
+
+impl FileType {
+    pub fn detect(path: &Path, data: &[u8]) -> Result<Self, String> {
+        if data.is_empty() {
+            return Err(String::from("The file is empty!"));
+        }
+        if StateFile::is_state(data) {
+            return Ok(FileType::State);
+        }
+        if Cartridge::is_cartridge(data) {
+            return Ok(FileType::Cartridge);
+        }
+        if data.starts_with(ZIP_MAGIC) {
+            return Ok(FileType::Zip);
+        }
+
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            None | Some("ch8") | Some("c8") | Some("chip8") | Some("bin") | Some("rom") => {
+                Ok(FileType::Rom(None))
+            }
+            Some("sc8") => Ok(FileType::Rom(Some(Platform::SuperChip))),
+            Some("xo8") => Ok(FileType::Rom(Some(Platform::XoChip))),
+            Some("8o") => Ok(FileType::OctoSource),
+            Some("p8s") => Err(String::from("Invalid state file!")),
+            Some("gif") => Err(String::from("The image is not an Octo cartridge!")),
+            Some("zip") => Err(String::from("Invalid ZIP archive!")),
+            Some("c8x") => Err(String::from("CHIP-8X ROMs are not supported!")),
+            Some("mc8") => Err(String::from("MEGA-CHIP ROMs are not supported!")),
+            Some(extension) => Err(format!("Unsupported file type: .{}", extension)),
+        }
+    }
+}
+
+/// Reads a file and detects its type, the first supported file of a ZIP archive is extracted.
+/// The path of an extracted file is next to the archive, where its options and symbols are looked up.
+pub fn read(path: &Path) -> Result<(FileType, PathBuf, Vec<u8>), String> {
+    let metadata = fs::metadata(path).map_err(|err| format!("Error: {}", err))?;
+    if metadata.len() > MAX_FILE_SIZE {
+        return Err(String::from("File is too big!"));
+    }
+    let file = fs::read(path).map_err(|err| format!("Error: {}", err))?;
+    let mut path = path.to_path_buf();
+    let mut file_type = FileType::detect(&path, &file)?;
+    let file = if file_type == FileType::Zip {
+        let (name, file) = extract_zip(&file, MAX_FILE_SIZE)?;
+        path.set_file_name(Path::new(&name).file_name().unwrap_or_default());
+        file_type = FileType::detect(&path, &file)?;
+        file
+    } else {
+        file
+    };
+    match file_type {
+        FileType::Rom(_) | FileType::State if file.len() > MAX_ROM_SIZE => {
+            Err(String::from("File is too big!"))
+        }
+        FileType::Zip => Err(String::from("Nested ZIP archives are not supported!")),
+        _ => Ok((file_type, path, file)),
+    }
+}
+
+/// Extracts the first supported file from a ZIP archive, returning its name and content.
+/// Files larger than `max_size` are skipped.
+pub fn extract_zip(data: &[u8], max_size: u64) -> Result<(String, Vec<u8>), String> {
+    let mut archive =
+        ZipArchive::new(Cursor::new(data)).map_err(|e| format!("Invalid ZIP archive: {}", e))?;
+    for i in 0..archive.len() {
+        let file = archive
+            .by_index(i)
+            .map_err(|e| format!("Invalid ZIP archive: {}", e))?;
+        // Files without an extension like LICENSE would be detected as ROMs
+        let name = file.name().to_string();
+        if !file.is_file() || file.size() > max_size || Path::new(&name).extension().is_none() {
+            continue;
+        }
+        // The size stored in the archive can't be trusted, so the content is limited while reading
+        let mut content = Vec::new();
+        file.take(max_size + 1)
+            .read_to_end(&mut content)
+            .map_err(|e| format!("Failed to extract {}: {}", name, e))?;
+        if content.len() as u64 > max_size {
+            continue;
+        }
+        match FileType::detect(Path::new(&name), &content) {
+            Ok(FileType::Zip) | Err(_) => (),
+            Ok(_) => return Ok((name, content)),
+        }
+    }
+    Err(String::from("The ZIP archive contains no supported file!"))
+}
+
+#[cfg(test)]
+mod file_type_test {
+    use super::*;
+    use std::io::Write;
+    use zip::{write::FileOptions, ZipWriter};
+
+    fn detect(name: &str, data: &[u8]) -> Result<FileType, String> {
+        FileType::detect(Path::new(name), data)
+    }
+
+    #[test]
+    fn test_detect() {
+        let rom = [0x12, 0x00];
+        assert_eq!(detect("game.ch8", &rom), Ok(FileType::Rom(None)));
+        assert_eq!(detect("GAME.C8", &rom), Ok(FileType::Rom(None)));
+        assert_eq!(detect("game", &rom), Ok(FileType::Rom(None)));
+        assert_eq!(
+            detect("game.sc8", &rom),
+            Ok(FileType::Rom(Some(Platform::SuperChip)))
+        );
+        assert_eq!(
+            detect("game.xo8", &rom),
+            Ok(FileType::Rom(Some(Platform::XoChip)))
+        );
+        assert_eq!(detect("game.8o", b": main"), Ok(FileType::OctoSource));
+        // Content takes precedence over the extension
+        assert_eq!(detect("game.ch8", b"p8s\x00"), Ok(FileType::State));
+        assert_eq!(detect("game.gif", b"GIF89a"), Ok(FileType::Cartridge));
+        assert_eq!(detect("game.zip", ZIP_MAGIC), Ok(FileType::Zip));
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert!(detect("game.ch8", b"").is_err());
+        assert!(detect("game.p8s", b"ch").is_err());
+        assert!(detect("game.gif", b"\x89PNG").is_err());
+        assert!(detect("game.mc8", &[0x12, 0x00]).is_err());
+        assert!(detect("game.c8x", &[0x12, 0x00]).is_err());
+        assert_eq!(
+            detect("notes.txt", b"text"),
+            Err(String::from("Unsupported file type: .txt"))
+        );
+    }
+
+    #[test]
+    fn test_extract_zip() {
+        let mut data = Vec::new();
+        {
+            let mut zip = ZipWriter::new(Cursor::new(&mut data));
+            zip.start_file("LICENSE", FileOptions::default()).unwrap();
+            zip.write_all(b"License").unwrap();
+            zip.start_file("docs/README", FileOptions::default())
+                .unwrap();
+            zip.write_all(b"Readme").unwrap();
+            zip.start_file("readme.txt", FileOptions::default())
+                .unwrap();
+            zip.write_all(b"Readme").unwrap();
+            zip.start_file("big.ch8", FileOptions::default()).unwrap();
+            zip.write_all(&[0; 64]).unwrap();
+            zip.start_file("game.xo8", FileOptions::default()).unwrap();
+            zip.write_all(&[0x12, 0x00]).unwrap();
+            zip.finish().unwrap();
+        }
+        assert_eq!(detect("roms.zip", &data), Ok(FileType::Zip));
+        assert_eq!(
+            extract_zip(&data, 16),
+            Ok((String::from("game.xo8"), vec![0x12, 0x00]))
+        );
+        assert!(extract_zip(&data, 1).is_err());
+        assert!(extract_zip(ZIP_MAGIC, 16).is_err());
+    }
+
+    #[test]
+    fn test_extract_zip_size() {
+        let mut data = Vec::new();
+        {
+            let mut zip = ZipWriter::new(Cursor::new(&mut data));
+            zip.start_file("big.ch8", FileOptions::default()).unwrap();
+            zip.write_all(&[0; 64]).unwrap();
+            zip.finish().unwrap();
+        }
+        assert!(extract_zip(&data, 64).is_ok());
+
+        // Claim a smaller size in the local and central headers
+        let central = data
+            .windows(4)
+            .position(|magic| magic == b"PK\x01\x02")
+            .unwrap();
+        data[22..26].copy_from_slice(&1u32.to_le_bytes());
+        data[central + 24..central + 28].copy_from_slice(&1u32.to_le_bytes());
+        assert!(extract_zip(&data, 16).is_err());
+    }
+
+    #[test]
+    fn test_read() {
+        let dir = std::env::temp_dir().join(format!("pich8-file-type-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut data = Vec::new();
+        {
+            let mut zip = ZipWriter::new(Cursor::new(&mut data));
+            zip.start_file("roms/game.sc8", FileOptions::default())
+                .unwrap();
+            zip.write_all(&[0x12, 0x00]).unwrap();
+            zip.finish().unwrap();
+        }
+        fs::write(dir.join("roms.zip"), &data).unwrap();
+        assert_eq!(
+            read(&dir.join("roms.zip")),
+            Ok((
+                FileType::Rom(Some(Platform::SuperChip)),
+                dir.join("game.sc8"),
+                vec![0x12, 0x00]
+            ))
+        );
+
+        fs::write(dir.join("big.ch8"), vec![0; MAX_ROM_SIZE + 1]).unwrap();
+        assert!(read(&dir.join("big.ch8")).is_err());
+        assert!(read(&dir.join("missing.ch8")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,539 @@
+use crate::cpu::{Breakpoint, Register};
+use crate::remote_debug::{DebugRequest, DebugResponse, RemoteRequest, StopReason};
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+enum Packet {
+    Command(String),
+    Interrupt,
+}
+
+/// Implements the GDB remote serial protocol on a localhost TCP port.
+/// Connections are handled one after another in a separate thread, every request is forwarded to the emulator.
+///
+/// Registers are numbered V0-VF (0-15), I (16), PC (17), SP (18), DT (19) and ST (20),
+/// 16 bit registers are transferred little endian, the byte order GDB falls back to for a target
+/// description without architecture.
+pub struct GdbStub {
+    stream: TcpStream,
+    requests: Sender<RemoteRequest>,
+    buffer: Vec<u8>,
+    /// Commands received while the emulation was running, handled once it stops
+    queued: VecDeque<String>,
+    no_ack: bool,
+    error_messages: bool,
+}
+
+impl GdbStub {
+    const REGISTERS: [(Register, &'static str, usize); 21] = [
+        (Register::V(0x0), "v0", 8),
+        (Register::V(0x1), "v1", 8),
+        (Register::V(0x2), "v2", 8),
+        (Register::V(0x3), "v3", 8),
+        (Register::V(0x4), "v4", 8),
+        (Register::V(0x5), "v5", 8),
+        (Register::V(0x6), "v6", 8),
+        (Register::V(0x7), "v7", 8),
+        (Register::V(0x8), "v8", 8),
+        (Register::V(0x9), "v9", 8),
+        (Register::V(0xA), "va", 8),
+        (Register::V(0xB), "vb", 8),
+        (Register::V(0xC), "vc", 8),
+        (Register::V(0xD), "vd", 8),
+        (Register::V(0xE), "ve", 8),
+        (Register::V(0xF), "vf", 8),
+        (Register::I, "i", 16),
+        (Register::PC, "pc", 16),
+        (Register::SP, "sp", 8),
+        (Register::DT, "dt", 8),
+        (Register::ST, "st", 8),
+    ];
+    const SIGINT: &'static str = "S02";
+    const SIGTRAP: &'static str = "S05";
+    const ERROR: &'static str = "E01";
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// Starts listening on the given port.
+    pub fn start(port: u16, requests: Sender<RemoteRequest>) -> Result<(), String> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .map_err(|e| format!("Failed to listen on port {}: {}", port, e))?;
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Ok(mut stub) = GdbStub::new(stream, requests.clone()) {
+                    // The connection is closed on errors, the debugger can simply reconnect
+                    let _ = stub.run();
+                    let _ = stub.request(DebugRequest::Detach);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn new(stream: TcpStream, requests: Sender<RemoteRequest>) -> io::Result<Self> {
+        stream.set_read_timeout(Some(Self::POLL_INTERVAL))?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            requests,
+            buffer: Vec::new(),
+            queued: VecDeque::new(),
+            no_ack: false,
+            error_messages: false,
+        })
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        // The debugger expects a stopped target when attaching
+        self.request(DebugRequest::Halt)?;
+        loop {
+            let packet = match self.queued.pop_front() {
+                Some(command) => Some(Packet::Command(command)),
+                None => self.next_packet()?,
+            };
+            let reply = match packet {
+                Some(Packet::Command(command)) => match self.handle_command(&command)? {
+                    Some(reply) => reply,
+                    None => return Ok(()),
+                },
+                Some(Packet::Interrupt) => {
+                    self.request(DebugRequest::Halt)?;
+                    Self::stop_reply(StopReason::Halt)
+                }
+                None => continue,
+            };
+            self.send_packet(&reply)?;
+        }
+    }
+
+    /// Handles a command and returns the reply, `None` ends the session.
+    fn handle_command(&mut self, command: &str) -> io::Result<Option<String>> {
+        if command.is_empty() || !command.is_char_boundary(1) {
+            return Ok(Some(String::new()));
+        }
+        let (cmd, args) = command.split_at(1);
+        let reply = match cmd {
+            "?" => {
+                self.request(DebugRequest::Halt)?;
+                Self::SIGTRAP.to_string()
+            }
+            "g" => {
+                let registers = Self::REGISTERS.iter().map(|(r, _, _)| *r).collect();
+                match self.request(DebugRequest::ReadRegisters(registers))? {
+                    DebugResponse::Registers(values) => Self::REGISTERS
+                        .iter()
+                        .zip(values)
+                        .map(|((_, _, bits), value)| Self::encode_register(value, *bits))
+                        .collect(),
+                    response => self.error_reply(response),
+                }
+            }
+            "G" => match Self::decode_registers(args) {
+                Some(values) => self.ok_reply(self.request(DebugRequest::WriteRegisters(values))?),
+                None => Self::ERROR.to_string(),
+            },
+            "p" => match Self::register_at(args) {
+                Some((register, bits)) => {
+                    match self.request(DebugRequest::ReadRegisters(vec![register]))? {
+                        DebugResponse::Registers(values) => Self::encode_register(values[0], bits),
+                        response => self.error_reply(response),
+                    }
+                }
+                None => Self::ERROR.to_string(),
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let register = parts.next().and_then(Self::register_at);
+                let value = register
+                    .zip(parts.next())
+                    .and_then(|((register, bits), value)| {
+                        Some((register, Self::decode_register(value, bits)?))
+                    });
+                match value {
+                    Some((register, value)) => {
+                        self.ok_reply(self.request(DebugRequest::WriteRegister(register, value))?)
+                    }
+                    None => Self::ERROR.to_string(),
+                }
+            }
+            "m" => match Self::parse_address_length(args) {
+                Some((addr, len)) => match self.request(DebugRequest::ReadMemory(addr, len))? {
+                    DebugResponse::Memory(data) => Self::encode_hex(&data),
+                    response => self.error_reply(response),
+                },
+                None => Self::ERROR.to_string(),
+            },
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                let target = parts.next().and_then(Self::parse_address_length);
+                let data = parts.next().and_then(Self::decode_hex);
+                match (target, data) {
+                    (Some((addr, len)), Some(data)) if data.len() == len => {
+                        self.ok_reply(self.request(DebugRequest::WriteMemory(addr, data))?)
+                    }
+                    _ => Self::ERROR.to_string(),
+                }
+            }
+            "Z" | "z" => match Self::parse_breakpoint(args) {
+                Some(addr) => {
+                    let request = if cmd == "Z" {
+                        DebugRequest::SetBreakpoint(Breakpoint::PC(addr))
+                    } else {
+                        DebugRequest::RemoveBreakpoint(Breakpoint::PC(addr))
+                    };
+                    self.ok_reply(self.request(request)?)
+                }
+                None => String::new(),
+            },
+            "s" => self.resume(DebugRequest::Step)?,
+            "c" => self.resume(DebugRequest::Continue)?,
+            "D" => {
+                self.request(DebugRequest::Detach)?;
+                self.send_packet("OK")?;
+                return Ok(None);
+            }
+            "k" => {
+                self.request(DebugRequest::Detach)?;
+                return Ok(None);
+            }
+            "H" | "T" => String::from("OK"),
+            "q" | "Q" => self.handle_query(command),
+            // Empty reply for unsupported commands, e.g. vCont
+            _ => String::new(),
+        };
+        Ok(Some(reply))
+    }
+
+    fn handle_query(&mut self, query: &str) -> String {
+        if query.starts_with("qSupported") {
+            self.error_messages = query.contains("error-message+");
+            String::from("PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;error-message+")
+        } else if query == "QStartNoAckMode" {
+            // Acknowledgement of this packet is still expected
+            self.no_ack = true;
+            String::from("OK")
+        } else if let Some(range) = query.strip_prefix("qXfer:features:read:target.xml:") {
+            let xml = Self::target_xml();
+            let mut parts = range.splitn(2, ',');
+            let offset = parts.next().and_then(|v| usize::from_str_radix(v, 16).ok());
+            let len = parts.next().and_then(|v| usize::from_str_radix(v, 16).ok());
+            match (offset, len) {
+                (Some(offset), Some(len)) if offset <= xml.len() => {
+                    let end = (offset + len).min(xml.len());
+                    let prefix = if end == xml.len() { "l" } else { "m" };
+                    format!("{}{}", prefix, &xml[offset..end])
+                }
+                _ => Self::ERROR.to_string(),
+            }
+        } else if query == "qAttached" {
+            String::from("1")
+        } else if query == "qC" {
+            String::from("QC1")
+        } else if query == "qfThreadInfo" {
+            String::from("m1")
+        } else if query == "qsThreadInfo" {
+            String::from("l")
+        } else {
+            String::new()
+        }
+    }
+
+    /// Steps or continues the emulation and waits until it stops again.
+    fn resume(&mut self, request: DebugRequest) -> io::Result<String> {
+        let (response_tx, response_rx) = channel();
+        self.send_request(request, response_tx)?;
+        loop {
+            match response_rx.recv_timeout(Self::POLL_INTERVAL) {
+                Ok(DebugResponse::Stopped(reason)) => return Ok(Self::stop_reply(reason)),
+                Ok(response) => return Ok(self.error_reply(response)),
+                Err(RecvTimeoutError::Timeout) => match self.next_packet()? {
+                    Some(Packet::Interrupt) => {
+                        self.request(DebugRequest::Halt)?;
+                    }
+                    Some(Packet::Command(command)) => self.queued.push_back(command),
+                    None => (),
+                },
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(ErrorKind::BrokenPipe, "Emulator stopped"))
+                }
+            }
+        }
+    }
+
+    fn request(&self, request: DebugRequest) -> io::Result<DebugResponse> {
+        let (response_tx, response_rx) = channel();
+        self.send_request(request, response_tx)?;
+        response_rx
+            .recv()
+            .map_err(|_| io::Error::new(ErrorKind::BrokenPipe, "Emulator stopped"))
+    }
+
+    fn send_request(
+        &self,
+        request: DebugRequest,
+        response_tx: Sender<DebugResponse>,
+    ) -> io::Result<()> {
+        self.requests
+            .send((request, response_tx))
+            .map_err(|_| io::Error::new(ErrorKind::BrokenPipe, "Emulator stopped"))
+    }
+
+    /// Reads the next packet, returns `None` if nothing was received in time.
+    fn next_packet(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            if let Some(packet) = self.parse_buffer()? {
+                return Ok(Some(packet));
+            }
+            let mut chunk = [0; 1024];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(len) => self.buffer.extend_from_slice(&chunk[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn parse_buffer(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            match Self::take_packet(&mut self.buffer) {
+                Some(Ok(packet)) => {
+                    if let Packet::Command(_) = packet {
+                        if !self.no_ack {
+                            self.stream.write_all(b"+")?;
+                        }
+                    }
+                    return Ok(Some(packet));
+                }
+                Some(Err(())) => self.stream.write_all(b"-")?,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Removes the first complete packet from the buffer.
+    /// Returns an error for packets with invalid checksum, which have to be resent.
+    fn take_packet(buffer: &mut Vec<u8>) -> Option<Result<Packet, ()>> {
+        loop {
+            match buffer.first()? {
+                0x03 => {
+                    buffer.remove(0);
+                    return Some(Ok(Packet::Interrupt));
+                }
+                b'$' => {
+                    let end = buffer.iter().position(|b| *b == b'#')?;
+                    if buffer.len() < end + 3 {
+                        return None;
+                    }
+                    let packet: Vec<u8> = buffer.drain(..end + 3).collect();
+                    let data = &packet[1..end];
+                    let checksum = std::str::from_utf8(&packet[end + 1..])
+                        .ok()
+                        .and_then(|c| u8::from_str_radix(c, 16).ok());
+                    return if checksum == Some(Self::checksum(data)) {
+                        Some(Ok(Packet::Command(
+                            String::from_utf8_lossy(data).into_owned(),
+                        )))
+                    } else {
+                        Some(Err(()))
+                    };
+                }
+                // Acknowledgements and noise
+                _ => {
+                    buffer.remove(0);
+                }
+            }
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        self.stream.write_all(Self::encode_packet(data).as_bytes())
+    }
+
+    fn encode_packet(data: &str) -> String {
+        format!("${}#{:02x}", data, Self::checksum(data.as_bytes()))
+    }
+
+    fn checksum(data: &[u8]) -> u8 {
+        data.iter().fold(0, |sum, b| sum.wrapping_add(*b))
+    }
+
+    fn register_at(num: &str) -> Option<(Register, usize)> {
+        let idx = usize::from_str_radix(num, 16).ok()?;
+        Self::REGISTERS
+            .get(idx)
+            .map(|(register, _, bits)| (*register, *bits))
+    }
+
+    /// Decodes the values of all registers as sent with `G`.
+    fn decode_registers(hex: &str) -> Option<Vec<(Register, u16)>> {
+        let mut offset = 0;
+        let values: Option<Vec<_>> = Self::REGISTERS
+            .iter()
+            .map(|(register, _, bits)| {
+                let len = bits / 4;
+                let value = Self::decode_register(hex.get(offset..offset + len)?, *bits)?;
+                offset += len;
+                Some((*register, value))
+            })
+            .collect();
+        values.filter(|_| offset == hex.len())
+    }
+
+    fn encode_register(value: u16, bits: usize) -> String {
+        Self::encode_hex(&value.to_le_bytes()[..bits / 8])
+    }
+
+    fn decode_register(hex: &str, bits: usize) -> Option<u16> {
+        match Self::decode_hex(hex)?.as_slice() {
+            [value] if bits == 8 => Some(*value as u16),
+            [low, high] if bits == 16 => Some(u16::from_le_bytes([*low, *high])),
+            _ => None,
+        }
+    }
+
+    fn encode_hex(data: &[u8]) -> String {
+        data.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+        hex.as_bytes()
+            .chunks(2)
+            .map(|byte| match byte {
+                [high, low] => {
+                    u8::from_str_radix(std::str::from_utf8(&[*high, *low]).ok()?, 16).ok()
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn parse_address_length(args: &str) -> Option<(u16, usize)> {
+        let mut parts = args.splitn(2, ',');
+        let addr = u16::from_str_radix(parts.next()?, 16).ok()?;
+        let len = usize::from_str_radix(parts.next()?, 16).ok()?;
+        Some((addr, len))
+    }
+
+    /// Parses `type,addr,kind` of software (0) and hardware (1) breakpoints.
+    fn parse_breakpoint(args: &str) -> Option<u16> {
+        let mut parts = args.split(',');
+        match parts.next()? {
+            "0" | "1" => u16::from_str_radix(parts.next()?, 16).ok(),
+            _ => None,
+        }
+    }
+
+    fn ok_reply(&self, response: DebugResponse) -> String {
+        match response {
+            DebugResponse::Ok => String::from("OK"),
+            response => self.error_reply(response),
+        }
+    }
+
+    /// Replies with the error message if the debugger supports it.
+    fn error_reply(&self, response: DebugResponse) -> String {
+        match response {
+            DebugResponse::Error(msg) if self.error_messages => format!("E.{}", msg),
+            _ => Self::ERROR.to_string(),
+        }
+    }
+
+    fn stop_reply(reason: StopReason) -> String {
+        match reason {
+            StopReason::Halt => Self::SIGINT.to_string(),
+            StopReason::Breakpoint | StopReason::Step => Self::SIGTRAP.to_string(),
+        }
+    }
+
+    fn target_xml() -> String {
+        let registers: String = Self::REGISTERS
+            .iter()
+            .map(|(register, name, bits)| {
+                let reg_type = match register {
+                    Register::PC => "code_ptr",
+                    Register::I => "data_ptr",
+                    _ => "int",
+                };
+                format!(
+                    "<reg name=\"{}\" bitsize=\"{}\" type=\"{}\"/>",
+                    name, bits, reg_type
+                )
+            })
+            .collect();
+        format!(
+            "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+             <target version=\"1.0\"><feature name=\"org.pich8.chip8\">{}</feature></target>",
+            registers
+        )
+    }
+}
+
+#[cfg(test)]
+mod gdb_stub_test {
+    use super::*;
+
+    #[test]
+    fn test_packets() {
+        assert_eq!(GdbStub::encode_packet("OK"), "$OK#9a");
+        assert_eq!(GdbStub::encode_packet(""), "$#00");
+
+        let mut buffer = b"+$m200,2#5d\x03$g#00$?#3f".to_vec();
+        match GdbStub::take_packet(&mut buffer) {
+            Some(Ok(Packet::Command(command))) => assert_eq!(command, "m200,2"),
+            _ => panic!("Expected command"),
+        }
+        assert!(matches!(
+            GdbStub::take_packet(&mut buffer),
+            Some(Ok(Packet::Interrupt))
+        ));
+        // Invalid checksum
+        assert!(matches!(GdbStub::take_packet(&mut buffer), Some(Err(()))));
+        assert!(matches!(
+            GdbStub::take_packet(&mut buffer),
+            Some(Ok(Packet::Command(_)))
+        ));
+        assert!(GdbStub::take_packet(&mut buffer).is_none());
+
+        // Incomplete packets stay in the buffer
+        let mut buffer = b"$g#6".to_vec();
+        assert!(GdbStub::take_packet(&mut buffer).is_none());
+        buffer.push(b'7');
+        assert!(matches!(
+            GdbStub::take_packet(&mut buffer),
+            Some(Ok(Packet::Command(_)))
+        ));
+    }
+
+    #[test]
+    fn test_parse_arguments() {
+        assert_eq!(GdbStub::parse_address_length("2a0,10"), Some((0x2A0, 16)));
+        assert_eq!(GdbStub::parse_address_length("2a0"), None);
+        assert_eq!(GdbStub::parse_breakpoint("0,2a4,2"), Some(0x2A4));
+        assert_eq!(GdbStub::parse_breakpoint("2,2a4,2"), None);
+        assert_eq!(GdbStub::decode_hex("00e0a2"), Some(vec![0x00, 0xE0, 0xA2]));
+        assert_eq!(GdbStub::decode_hex("0e0"), None);
+        assert_eq!(GdbStub::encode_hex(&[0x12, 0xAB]), "12ab");
+        assert_eq!(GdbStub::register_at("11"), Some((Register::PC, 16)));
+        assert_eq!(GdbStub::register_at("15"), None);
+        assert_eq!(GdbStub::encode_register(0x2A4, 16), "a402");
+        assert_eq!(GdbStub::encode_register(0x0F, 8), "0f");
+        assert_eq!(GdbStub::decode_register("0002", 16), Some(0x200));
+        assert_eq!(GdbStub::decode_register("0f", 8), Some(0x0F));
+        assert_eq!(GdbStub::decode_register("0f", 16), None);
+
+        let registers = format!("{}{}{}{}", "01".repeat(16), "0003", "0202", "000000");
+        let values = GdbStub::decode_registers(&registers).unwrap();
+        assert_eq!(values.len(), 21);
+        assert_eq!(values[0], (Register::V(0), 0x01));
+        assert_eq!(values[16], (Register::I, 0x300));
+        assert_eq!(values[17], (Register::PC, 0x202));
+        assert_eq!(GdbStub::decode_registers(&registers[1..]), None);
+        assert_eq!(GdbStub::decode_registers(&registers[..40]), None);
+    }
+}
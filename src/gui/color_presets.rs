@@ -61,7 +61,8 @@ impl<'a> ColorPresetHandler<'a> {
     pub fn is_active(&self, preset: ColorPreset) -> bool {
         for (v1, v2) in self.settings.iter().zip(self.get_preset(preset).iter()) {
             for (f1, f2) in v1.iter().zip(v2.iter()) {
-                if (f1 - f2).abs() > f32::EPSILON {
+                // Colors from the config file are quantized to 8 bits
+                if (f1 - f2).abs() > 0.5 / 255.0 {
                     return false;
                 }
             }
@@ -1,5 +1,6 @@
 use std::slice::{Iter, IterMut};
 
+#[derive(Copy, Clone)]
 pub enum Color {
     Background,
     Plane1,
@@ -0,0 +1,115 @@
+use crate::cpu::{Access, Coverage, CPU};
+use imgui::{ChildWindow, ListClipper, Ui};
+
+/// Hex view of the whole memory, optionally colored by how each byte was accessed.
+pub struct MemoryView {
+    address_input: String,
+    scroll_to: Option<usize>,
+    coverage_overlay: bool,
+}
+
+impl MemoryView {
+    const BYTES_PER_ROW: usize = 16;
+    const WIDTH_ADDRESS: f32 = 45.0;
+    const WIDTH_BYTE: f32 = 22.0;
+    const WIDTH_TEXTBOX_ADDRESS: f32 = 40.0;
+    const COLOR_UNTOUCHED: [f32; 4] = [1.0, 1.0, 1.0, 0.5];
+    const COLOR_ACCESS: [(Access, &'static str, [f32; 4]); 4] = [
+        (Access::Code, "Code", [0.4, 0.9, 0.4, 1.0]),
+        (Access::Sprite, "Sprite", [0.4, 0.7, 1.0, 1.0]),
+        (Access::Data, "Data", [1.0, 0.85, 0.3, 1.0]),
+        (Access::Write, "Written", [1.0, 0.45, 0.45, 1.0]),
+    ];
+
+    pub fn new() -> Self {
+        Self {
+            address_input: String::from("0200"),
+            scroll_to: Some(0x200),
+            coverage_overlay: true,
+        }
+    }
+
+    /// Returns true if the coverage should be exported.
+    pub fn build(&mut self, ui: &Ui, cpu: &CPU) -> bool {
+        let item_width = ui.push_item_width(Self::WIDTH_TEXTBOX_ADDRESS);
+        if ui
+            .input_text("Go to", &mut self.address_input)
+            .chars_hexadecimal(true)
+            .chars_uppercase(true)
+            .enter_returns_true(true)
+            .build()
+        {
+            if let Ok(address) = u16::from_str_radix(&self.address_input, 16) {
+                self.scroll_to = Some(address as usize);
+            }
+        }
+        item_width.pop(ui);
+        ui.same_line();
+        if ui.small_button("PC") {
+            self.scroll_to = Some(cpu.PC() as usize);
+        }
+        ui.same_line();
+        if ui.small_button("I") {
+            self.scroll_to = Some(cpu.I() as usize);
+        }
+
+        ui.checkbox("Coverage", &mut self.coverage_overlay);
+        ui.same_line();
+        let export = ui.small_button("Export Coverage...");
+        if self.coverage_overlay {
+            for (_, name, color) in &Self::COLOR_ACCESS {
+                ui.same_line();
+                ui.text_colored(*color, name);
+            }
+        }
+        ui.separator();
+
+        let mem = cpu.mem();
+        let coverage = cpu.coverage();
+        let scroll_to = self.scroll_to.take();
+        let coverage_overlay = self.coverage_overlay;
+        ChildWindow::new("##memory_rows").build(ui, || {
+            let line_height = ui.text_line_height_with_spacing();
+            if let Some(address) = scroll_to {
+                ui.set_scroll_y((address / Self::BYTES_PER_ROW) as f32 * line_height);
+            }
+
+            let rows = mem.len() / Self::BYTES_PER_ROW;
+            let mut clipper = ListClipper::new(rows as i32)
+                .items_height(line_height)
+                .begin(ui);
+            while clipper.step() {
+                for row in clipper.display_start()..clipper.display_end() {
+                    let start = row as usize * Self::BYTES_PER_ROW;
+                    ui.text(format!("{:04X}", start));
+                    for (col, addr) in (start..start + Self::BYTES_PER_ROW).enumerate() {
+                        ui.same_line_with_pos(Self::WIDTH_ADDRESS + col as f32 * Self::WIDTH_BYTE);
+                        let text = format!("{:02X}", mem[addr]);
+                        if coverage_overlay {
+                            let flags = coverage.get(addr as u16);
+                            ui.text_colored(Self::color(flags), text);
+                            if ui.is_item_hovered() {
+                                ui.tooltip_text(format!(
+                                    "{:04X}: {}",
+                                    addr,
+                                    Coverage::format_flags(flags)
+                                ));
+                            }
+                        } else {
+                            ui.text(text);
+                        }
+                    }
+                }
+            }
+        });
+
+        export
+    }
+
+    fn color(flags: u8) -> [f32; 4] {
+        Self::COLOR_ACCESS
+            .iter()
+            .find(|(access, _, _)| flags & *access as u8 > 0)
+            .map_or(Self::COLOR_UNTOUCHED, |(_, _, color)| *color)
+    }
+}
@@ -1,4 +1,10 @@
-use crate::cpu::CPU;
+use crate::config::{self, Config};
+use crate::cpu::{Register, CPU};
+use crate::emulator_core::{Speed, TimeScale};
+use crate::profiler::Profiler;
+use crate::rom_database::{self, RomInfo};
+use crate::save_slots::{SaveSlots, SLOT_COUNT};
+use crate::symbols::SymbolTable;
 use color_presets::{ColorPreset, ColorPresetHandler};
 pub use color_settings::Color;
 use color_settings::ColorSettings;
@@ -9,15 +15,25 @@ use imgui::{
 };
 use imgui_glium_renderer::Renderer;
 use imgui_winit_support::{HiDpiMode, WinitPlatform};
+use memory_view::MemoryView;
+use profiler_view::{ProfilerRequest, ProfilerView};
 use quirks_presets::{QuirksPreset, QuirksPresetHandler};
 pub use quirks_settings::Quirk;
 use quirks_settings::QuirksSettings;
+use slot_browser::{SlotBrowser, SlotRequest};
+use sprite_viewer::SpriteViewer;
 use std::time::Duration;
+use video_inspector::VideoInspector;
 
 mod color_presets;
 mod color_settings;
+mod memory_view;
+mod profiler_view;
 mod quirks_presets;
 mod quirks_settings;
+mod slot_browser;
+mod sprite_viewer;
+mod video_inspector;
 
 pub struct GUI {
     imgui: Context,
@@ -39,6 +55,10 @@ pub struct GUI {
     pub flag_open_rom_url: bool,
 
     pub flag_save_state: bool,
+    flag_slot_browser: bool,
+    slot_browser: SlotBrowser,
+    pub flag_save_slot: Option<usize>,
+    pub flag_load_slot: Option<usize>,
     pub flag_reset: bool,
     pub flag_exit: bool,
 
@@ -51,17 +71,29 @@ pub struct GUI {
     pub flag_pause: bool,
     pub cpu_speed: u32,
     cpu_multiplier: u32,
+    pub instructions_per_frame: u32,
+    pub flag_frame_timing: bool,
+    /// Held down by the fast-forward key
+    pub flag_fast_forward: bool,
+    fast_forward: TimeScale,
+    pub flag_slow_motion: bool,
+    slow_motion: u32,
     pub flag_mute: bool,
     pub volume: f32,
+    pub flag_restore_defaults: bool,
+    flag_reset_debug_layout: bool,
 
     quirks_settings: QuirksSettings,
 
     flag_about: bool,
+    flag_rom_info: bool,
+    rom_info: Option<RomInfo>,
     flag_error: bool,
     error_text: String,
     pub flag_downloading: bool,
     pub flag_step: bool,
     pub flag_step_timers: bool,
+    pub flag_advance_frame: bool,
 
     flag_breakpoint_pc: bool,
     breakpoint_pc: String,
@@ -70,6 +102,20 @@ pub struct GUI {
     flag_breakpoint_opcode: bool,
     breakpoint_opcode: String,
 
+    register_edits: Vec<(Register, u16)>,
+
+    flag_sprite_viewer: bool,
+    sprite_viewer: SpriteViewer,
+    flag_video_inspector: bool,
+    video_inspector: VideoInspector,
+    flag_profiler: bool,
+    profiler_view: ProfilerView,
+    pub flag_reset_profile: bool,
+    pub flag_export_profile: bool,
+    flag_memory_view: bool,
+    memory_view: MemoryView,
+    pub flag_export_coverage: bool,
+
     about_name: String,
     about_version: String,
     about_description: String,
@@ -80,6 +126,7 @@ impl GUI {
     const FONT_SIZE: f32 = 16.0;
     const MENU_HEIGHT_CLEARANCE: u32 = 1;
     const WIDTH_TEXTBOX_REGISTER: f32 = 32.0;
+    const WIDTH_TEXTBOX_LABEL: f32 = 90.0;
     const COLOR_TEXT_DISABLED: [f32; 4] = [1.0, 1.0, 1.0, 0.5];
 
     pub fn new(display: &Display) -> Self {
@@ -146,6 +193,10 @@ impl GUI {
             flag_open_rom_url: false,
 
             flag_save_state: false,
+            flag_slot_browser: false,
+            slot_browser: SlotBrowser::new(),
+            flag_save_slot: None,
+            flag_load_slot: None,
             flag_reset: false,
             flag_exit: false,
 
@@ -159,18 +210,29 @@ impl GUI {
 
             cpu_speed: 0,
             cpu_multiplier: 1,
+            instructions_per_frame: 15,
+            flag_frame_timing: false,
+            flag_fast_forward: false,
+            fast_forward: TimeScale::Uncapped,
+            flag_slow_motion: false,
+            slow_motion: 50,
 
             flag_mute: false,
             volume: 0.0,
+            flag_restore_defaults: false,
+            flag_reset_debug_layout: false,
 
             quirks_settings,
 
             flag_about: false,
+            flag_rom_info: false,
+            rom_info: None,
             flag_error: false,
             error_text: String::new(),
             flag_downloading: false,
             flag_step: false,
             flag_step_timers: false,
+            flag_advance_frame: false,
 
             flag_breakpoint_pc: false,
             breakpoint_pc,
@@ -179,6 +241,20 @@ impl GUI {
             flag_breakpoint_opcode: false,
             breakpoint_opcode,
 
+            register_edits: Vec::new(),
+
+            flag_sprite_viewer: false,
+            sprite_viewer: SpriteViewer::new(),
+            flag_video_inspector: false,
+            video_inspector: VideoInspector::new(),
+            flag_profiler: false,
+            profiler_view: ProfilerView::new(),
+            flag_reset_profile: false,
+            flag_export_profile: false,
+            flag_memory_view: false,
+            memory_view: MemoryView::new(),
+            flag_export_coverage: false,
+
             about_name: env!("CARGO_PKG_NAME").to_string(),
             about_version: env!("CARGO_PKG_VERSION").to_string(),
             about_description: env!("CARGO_PKG_DESCRIPTION").to_string(),
@@ -198,6 +274,17 @@ impl GUI {
     pub fn quirks_settings(&self) -> &QuirksSettings {
         &self.quirks_settings
     }
+    /// Name of the quirks preset matching the current quirks, if any.
+    pub fn quirks_preset_name(&mut self) -> Option<&'static str> {
+        let preset_handler = QuirksPresetHandler::new(&mut self.quirks_settings);
+        [
+            (QuirksPreset::Default, "Default"),
+            (QuirksPreset::Octo, "Octo"),
+        ]
+        .iter()
+        .find(|(preset, _)| preset_handler.is_active(*preset))
+        .map(|(_, name)| *name)
+    }
     pub fn flag_breakpoint_pc(&self) -> bool {
         self.flag_breakpoint_pc
     }
@@ -217,6 +304,13 @@ impl GUI {
         &self.breakpoint_opcode
     }
 
+    pub fn profiling(&self) -> bool {
+        self.flag_debug && self.flag_profiler && self.profiler_view.recording
+    }
+    pub fn take_register_edits(&mut self) -> Vec<(Register, u16)> {
+        std::mem::take(&mut self.register_edits)
+    }
+
     pub fn handle_event<T>(&mut self, display: &Display, event: &Event<T>) {
         let gl_window = display.gl_window();
         self.platform
@@ -230,6 +324,7 @@ impl GUI {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn render<S: Surface>(
         &mut self,
         delta_time: Duration,
@@ -237,11 +332,14 @@ impl GUI {
         target: &mut S,
         fps: f64,
         cpu: &CPU,
+        symbols: &SymbolTable,
+        profiler: &Profiler,
+        slots: &SaveSlots,
     ) -> Result<(), String> {
         self.is_open = false;
         self.imgui.io_mut().update_delta_time(delta_time);
 
-        let mut reset_debug_layout = false;
+        let mut reset_debug_layout = std::mem::take(&mut self.flag_reset_debug_layout);
 
         let about_name = &self.about_name;
         let about_version = &self.about_version;
@@ -250,6 +348,7 @@ impl GUI {
 
         let window_width = display.gl_window().window().inner_size().width as f32;
         let window_height = display.gl_window().window().inner_size().height as f32;
+        let cpu_speed = self.speed().frequency();
 
         let ui = self.imgui.frame();
         let custom_font = ui.push_font(self.custom_font);
@@ -268,6 +367,30 @@ impl GUI {
                 MenuItem::new("Save State...")
                     .shortcut("Ctrl + S")
                     .build_with_ref(&ui, &mut self.flag_save_state);
+                MenuItem::new("Save Slots...").build_with_ref(&ui, &mut self.flag_slot_browser);
+                if let Some(slots_menu) = ui.begin_menu("Quick Save") {
+                    for slot in 0..SLOT_COUNT {
+                        if MenuItem::new(&format!("Slot {}", slot + 1))
+                            .shortcut(&format!("Shift + F{}", slot + 1))
+                            .build(&ui)
+                        {
+                            self.flag_save_slot = Some(slot);
+                        }
+                    }
+                    slots_menu.end();
+                }
+                if let Some(slots_menu) = ui.begin_menu("Quick Load") {
+                    for (slot, info) in slots.slots().iter().enumerate() {
+                        if MenuItem::new(&format!("Slot {}", slot + 1))
+                            .shortcut(&format!("Ctrl + F{}", slot + 1))
+                            .enabled(info.is_some())
+                            .build(&ui)
+                        {
+                            self.flag_load_slot = Some(slot);
+                        }
+                    }
+                    slots_menu.end();
+                }
                 ui.separator();
                 MenuItem::new("Reset")
                     .shortcut("F5")
@@ -392,6 +515,12 @@ impl GUI {
                     .shortcut("F7")
                     .build_with_ref(&ui, &mut self.flag_debug);
                 if self.flag_debug {
+                    MenuItem::new("Memory View").build_with_ref(&ui, &mut self.flag_memory_view);
+                    MenuItem::new("Sprite Viewer")
+                        .build_with_ref(&ui, &mut self.flag_sprite_viewer);
+                    MenuItem::new("Video Memory Inspector")
+                        .build_with_ref(&ui, &mut self.flag_video_inspector);
+                    MenuItem::new("Profiler").build_with_ref(&ui, &mut self.flag_profiler);
                     MenuItem::new("Reset Debug Window Layout")
                         .build_with_ref(&ui, &mut reset_debug_layout);
                 }
@@ -402,6 +531,9 @@ impl GUI {
                 MenuItem::new("Pause")
                     .shortcut("P")
                     .build_with_ref(&ui, &mut self.flag_pause);
+                MenuItem::new("Slow Motion")
+                    .shortcut("L")
+                    .build_with_ref(&ui, &mut self.flag_slow_motion);
                 ui.separator();
                 if let Some(cpu_speed_menu) = ui.begin_menu("CPU Speed") {
                     Self::cpu_speed_menu_item(
@@ -409,36 +541,42 @@ impl GUI {
                         "Slowest",
                         420 * self.cpu_multiplier,
                         &mut self.cpu_speed,
+                        &mut self.flag_frame_timing,
                     );
                     Self::cpu_speed_menu_item(
                         &ui,
                         "Slow",
                         600 * self.cpu_multiplier,
                         &mut self.cpu_speed,
+                        &mut self.flag_frame_timing,
                     );
                     Self::cpu_speed_menu_item(
                         &ui,
                         "Normal",
                         720 * self.cpu_multiplier,
                         &mut self.cpu_speed,
+                        &mut self.flag_frame_timing,
                     );
                     Self::cpu_speed_menu_item(
                         &ui,
                         "Fast",
                         900 * self.cpu_multiplier,
                         &mut self.cpu_speed,
+                        &mut self.flag_frame_timing,
                     );
                     Self::cpu_speed_menu_item(
                         &ui,
                         "Faster",
                         1200 * self.cpu_multiplier,
                         &mut self.cpu_speed,
+                        &mut self.flag_frame_timing,
                     );
                     Self::cpu_speed_menu_item(
                         &ui,
                         "Fastest",
                         1500 * self.cpu_multiplier,
                         &mut self.cpu_speed,
+                        &mut self.flag_frame_timing,
                     );
                     ui.separator();
                     let before = self.cpu_multiplier == 50;
@@ -451,8 +589,53 @@ impl GUI {
                         self.cpu_multiplier = 1;
                         self.cpu_speed /= 50;
                     }
+                    ui.separator();
+                    if let Some(frame_menu) = ui.begin_menu("Instructions per Frame") {
+                        for &instructions in &[7, 15, 20, 30, 100, 200, 500, 1000] {
+                            let mut flag = self.flag_frame_timing
+                                && self.instructions_per_frame == instructions;
+                            MenuItem::new(&format!("{}", instructions))
+                                .build_with_ref(&ui, &mut flag);
+                            if flag {
+                                self.instructions_per_frame = instructions;
+                                self.flag_frame_timing = true;
+                            }
+                        }
+                        ui.separator();
+                        let mut instructions = self.instructions_per_frame as i32;
+                        if ui.input_int("Custom", &mut instructions).build() {
+                            self.instructions_per_frame = instructions.max(1) as u32;
+                            self.flag_frame_timing = true;
+                        }
+                        frame_menu.end();
+                    }
                     cpu_speed_menu.end();
                 }
+                if let Some(fast_forward_menu) = ui.begin_menu("Fast-Forward (Hold Tab)") {
+                    for &time_scale in &[
+                        TimeScale::Uncapped,
+                        TimeScale::Percent(200),
+                        TimeScale::Percent(400),
+                        TimeScale::Percent(800),
+                    ] {
+                        let mut flag = self.fast_forward == time_scale;
+                        MenuItem::new(&format!("{}", time_scale)).build_with_ref(&ui, &mut flag);
+                        if flag {
+                            self.fast_forward = time_scale;
+                        }
+                    }
+                    fast_forward_menu.end();
+                }
+                if let Some(slow_motion_menu) = ui.begin_menu("Slow Motion Speed") {
+                    for &percent in &[25, 50] {
+                        let mut flag = self.slow_motion == percent;
+                        MenuItem::new(&format!("{}%", percent)).build_with_ref(&ui, &mut flag);
+                        if flag {
+                            self.slow_motion = percent;
+                        }
+                    }
+                    slow_motion_menu.end();
+                }
                 if let Some(quirks_menu) = ui.begin_menu("Quirks") {
                     MenuItem::new("Load/Store")
                         .build_with_ref(&ui, &mut self.quirks_settings.get_mut(Quirk::LoadStore));
@@ -501,10 +684,16 @@ impl GUI {
                 MenuItem::new("Mute Audio")
                     .shortcut("M")
                     .build_with_ref(&ui, &mut self.flag_mute);
+                ui.separator();
+                MenuItem::new("Restore Defaults")
+                    .build_with_ref(&ui, &mut self.flag_restore_defaults);
                 menu.end();
             }
             if let Some(menu) = ui.begin_menu("Help") {
                 self.is_open = true;
+                MenuItem::new("ROM Info")
+                    .enabled(self.rom_info.is_some())
+                    .build_with_ref(&ui, &mut self.flag_rom_info);
                 MenuItem::new("About").build_with_ref(&ui, &mut self.flag_about);
                 menu.end();
             }
@@ -607,6 +796,64 @@ impl GUI {
                         ui.text_wrapped(&error_text);
                     });
             }
+            if let (true, Some(info)) = (self.flag_rom_info, &self.rom_info) {
+                let size = [320.0, 280.0];
+                let pos = [window_width - size[0] - 10.0, 40.0];
+                let custom_font_big = self.custom_font_big;
+                Window::new("ROM Info")
+                    .opened(&mut self.flag_rom_info)
+                    .position(pos, Condition::FirstUseEver)
+                    .size(size, Condition::FirstUseEver)
+                    .build(&ui, || {
+                        let font = ui.push_font(custom_font_big);
+                        ui.text_wrapped(&info.title);
+                        font.pop();
+                        if !info.authors.is_empty() {
+                            ui.text_wrapped(format!("by {}", info.authors.join(", ")));
+                        }
+                        if let Some(release) = &info.release {
+                            ui.text_disabled(format!("Released {}", release));
+                        }
+                        if let Some(platform) = &info.platform {
+                            ui.text(format!("Platform: {}", platform));
+                        }
+                        if let Some(description) = &info.description {
+                            ui.separator();
+                            ui.text_wrapped(description);
+                        }
+                        if !info.keys.is_empty() {
+                            ui.separator();
+                            ui.text("Keys");
+                            for (input, key) in &info.keys {
+                                match rom_database::keyboard_key(*key) {
+                                    Some(keyboard_key) => {
+                                        ui.text(format!("{}: {:X} ({})", input, key, keyboard_key))
+                                    }
+                                    None => ui.text(format!("{}: {:X}", input, key)),
+                                }
+                            }
+                        }
+                    });
+            }
+            if self.flag_slot_browser {
+                self.is_open = true;
+                let size = [300.0, 500.0];
+                let pos = [window_width / 2.0 - size[0] / 2.0, 40.0];
+                let slot_browser = &mut self.slot_browser;
+                let mut request = SlotRequest::None;
+                Window::new("Save Slots")
+                    .opened(&mut self.flag_slot_browser)
+                    .position(pos, Condition::FirstUseEver)
+                    .size(size, Condition::FirstUseEver)
+                    .build(&ui, || {
+                        request = slot_browser.build(&ui, slots);
+                    });
+                match request {
+                    SlotRequest::Save(slot) => self.flag_save_slot = Some(slot),
+                    SlotRequest::Load(slot) => self.flag_load_slot = Some(slot),
+                    SlotRequest::None => (),
+                }
+            }
 
             if self.flag_debug {
                 let font = self.custom_font_small;
@@ -618,7 +865,11 @@ impl GUI {
                     Condition::FirstUseEver
                 };
 
-                let size = [130.0, 265.0];
+                // Registers can be edited while paused, the values are applied by the emulator
+                let editable = self.flag_pause;
+                let register_edits = &mut self.register_edits;
+
+                let size = [130.0, 360.0];
                 let pos = [10.0, 40.0];
                 Window::new("Registers")
                     .position(pos, pos_condition)
@@ -627,61 +878,119 @@ impl GUI {
                     .build(&ui, || {
                         ui.columns(2, "registers", true);
 
-                        Self::register_col_u16(&ui, "PC", cpu.PC());
-                        Self::register_col_u16(&ui, "I ", cpu.I());
-                        Self::register_col_u8_greyed(&ui, "DT", cpu.DT(), cpu.DT() == 0);
-                        Self::register_col_u8_greyed(&ui, "ST", cpu.ST(), cpu.ST() == 0);
+                        if let Some(val) = Self::register_col_u16(&ui, "PC", cpu.PC(), editable) {
+                            register_edits.push((Register::PC, val));
+                        }
+                        if let Some(val) = Self::register_col_u16(&ui, "I ", cpu.I(), editable) {
+                            register_edits.push((Register::I, val));
+                        }
+                        if let Some(val) = Self::register_col_u8_greyed(
+                            &ui,
+                            "DT",
+                            cpu.DT(),
+                            cpu.DT() == 0,
+                            editable,
+                        ) {
+                            register_edits.push((Register::DT, val as u16));
+                        }
+                        if let Some(val) = Self::register_col_u8_greyed(
+                            &ui,
+                            "ST",
+                            cpu.ST(),
+                            cpu.ST() == 0,
+                            editable,
+                        ) {
+                            register_edits.push((Register::ST, val as u16));
+                        }
                         ui.separator();
                         let v = cpu.V();
-                        Self::register_col_u8(&ui, "V0", v[0]);
-                        Self::register_col_u8(&ui, "V8", v[8]);
-                        Self::register_col_u8(&ui, "V1", v[1]);
-                        Self::register_col_u8(&ui, "V9", v[9]);
-                        Self::register_col_u8(&ui, "V2", v[2]);
-                        Self::register_col_u8(&ui, "VA", v[10]);
-                        Self::register_col_u8(&ui, "V3", v[3]);
-                        Self::register_col_u8(&ui, "VB", v[11]);
-                        Self::register_col_u8(&ui, "V4", v[4]);
-                        Self::register_col_u8(&ui, "VC", v[12]);
-                        Self::register_col_u8(&ui, "V5", v[5]);
-                        Self::register_col_u8(&ui, "VD", v[13]);
-                        Self::register_col_u8(&ui, "V6", v[6]);
-                        Self::register_col_u8(&ui, "VE", v[14]);
-                        Self::register_col_u8(&ui, "V7", v[7]);
-                        Self::register_col_u8(&ui, "VF", v[15]);
+                        for row in 0..8 {
+                            for idx in &[row, row + 8] {
+                                let name = format!("V{:X}", idx);
+                                if let Some(val) =
+                                    Self::register_col_u8(&ui, &name, v[*idx], editable)
+                                {
+                                    register_edits.push((Register::V(*idx), val as u16));
+                                }
+                            }
+                        }
+                        ui.separator();
+                        let rpl = cpu.RPL();
+                        for row in 0..4 {
+                            for idx in &[row, row + 4] {
+                                let name = format!("R{}", idx);
+                                if let Some(val) =
+                                    Self::register_col_u8(&ui, &name, rpl[*idx], editable)
+                                {
+                                    register_edits.push((Register::RPL(*idx), val as u16));
+                                }
+                            }
+                        }
                     });
 
-                let size = [130.0, 245.0];
+                let size = if symbols.is_empty() {
+                    [130.0, 245.0]
+                } else {
+                    [220.0, 320.0]
+                };
                 let pos = [window_width - size[0] - 10.0, 40.0];
                 Window::new("Stack")
                     .position(pos, pos_condition)
                     .size(size, Condition::Always)
                     .resizable(false)
                     .build(&ui, || {
-                        ui.columns(2, "stack", true);
-                        Self::register_col_u8(&ui, "SP", cpu.sp() as u8);
-                        ui.next_column();
-                        ui.separator();
                         let stack = cpu.stack();
-                        Self::register_col_u16_greyed(&ui, "0 ", stack[0], cpu.sp() == 0);
-                        Self::register_col_u16_greyed(&ui, "8 ", stack[8], cpu.sp() <= 8);
-                        Self::register_col_u16_greyed(&ui, "1 ", stack[1], cpu.sp() <= 1);
-                        Self::register_col_u16_greyed(&ui, "9 ", stack[9], cpu.sp() <= 9);
-                        Self::register_col_u16_greyed(&ui, "2 ", stack[2], cpu.sp() <= 2);
-                        Self::register_col_u16_greyed(&ui, "10", stack[10], cpu.sp() <= 10);
-                        Self::register_col_u16_greyed(&ui, "3 ", stack[3], cpu.sp() <= 3);
-                        Self::register_col_u16_greyed(&ui, "11", stack[11], cpu.sp() <= 11);
-                        Self::register_col_u16_greyed(&ui, "4 ", stack[4], cpu.sp() <= 4);
-                        Self::register_col_u16_greyed(&ui, "12", stack[12], cpu.sp() <= 12);
-                        Self::register_col_u16_greyed(&ui, "5 ", stack[5], cpu.sp() <= 5);
-                        Self::register_col_u16_greyed(&ui, "13", stack[13], cpu.sp() <= 13);
-                        Self::register_col_u16_greyed(&ui, "6 ", stack[6], cpu.sp() <= 6);
-                        Self::register_col_u16_greyed(&ui, "14", stack[14], cpu.sp() <= 14);
-                        Self::register_col_u16_greyed(&ui, "7 ", stack[7], cpu.sp() <= 7);
-                        Self::register_col_u16_greyed(&ui, "15", stack[15], cpu.sp() <= 15);
+                        if symbols.is_empty() {
+                            ui.columns(2, "stack", true);
+                            if let Some(val) =
+                                Self::register_col_u8(&ui, "SP", cpu.sp() as u8, editable)
+                            {
+                                register_edits.push((Register::SP, val as u16));
+                            }
+                            ui.next_column();
+                            ui.separator();
+                            Self::register_col_u16_greyed(&ui, "0 ", stack[0], cpu.sp() == 0);
+                            Self::register_col_u16_greyed(&ui, "8 ", stack[8], cpu.sp() <= 8);
+                            Self::register_col_u16_greyed(&ui, "1 ", stack[1], cpu.sp() <= 1);
+                            Self::register_col_u16_greyed(&ui, "9 ", stack[9], cpu.sp() <= 9);
+                            Self::register_col_u16_greyed(&ui, "2 ", stack[2], cpu.sp() <= 2);
+                            Self::register_col_u16_greyed(&ui, "10", stack[10], cpu.sp() <= 10);
+                            Self::register_col_u16_greyed(&ui, "3 ", stack[3], cpu.sp() <= 3);
+                            Self::register_col_u16_greyed(&ui, "11", stack[11], cpu.sp() <= 11);
+                            Self::register_col_u16_greyed(&ui, "4 ", stack[4], cpu.sp() <= 4);
+                            Self::register_col_u16_greyed(&ui, "12", stack[12], cpu.sp() <= 12);
+                            Self::register_col_u16_greyed(&ui, "5 ", stack[5], cpu.sp() <= 5);
+                            Self::register_col_u16_greyed(&ui, "13", stack[13], cpu.sp() <= 13);
+                            Self::register_col_u16_greyed(&ui, "6 ", stack[6], cpu.sp() <= 6);
+                            Self::register_col_u16_greyed(&ui, "14", stack[14], cpu.sp() <= 14);
+                            Self::register_col_u16_greyed(&ui, "7 ", stack[7], cpu.sp() <= 7);
+                            Self::register_col_u16_greyed(&ui, "15", stack[15], cpu.sp() <= 15);
+                        } else {
+                            // The stack holds the address of the call, so we can resolve the calling routine
+                            if let Some(val) =
+                                Self::register_col_u8(&ui, "SP", cpu.sp() as u8, editable)
+                            {
+                                register_edits.push((Register::SP, val as u16));
+                            }
+                            ui.separator();
+                            for (idx, addr) in stack.iter().enumerate() {
+                                let routine = symbols.format_routine(*addr).unwrap_or_default();
+                                Self::stack_entry_text(
+                                    &ui,
+                                    &format!("{:<2}", idx),
+                                    *addr,
+                                    &routine,
+                                    cpu.sp() <= idx,
+                                );
+                            }
+                        }
                     });
 
-                let size = [260.0, 80.0];
+                let size = if symbols.is_empty() {
+                    [260.0, 80.0]
+                } else {
+                    [380.0, 80.0]
+                };
                 let pos = [
                     window_width / 3.0 - size[0] / 2.0,
                     window_height - size[1] - 10.0,
@@ -704,6 +1013,7 @@ impl GUI {
                             flag_breakpoint_pc,
                             breakpoint_pc,
                             true,
+                            !symbols.is_empty(),
                         );
 
                         ui.same_line();
@@ -717,6 +1027,7 @@ impl GUI {
                             flag_breakpoint_i,
                             breakpoint_i,
                             true,
+                            !symbols.is_empty(),
                         );
 
                         // Break on opcode
@@ -726,6 +1037,7 @@ impl GUI {
                             flag_breakpoint_opcode,
                             breakpoint_opcode,
                             false,
+                            false,
                         ) {
                             // Sanitize and fill input
                             let mut value: String = breakpoint_opcode
@@ -744,7 +1056,11 @@ impl GUI {
                         }
                     });
 
-                let size = [260.0, 80.0];
+                let size = if symbols.is_empty() {
+                    [260.0, 80.0]
+                } else {
+                    [320.0, 100.0]
+                };
                 let pos = [
                     2.0 * window_width / 3.0 - size[0] / 2.0,
                     window_height - size[1] - 10.0,
@@ -754,19 +1070,93 @@ impl GUI {
                     .size(size, Condition::Always)
                     .resizable(false)
                     .build(&ui, || {
+                        if let Some(routine) = symbols.format_routine(cpu.PC()) {
+                            ui.text(format!("@ {}", routine));
+                        }
                         Self::opcode_text(
                             &ui,
                             "> Next",
                             cpu.next_opcode(),
-                            &cpu.next_opcode_description(),
+                            &symbols
+                                .annotate_opcode(cpu.next_opcode(), cpu.next_opcode_description()),
                         );
                         let style =
                             ui.push_style_color(StyleColor::Text, Self::COLOR_TEXT_DISABLED);
-                        Self::opcode_text(&ui, "  Last", cpu.opcode(), &cpu.opcode_description());
+                        Self::opcode_text(
+                            &ui,
+                            "  Last",
+                            cpu.opcode(),
+                            &symbols.annotate_opcode(cpu.opcode(), cpu.opcode_description()),
+                        );
                         style.pop();
                     });
 
-                let size = [347.0, 37.0];
+                if self.flag_memory_view {
+                    let size = [440.0, 400.0];
+                    let pos = [150.0, 310.0];
+                    let memory_view = &mut self.memory_view;
+                    let mut export = false;
+                    Window::new("Memory")
+                        .opened(&mut self.flag_memory_view)
+                        .position(pos, pos_condition)
+                        .size(size, Condition::FirstUseEver)
+                        .build(&ui, || {
+                            export = memory_view.build(&ui, cpu);
+                        });
+                    if export {
+                        self.flag_export_coverage = true;
+                    }
+                }
+
+                if self.flag_sprite_viewer {
+                    let size = [250.0, 260.0];
+                    let pos = [150.0, 40.0];
+                    let sprite_viewer = &mut self.sprite_viewer;
+                    let color_settings = &self.color_settings;
+                    Window::new("Sprite Viewer")
+                        .opened(&mut self.flag_sprite_viewer)
+                        .position(pos, pos_condition)
+                        .size(size, Condition::FirstUseEver)
+                        .build(&ui, || {
+                            sprite_viewer.build(&ui, cpu, color_settings);
+                        });
+                }
+
+                if self.flag_video_inspector {
+                    let size = [300.0, 420.0];
+                    let pos = [window_width - size[0] - 150.0, 40.0];
+                    let video_inspector = &mut self.video_inspector;
+                    let color_settings = &self.color_settings;
+                    Window::new("Video Memory")
+                        .opened(&mut self.flag_video_inspector)
+                        .position(pos, pos_condition)
+                        .size(size, Condition::FirstUseEver)
+                        .horizontal_scrollbar(true)
+                        .build(&ui, || {
+                            video_inspector.build(&ui, cpu.vmem(), color_settings);
+                        });
+                }
+
+                if self.flag_profiler {
+                    let size = [360.0, 400.0];
+                    let pos = [window_width / 2.0 - size[0] / 2.0, 90.0];
+                    let profiler_view = &mut self.profiler_view;
+                    let mut request = ProfilerRequest::None;
+                    Window::new("Profiler")
+                        .opened(&mut self.flag_profiler)
+                        .position(pos, pos_condition)
+                        .size(size, Condition::FirstUseEver)
+                        .build(&ui, || {
+                            request = profiler_view.build(&ui, profiler, symbols, cpu_speed);
+                        });
+                    match request {
+                        ProfilerRequest::Reset => self.flag_reset_profile = true,
+                        ProfilerRequest::Export => self.flag_export_profile = true,
+                        ProfilerRequest::None => (),
+                    }
+                }
+
+                let size = [460.0, 37.0];
                 let pos = [
                     window_width / 2.0 - size[0] / 2.0,
                     self.last_menu_height as f32 + 10.0,
@@ -774,6 +1164,7 @@ impl GUI {
                 let mut pause = &mut self.flag_pause;
                 let step = &mut self.flag_step;
                 let step_timers = &mut self.flag_step_timers;
+                let advance_frame = &mut self.flag_advance_frame;
                 Window::new("Debug")
                     .position(pos, Condition::Always)
                     .size(size, Condition::Always)
@@ -795,6 +1186,15 @@ impl GUI {
                         ) {
                             *step_timers = true;
                         }
+                        ui.same_line();
+                        if Self::button_disabled(
+                            &ui,
+                            "Advance Frame (F10)",
+                            button_size,
+                            !*pause,
+                        ) {
+                            *advance_frame = true;
+                        }
                     });
 
                 font.pop();
@@ -819,30 +1219,22 @@ impl GUI {
         Ok(())
     }
 
-    fn register_col_u16(ui: &Ui, name: &str, value: u16) {
+    fn register_col_u16(ui: &Ui, name: &str, value: u16, editable: bool) -> Option<u16> {
         ui.align_text_to_frame_padding();
         ui.text(name);
         ui.same_line();
-        let mut inp = format!("{:04X}", value);
-        let width = ui.push_item_width(Self::WIDTH_TEXTBOX_REGISTER);
-        ui.input_text(format!("##{}", name), &mut inp)
-            .read_only(true)
-            .build();
-        width.pop(&ui);
+        let res = Self::register_input(ui, name, format!("{:04X}", value), editable);
         ui.next_column();
+        res.and_then(|inp| u16::from_str_radix(&inp, 16).ok())
     }
 
-    fn register_col_u8(ui: &Ui, name: &str, value: u8) {
+    fn register_col_u8(ui: &Ui, name: &str, value: u8, editable: bool) -> Option<u8> {
         ui.align_text_to_frame_padding();
         ui.text(name);
         ui.same_line();
-        let mut inp = format!("{:02X}", value);
-        let width = ui.push_item_width(Self::WIDTH_TEXTBOX_REGISTER);
-        ui.input_text(format!("##{}", name), &mut inp)
-            .read_only(true)
-            .build();
-        width.pop(&ui);
+        let res = Self::register_input(ui, name, format!("{:02X}", value), editable);
         ui.next_column();
+        res.and_then(|inp| u8::from_str_radix(&inp, 16).ok())
     }
 
     fn register_col_u16_greyed(ui: &Ui, name: &str, value: u16, greyed: bool) {
@@ -850,53 +1242,81 @@ impl GUI {
         if greyed {
             style = Some(ui.push_style_color(StyleColor::Text, Self::COLOR_TEXT_DISABLED));
         }
-        ui.align_text_to_frame_padding();
-        ui.text(name);
-        ui.same_line();
-        let mut inp = format!("{:04X}", value);
-        let width = ui.push_item_width(Self::WIDTH_TEXTBOX_REGISTER);
-        ui.input_text(format!("##{}", name), &mut inp)
-            .read_only(true)
-            .build();
-        width.pop(&ui);
-        ui.next_column();
+        Self::register_col_u16(ui, name, value, false);
         if let Some(style) = style {
             style.pop();
         }
     }
 
-    fn register_col_u8_greyed(ui: &Ui, name: &str, value: u8, greyed: bool) {
+    fn register_col_u8_greyed(
+        ui: &Ui,
+        name: &str,
+        value: u8,
+        greyed: bool,
+        editable: bool,
+    ) -> Option<u8> {
         let mut style = None;
         if greyed {
             style = Some(ui.push_style_color(StyleColor::Text, Self::COLOR_TEXT_DISABLED));
         }
+        let res = Self::register_col_u8(ui, name, value, editable);
+        if let Some(style) = style {
+            style.pop();
+        }
+        res
+    }
+
+    /// Returns the new input if it was confirmed with enter.
+    fn register_input(ui: &Ui, name: &str, mut inp: String, editable: bool) -> Option<String> {
+        let width = ui.push_item_width(Self::WIDTH_TEXTBOX_REGISTER);
+        let entered = ui
+            .input_text(format!("##{}", name), &mut inp)
+            .read_only(!editable)
+            .chars_hexadecimal(true)
+            .chars_uppercase(true)
+            .enter_returns_true(true)
+            .build();
+        width.pop(ui);
+        if entered && !inp.is_empty() {
+            Some(inp)
+        } else {
+            None
+        }
+    }
+
+    fn opcode_text(ui: &Ui, name: &str, value: u16, description: &str) {
         ui.align_text_to_frame_padding();
         ui.text(name);
         ui.same_line();
-        let mut inp = format!("{:02X}", value);
+        let mut inp = format!("{:04X}", value);
         let width = ui.push_item_width(Self::WIDTH_TEXTBOX_REGISTER);
         ui.input_text(format!("##{}", name), &mut inp)
             .read_only(true)
             .build();
         width.pop(&ui);
-        ui.next_column();
-        if let Some(style) = style {
-            style.pop();
-        }
+        ui.same_line();
+        ui.text(description);
     }
 
-    fn opcode_text(ui: &Ui, name: &str, value: u16, description: &str) {
+    fn stack_entry_text(ui: &Ui, name: &str, value: u16, routine: &str, greyed: bool) {
+        let mut style = None;
+        if greyed {
+            style = Some(ui.push_style_color(StyleColor::Text, Self::COLOR_TEXT_DISABLED));
+        }
         ui.align_text_to_frame_padding();
         ui.text(name);
         ui.same_line();
         let mut inp = format!("{:04X}", value);
         let width = ui.push_item_width(Self::WIDTH_TEXTBOX_REGISTER);
-        ui.input_text(format!("##{}", name), &mut inp)
+        ui.input_text(format!("##stack{}", name), &mut inp)
             .read_only(true)
             .build();
-        width.pop(&ui);
+        width.pop(ui);
         ui.same_line();
-        ui.text(description);
+        ui.text(routine);
+        if let Some(style) = style {
+            style.pop();
+        }
     }
 
     fn breakpoint_input(
@@ -905,13 +1325,19 @@ impl GUI {
         enabled: &mut bool,
         value: &mut String,
         hex_chars: bool,
+        labels: bool,
     ) -> bool {
+        // Addresses can also be given as label if symbols are loaded
         ui.checkbox(name, enabled);
         ui.same_line();
-        let width = ui.push_item_width(Self::WIDTH_TEXTBOX_REGISTER);
+        let width = ui.push_item_width(if labels {
+            Self::WIDTH_TEXTBOX_LABEL
+        } else {
+            Self::WIDTH_TEXTBOX_REGISTER
+        });
         ui.input_text(format!("##{}", name), value)
-            .chars_hexadecimal(hex_chars)
-            .chars_uppercase(true)
+            .chars_hexadecimal(hex_chars && !labels)
+            .chars_uppercase(!labels)
             .build();
         width.pop(&ui);
         ui.is_item_deactivated_after_edit()
@@ -994,11 +1420,151 @@ impl GUI {
         self.last_menu_height
     }
 
-    fn cpu_speed_menu_item(ui: &Ui, name: &str, item_speed: u32, current_speed: &mut u32) {
-        let mut flag = *current_speed == item_speed;
+    /// The speed as chosen in the menu, either a frequency or instructions per frame.
+    pub fn speed(&self) -> Speed {
+        if self.flag_frame_timing {
+            Speed::InstructionsPerFrame(self.instructions_per_frame)
+        } else {
+            Speed::Frequency(self.cpu_speed)
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        match speed {
+            Speed::Frequency(frequency) => {
+                self.cpu_speed = frequency;
+                self.flag_frame_timing = false;
+            }
+            Speed::InstructionsPerFrame(instructions) => {
+                self.instructions_per_frame = instructions;
+                self.flag_frame_timing = true;
+            }
+        }
+    }
+
+    pub fn set_color(&mut self, color: Color, value: [u8; 3]) {
+        *self.color_settings.get_mut(color) = [
+            value[0] as f32 / 255.0,
+            value[1] as f32 / 255.0,
+            value[2] as f32 / 255.0,
+        ];
+        self.color_settings.changed = true;
+    }
+
+    pub fn set_quirk(&mut self, quirk: Quirk, value: bool) {
+        *self.quirks_settings.get_mut(quirk) = value;
+    }
+
+    /// Sets the database entry of the loaded ROM, showing it if there is one.
+    pub fn set_rom_info(&mut self, info: Option<RomInfo>) {
+        self.flag_rom_info = info.is_some();
+        self.rom_info = info;
+    }
+
+    /// Applies persisted settings, also used to restore the defaults.
+    pub fn apply_config(&mut self, config: &Config) {
+        self.apply_rom_settings(config);
+        self.cpu_multiplier = 1;
+        self.volume = config.volume;
+        self.flag_mute = config.mute;
+        self.flag_display_fps = config.display_fps;
+        self.flag_debug = config.debug;
+
+        if config.layout.is_empty() {
+            self.flag_reset_debug_layout = true;
+        } else {
+            self.imgui.load_ini_settings(&config.layout);
+        }
+    }
+
+    /// Applies the speed, colors and quirks, the settings which ROMs may change.
+    pub fn apply_rom_settings(&mut self, config: &Config) {
+        self.set_speed(config.speed());
+
+        let colors = &config.colors;
+        for (color, value) in &[
+            (Color::Background, colors.background),
+            (Color::Plane1, colors.plane_1),
+            (Color::Plane2, colors.plane_2),
+            (Color::PlaneBoth, colors.plane_both),
+        ] {
+            self.set_color(*color, *value);
+        }
+
+        let quirks = &config.quirks;
+        for (quirk, value) in [
+            (Quirk::LoadStore, quirks.load_store),
+            (Quirk::Shift, quirks.shift),
+            (Quirk::Draw, quirks.draw),
+            (Quirk::Jump, quirks.jump),
+            (Quirk::VfOrder, quirks.vf_order),
+            (Quirk::PartialWrapH, quirks.partial_wrap_h),
+            (Quirk::PartialWrapV, quirks.partial_wrap_v),
+        ] {
+            self.set_quirk(quirk, value);
+        }
+    }
+
+    /// Collects the settings to persist, except for the ones not part of the GUI.
+    pub fn config(&mut self) -> Config {
+        let color = |color| {
+            let value: [f32; 3] = self.color_settings.get(color);
+            [
+                (value[0] * 255.0).round() as u8,
+                (value[1] * 255.0).round() as u8,
+                (value[2] * 255.0).round() as u8,
+            ]
+        };
+        let mut config = Config {
+            volume: self.volume,
+            mute: self.flag_mute,
+            display_fps: self.flag_display_fps,
+            debug: self.flag_debug,
+            colors: config::Colors {
+                background: color(Color::Background),
+                plane_1: color(Color::Plane1),
+                plane_2: color(Color::Plane2),
+                plane_both: color(Color::PlaneBoth),
+            },
+            quirks: config::Quirks {
+                load_store: self.quirks_settings.get(Quirk::LoadStore),
+                shift: self.quirks_settings.get(Quirk::Shift),
+                draw: self.quirks_settings.get(Quirk::Draw),
+                jump: self.quirks_settings.get(Quirk::Jump),
+                vf_order: self.quirks_settings.get(Quirk::VfOrder),
+                partial_wrap_h: self.quirks_settings.get(Quirk::PartialWrapH),
+                partial_wrap_v: self.quirks_settings.get(Quirk::PartialWrapV),
+            },
+            ..Config::default()
+        };
+        config.set_speed(self.speed());
+        self.imgui.save_ini_settings(&mut config.layout);
+        config
+    }
+
+    /// Fast-forward while its key is held, otherwise slow-motion if enabled.
+    pub fn time_scale(&self) -> TimeScale {
+        if self.flag_fast_forward {
+            self.fast_forward
+        } else if self.flag_slow_motion {
+            TimeScale::Percent(self.slow_motion)
+        } else {
+            TimeScale::REAL_TIME
+        }
+    }
+
+    fn cpu_speed_menu_item(
+        ui: &Ui,
+        name: &str,
+        item_speed: u32,
+        current_speed: &mut u32,
+        frame_timing: &mut bool,
+    ) {
+        let mut flag = !*frame_timing && *current_speed == item_speed;
         MenuItem::new(&format!("{} ({}Hz)", name, item_speed)).build_with_ref(ui, &mut flag);
         if flag {
             *current_speed = item_speed;
+            *frame_timing = false;
         }
     }
 
@@ -0,0 +1,124 @@
+use crate::profiler::Profiler;
+use crate::symbols::SymbolTable;
+use imgui::Ui;
+
+pub enum ProfilerRequest {
+    None,
+    Reset,
+    Export,
+}
+
+/// Shows the hotspots collected by the profiler as sorted tables.
+pub struct ProfilerView {
+    pub recording: bool,
+    view: usize,
+}
+
+impl ProfilerView {
+    const VIEWS: [&'static str; 2] = ["Addresses", "Subroutines"];
+    const MAX_ROWS: usize = 100;
+
+    pub fn new() -> Self {
+        Self {
+            recording: false,
+            view: 0,
+        }
+    }
+
+    pub fn build(
+        &mut self,
+        ui: &Ui,
+        profiler: &Profiler,
+        symbols: &SymbolTable,
+        cpu_speed: u32,
+    ) -> ProfilerRequest {
+        let mut request = ProfilerRequest::None;
+
+        ui.checkbox("Record", &mut self.recording);
+        ui.same_line();
+        if ui.small_button("Reset") {
+            request = ProfilerRequest::Reset;
+        }
+        ui.same_line();
+        if ui.small_button("Export...") {
+            request = ProfilerRequest::Export;
+        }
+
+        let cycles = profiler.cycles();
+        let key_wait = profiler.key_wait_cycles();
+        ui.text(format!("Cycles: {}", cycles));
+        ui.text(format!(
+            "Key wait (FX0A): {} ({:.1}%, {:.2}s)",
+            key_wait,
+            Self::percentage(key_wait, cycles),
+            key_wait as f64 / cpu_speed as f64
+        ));
+
+        let item_width = ui.push_item_width(120.0);
+        ui.combo_simple_string("##profiler_view", &mut self.view, &Self::VIEWS);
+        item_width.pop(ui);
+        ui.separator();
+
+        if self.view == 0 {
+            ui.columns(4, "profiler_addresses", true);
+            Self::header(ui, &["Address", "Hits", "%", "Routine"]);
+            for stats in profiler.hotspots(symbols).iter().take(Self::MAX_ROWS) {
+                ui.text(format!("{:04X}", stats.address));
+                ui.next_column();
+                if stats.key_wait > 0 {
+                    ui.text(format!("{} +{} wait", stats.hits, stats.key_wait));
+                } else {
+                    ui.text(stats.hits.to_string());
+                }
+                ui.next_column();
+                ui.text(format!(
+                    "{:.1}",
+                    Self::percentage(stats.hits + stats.key_wait, cycles)
+                ));
+                ui.next_column();
+                ui.text(stats.label.as_deref().unwrap_or(""));
+                ui.next_column();
+            }
+        } else {
+            ui.columns(5, "profiler_routines", true);
+            Self::header(ui, &["Routine", "Calls", "Self", "Total", "%"]);
+            for stats in profiler.routines(symbols).iter().take(Self::MAX_ROWS) {
+                match &stats.label {
+                    Some(label) => ui.text(label),
+                    None => ui.text(format!("{:04X}", stats.address)),
+                }
+                ui.next_column();
+                ui.text(stats.calls.to_string());
+                ui.next_column();
+                ui.text(stats.self_cycles.to_string());
+                ui.next_column();
+                ui.text(stats.total_cycles.to_string());
+                ui.next_column();
+                ui.text(format!(
+                    "{:.1}",
+                    Self::percentage(stats.total_cycles, cycles)
+                ));
+                ui.next_column();
+            }
+        }
+        ui.columns(1, "", false);
+
+        request
+    }
+
+    fn header(ui: &Ui, names: &[&str]) {
+        for name in names {
+            ui.text(name);
+            ui.next_column();
+        }
+        ui.separator();
+    }
+
+    fn percentage(value: u64, total: u64) -> f64 {
+        if total == 0 {
+            0.0
+        } else {
+            value as f64 * 100.0 / total as f64
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::save_slots::{self, SaveSlots, Thumbnail};
+use imgui::{DrawListMut, Ui};
+use std::time::SystemTime;
+
+pub enum SlotRequest {
+    None,
+    Save(usize),
+    Load(usize),
+}
+
+/// Lists the save slots of the current ROM with thumbnail and age.
+pub struct SlotBrowser {}
+
+impl SlotBrowser {
+    const THUMBNAIL_WIDTH: f32 = 128.0;
+
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn build(&mut self, ui: &Ui, slots: &SaveSlots) -> SlotRequest {
+        if !slots.is_available() {
+            ui.text("Save slots are only available for ROMs.");
+            return SlotRequest::None;
+        }
+
+        let mut request = SlotRequest::None;
+        let now = SystemTime::now();
+        for (slot, info) in slots.slots().iter().enumerate() {
+            let id = ui.push_id(slot as i32);
+            match info.as_ref().and_then(|info| info.thumbnail.as_ref()) {
+                Some(thumbnail) => Self::thumbnail_image(ui, thumbnail),
+                None => ui.dummy([Self::THUMBNAIL_WIDTH, Self::THUMBNAIL_WIDTH / 2.0]),
+            }
+            ui.same_line();
+            ui.group(|| {
+                ui.text(format!("Slot {}", slot + 1));
+                match info {
+                    Some(info) => ui.text(save_slots::format_age(info.timestamp, now)),
+                    None => ui.text_disabled("Empty"),
+                }
+                ui.text_disabled(format!("Shift/Ctrl + F{}", slot + 1));
+                if ui.small_button("Save") {
+                    request = SlotRequest::Save(slot);
+                }
+                if info.is_some() {
+                    ui.same_line();
+                    if ui.small_button("Load") {
+                        request = SlotRequest::Load(slot);
+                    }
+                }
+            });
+            id.pop();
+            ui.separator();
+        }
+        request
+    }
+
+    fn thumbnail_image(ui: &Ui, thumbnail: &Thumbnail) {
+        let scale = Self::THUMBNAIL_WIDTH / thumbnail.width as f32;
+        let origin = ui.cursor_screen_pos();
+        let size = [Self::THUMBNAIL_WIDTH, thumbnail.height as f32 * scale];
+        {
+            let draw_list = ui.get_window_draw_list();
+
+            // Merge horizontal runs of the same color to keep the number of vertices low
+            for (y, row) in thumbnail.pixels.chunks(thumbnail.width).enumerate() {
+                let mut start = 0;
+                for x in 1..=row.len() {
+                    if x == row.len() || row[x] != row[start] {
+                        Self::rect(
+                            &draw_list,
+                            [
+                                origin[0] + start as f32 * scale,
+                                origin[1] + y as f32 * scale,
+                            ],
+                            [(x - start) as f32 * scale, scale],
+                            row[start],
+                        );
+                        start = x;
+                    }
+                }
+            }
+        }
+        ui.dummy(size);
+    }
+
+    fn rect(draw_list: &DrawListMut, pos: [f32; 2], size: [f32; 2], color: [u8; 3]) {
+        let color = [
+            color[0] as f32 / 255.0,
+            color[1] as f32 / 255.0,
+            color[2] as f32 / 255.0,
+            1.0,
+        ];
+        draw_list
+            .add_rect(pos, [pos[0] + size[0], pos[1] + size[1]], color)
+            .filled(true)
+            .build();
+    }
+}
@@ -0,0 +1,143 @@
+use super::ColorSettings;
+use crate::cpu::CPU;
+use imgui::{Slider, Ui};
+
+/// Renders the memory at I or any chosen address as sprite.
+pub struct SpriteViewer {
+    follow_i: bool,
+    address: u16,
+    address_input: String,
+    layout: usize,
+    height: u32,
+    two_planes: bool,
+}
+
+impl SpriteViewer {
+    const LAYOUTS: [&'static str; 2] = ["8xN", "16x16"];
+    const PIXEL_SIZE: f32 = 8.0;
+    const WIDTH_TEXTBOX_ADDRESS: f32 = 40.0;
+
+    pub fn new() -> Self {
+        Self {
+            follow_i: true,
+            address: 0,
+            address_input: String::from("0000"),
+            layout: 0,
+            height: 15,
+            two_planes: false,
+        }
+    }
+
+    fn sprite_size(&self) -> (usize, usize) {
+        if self.layout == 0 {
+            (8, self.height as usize)
+        } else {
+            (16, 16)
+        }
+    }
+
+    fn set_address(&mut self, address: u16) {
+        self.follow_i = false;
+        self.address = address;
+        self.address_input = format!("{:04X}", address);
+    }
+
+    pub fn build(&mut self, ui: &Ui, cpu: &CPU, colors: &ColorSettings) {
+        if self.follow_i && self.address != cpu.I() {
+            self.address = cpu.I();
+            self.address_input = format!("{:04X}", self.address);
+        }
+
+        let (width, height) = self.sprite_size();
+        let planes = if self.two_planes { 2 } else { 1 };
+        let sprite_len = (width / 8 * height * planes) as u16;
+
+        // Address selection
+        ui.checkbox("Follow I", &mut self.follow_i);
+        ui.same_line();
+        let item_width = ui.push_item_width(Self::WIDTH_TEXTBOX_ADDRESS);
+        if ui
+            .input_text("##sprite_address", &mut self.address_input)
+            .chars_hexadecimal(true)
+            .chars_uppercase(true)
+            .read_only(self.follow_i)
+            .build()
+        {
+            if let Ok(address) = u16::from_str_radix(&self.address_input, 16) {
+                self.address = address;
+            }
+        }
+        item_width.pop(ui);
+        ui.same_line();
+        if ui.small_button("<<") {
+            self.set_address(self.address.wrapping_sub(sprite_len));
+        }
+        ui.same_line();
+        if ui.small_button("<") {
+            self.set_address(self.address.wrapping_sub(1));
+        }
+        ui.same_line();
+        if ui.small_button(">") {
+            self.set_address(self.address.wrapping_add(1));
+        }
+        ui.same_line();
+        if ui.small_button(">>") {
+            self.set_address(self.address.wrapping_add(sprite_len));
+        }
+
+        // Layout
+        let item_width = ui.push_item_width(60.0);
+        ui.combo_simple_string("##sprite_layout", &mut self.layout, &Self::LAYOUTS);
+        item_width.pop(ui);
+        if self.layout == 0 {
+            ui.same_line();
+            let item_width = ui.push_item_width(80.0);
+            Slider::new("##sprite_height", 1, 15)
+                .display_format("N = %d")
+                .build(ui, &mut self.height);
+            item_width.pop(ui);
+        }
+        ui.checkbox("Two planes (XO-CHIP)", &mut self.two_planes);
+        ui.separator();
+
+        // Sprite
+        let palette: Vec<[f32; 3]> = colors.iter().cloned().collect();
+        let pixels = cpu.sprite_pixels(self.address, width, height, planes);
+        let origin = ui.cursor_screen_pos();
+        let size = [
+            width as f32 * Self::PIXEL_SIZE,
+            height as f32 * Self::PIXEL_SIZE,
+        ];
+        {
+            let draw_list = ui.get_window_draw_list();
+            for (idx, value) in pixels.iter().enumerate() {
+                let color = palette[*value as usize];
+                let p1 = [
+                    origin[0] + (idx % width) as f32 * Self::PIXEL_SIZE,
+                    origin[1] + (idx / width) as f32 * Self::PIXEL_SIZE,
+                ];
+                let p2 = [p1[0] + Self::PIXEL_SIZE, p1[1] + Self::PIXEL_SIZE];
+                draw_list
+                    .add_rect(p1, p2, [color[0], color[1], color[2], 1.0])
+                    .filled(true)
+                    .build();
+            }
+        }
+        ui.dummy(size);
+
+        if ui.is_item_hovered() {
+            let mouse_pos = ui.io().mouse_pos;
+            let x = ((mouse_pos[0] - origin[0]) / Self::PIXEL_SIZE) as usize;
+            let y = ((mouse_pos[1] - origin[1]) / Self::PIXEL_SIZE) as usize;
+            if x < width && y < height {
+                let byte_addr = self.address as usize + y * width / 8 + x / 8;
+                ui.tooltip_text(format!("{}, {} @ {:04X}", x, y, byte_addr as u16));
+            }
+        }
+        ui.text(format!(
+            "{:04X} - {:04X}",
+            self.address,
+            self.address.wrapping_add(sprite_len - 1)
+        ));
+    }
+}
@@ -0,0 +1,120 @@
+use super::{Color, ColorSettings};
+use crate::video_memory::{Plane, VideoMemory};
+use imgui::{DrawListMut, Slider, Ui};
+
+/// Shows both planes of the video memory as separate images.
+pub struct VideoInspector {
+    scale: u32,
+}
+
+impl VideoInspector {
+    pub fn new() -> Self {
+        Self { scale: 2 }
+    }
+
+    pub fn build(&mut self, ui: &Ui, vmem: &VideoMemory, colors: &ColorSettings) {
+        ui.text(format!(
+            "Mode: {:?}, Plane: {:?}",
+            vmem.video_mode,
+            vmem.current_plane()
+        ));
+        ui.text(format!(
+            "Resolution: {}x{} (Render: {}x{})",
+            vmem.width(),
+            vmem.height(),
+            vmem.render_width(),
+            vmem.render_height()
+        ));
+        let item_width = ui.push_item_width(100.0);
+        Slider::new("Scale", 1, 4).build(ui, &mut self.scale);
+        item_width.pop(ui);
+        ui.separator();
+
+        for (plane, name, color) in &[
+            (Plane::First, "Plane 1", Color::Plane1),
+            (Plane::Second, "Plane 2", Color::Plane2),
+        ] {
+            ui.text(name);
+            self.plane_image(
+                ui,
+                vmem,
+                *plane,
+                colors.get(Color::Background),
+                colors.get(*color),
+            );
+        }
+    }
+
+    fn plane_image(
+        &self,
+        ui: &Ui,
+        vmem: &VideoMemory,
+        plane: Plane,
+        color_bg: [f32; 3],
+        color_fg: [f32; 3],
+    ) {
+        let scale = self.scale as f32;
+        let origin = ui.cursor_screen_pos();
+        let size = [
+            vmem.render_width() as f32 * scale,
+            vmem.render_height() as f32 * scale,
+        ];
+        {
+            let draw_list = ui.get_window_draw_list();
+            Self::rect(&draw_list, origin, [0.0, 0.0], size, color_bg);
+
+            // Merge horizontal runs of set pixels to keep the number of vertices low
+            for y in 0..vmem.render_height() {
+                let mut run_start = None;
+                for x in 0..=vmem.render_width() {
+                    let set =
+                        x < vmem.render_width() && vmem.get_index_plane(plane, vmem.to_index(x, y));
+                    match (set, run_start) {
+                        (true, None) => run_start = Some(x),
+                        (false, Some(start)) => {
+                            Self::rect(
+                                &draw_list,
+                                origin,
+                                [start as f32 * scale, y as f32 * scale],
+                                [(x - start) as f32 * scale, scale],
+                                color_fg,
+                            );
+                            run_start = None;
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
+        ui.dummy(size);
+
+        if ui.is_item_hovered() {
+            let mouse_pos = ui.io().mouse_pos;
+            let x = ((mouse_pos[0] - origin[0]) / scale) as usize;
+            let y = ((mouse_pos[1] - origin[1]) / scale) as usize;
+            if x < vmem.render_width() && y < vmem.render_height() {
+                let (logical_x, logical_y) = vmem.render_to_logical(x, y);
+                let value = vmem.get_index_plane(plane, vmem.to_index(x, y));
+                ui.tooltip_text(format!(
+                    "Logical: {}, {}\nRender: {}, {}\nValue: {}",
+                    logical_x, logical_y, x, y, value as u8
+                ));
+            }
+        }
+    }
+
+    fn rect(
+        draw_list: &DrawListMut,
+        origin: [f32; 2],
+        pos: [f32; 2],
+        size: [f32; 2],
+        color: [f32; 3],
+    ) {
+        let p1 = [origin[0] + pos[0], origin[1] + pos[1]];
+        let p2 = [p1[0] + size[0], p1[1] + size[1]];
+        draw_list
+            .add_rect(p1, p2, [color[0], color[1], color[2], 1.0])
+            .filled(true)
+            .build();
+    }
+}
@@ -0,0 +1,25 @@
+//! The interpreter core without any frontend, used by the pich8 application as well as bindings for other languages.
+
+pub mod cpu;
+pub mod emulator_core;
+pub mod state_file;
+pub mod symbols;
+pub mod video_memory;
+
+use std::panic::{self, AssertUnwindSafe};
+
+/// Runs the function and turns a panic into an error, for bindings where unwinding into C is
+/// undefined behavior.
+pub fn catch_panic<T, E: ToString>(f: impl FnOnce() -> Result<T, E>) -> Result<T, String> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(payload) => {
+            let msg = payload
+                .downcast_ref::<&str>()
+                .map(|msg| msg.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Err(format!("Internal error: {}", msg))
+        }
+    }
+}
@@ -1,34 +1,184 @@
 #![cfg_attr(not(any(test, debug_assertions)), windows_subsystem = "windows")]
 
-mod cpu;
+mod config;
+#[cfg(feature = "gui")]
+mod control_server;
+#[cfg(feature = "gui")]
+mod dap_server;
+#[cfg(feature = "gui")]
 mod dialog_handler;
+#[cfg(feature = "gui")]
 mod display;
+#[cfg(feature = "gui")]
+mod emulation_thread;
+#[cfg(feature = "gui")]
 mod emulator;
+#[cfg(feature = "gui")]
+mod expression;
+mod file_type;
+#[cfg(feature = "gui")]
 mod fps_counter;
+#[cfg(feature = "gui")]
+mod gdb_stub;
+#[cfg(feature = "gui")]
 mod gui;
+mod octo_assembler;
+mod octo_cartridge;
+mod octo_options;
+#[cfg(feature = "gui")]
+mod profiler;
+#[cfg(feature = "gui")]
+mod remote_debug;
+mod rom_database;
+#[cfg(feature = "gui")]
+mod save_slots;
+#[cfg(feature = "gui")]
 mod sound;
-mod video_memory;
 
 #[cfg(feature = "rom-download")]
 mod rom_downloader;
+#[cfg(feature = "tui")]
+mod tui;
 
+use config::{Config, Overrides};
+#[cfg(feature = "gui")]
 use emulator::Emulator;
-use getopts::Options;
+use emulator_core::Speed;
+use getopts::{Matches, Options};
+#[cfg(feature = "gui")]
+use pich8::symbols;
+use pich8::{cpu, emulator_core, state_file, video_memory};
 use std::env;
+use std::path::PathBuf;
 
 const OPT_VSYNC: &str = "vsync";
+const OPT_GDB: &str = "gdb";
+const OPT_DAP: &str = "dap";
+const OPT_DAP_PORT: &str = "dap-port";
+const OPT_CONTROL_SOCKET: &str = "control-socket";
+const OPT_IPF: &str = "ipf";
+const OPT_SPEED: &str = "speed";
+const OPT_VOLUME: &str = "volume";
+const OPT_MUTE: &str = "mute";
+const OPT_CONFIG: &str = "config";
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     let mut opts = Options::new();
     opts.optflag("", OPT_VSYNC, "Turn on vsync");
+    opts.optopt(
+        "",
+        OPT_GDB,
+        "Listen for GDB remote debugging on the given localhost port",
+        "PORT",
+    );
+    opts.optflag("", OPT_DAP, "Serve the Debug Adapter Protocol on stdio");
+    opts.optopt(
+        "",
+        OPT_DAP_PORT,
+        "Serve the Debug Adapter Protocol on the given localhost port",
+        "PORT",
+    );
+    opts.optopt(
+        "",
+        OPT_CONTROL_SOCKET,
+        "Serve the JSON-RPC control API on the given Unix socket path or localhost port",
+        "PATH|PORT",
+    );
+    opts.optopt(
+        "",
+        OPT_IPF,
+        "Run the given number of instructions per 60 Hz frame instead of a fixed frequency",
+        "N",
+    );
+    opts.optopt(
+        "",
+        OPT_SPEED,
+        "Run the given number of instructions per second",
+        "HZ",
+    );
+    opts.optopt("", OPT_VOLUME, "Set the audio volume in percent", "PERCENT");
+    opts.optflag("", OPT_MUTE, "Mute the audio");
+    opts.optopt(
+        "",
+        OPT_CONFIG,
+        "Load and save the settings in the given file instead of the platform config directory",
+        "PATH",
+    );
 
-    let mut vsync = false;
-    if let Ok(matches) = opts.parse(args) {
-        vsync = matches.opt_present(OPT_VSYNC);
+    let mut overrides = Overrides::default();
+    let mut config_path = Config::path();
+    let matches = opts.parse(args).ok();
+    if let Some(matches) = &matches {
+        overrides.vsync = matches.opt_present(OPT_VSYNC);
+        overrides.mute = matches.opt_present(OPT_MUTE);
+        if let Some(hz) = matches.opt_str(OPT_SPEED) {
+            let hz = hz.parse().expect("Invalid speed");
+            overrides.speed = Some(Speed::Frequency(hz));
+        }
+        if let Some(ipf) = matches.opt_str(OPT_IPF) {
+            let ipf = ipf.parse().expect("Invalid instructions per frame");
+            overrides.speed = Some(Speed::InstructionsPerFrame(ipf));
+        }
+        if let Some(volume) = matches.opt_str(OPT_VOLUME) {
+            let volume: f32 = volume.parse().expect("Invalid volume");
+            overrides.volume = Some(volume.clamp(0.0, 100.0) / 100.0);
+        }
+        if let Some(path) = matches.opt_str(OPT_CONFIG) {
+            config_path = Some(PathBuf::from(path));
+        }
+
+        // Run in the terminal instead of a window with "pich8 tui <rom>"
+        #[cfg(feature = "tui")]
+        if matches.free.get(1).map(String::as_str) == Some("tui") {
+            let rom = matches.free.get(2).expect("Usage: pich8 tui <rom>");
+            let mut config = match &config_path {
+                Some(path) => Config::load(path).unwrap_or_else(|msg| {
+                    eprintln!("{}", msg);
+                    Config::default()
+                }),
+                None => Config::default(),
+            };
+            overrides.apply(&mut config);
+            if let Err(e) = tui::Tui::run(rom, &config, overrides.speed) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
     }
+    run_window(matches, config_path, overrides);
+}
 
+#[cfg(feature = "gui")]
+fn run_window(matches: Option<Matches>, config_path: Option<PathBuf>, overrides: Overrides) {
     let event_loop = glium::glutin::event_loop::EventLoop::new();
-    let mut emu = Emulator::new(&event_loop, vsync).expect("Failed to create emulator");
+    let mut emu =
+        Emulator::new(&event_loop, config_path, overrides).expect("Failed to create emulator");
+    if let Some(matches) = matches {
+        if let Some(port) = matches.opt_str(OPT_GDB) {
+            let port = port.parse().expect("Invalid GDB port");
+            emu.start_gdb_stub(port).expect("Failed to start GDB stub");
+        }
+        if matches.opt_present(OPT_DAP) {
+            emu.start_dap_stdio();
+        }
+        if let Some(port) = matches.opt_str(OPT_DAP_PORT) {
+            let port = port.parse().expect("Invalid DAP port");
+            emu.start_dap_server(port)
+                .expect("Failed to start DAP server");
+        }
+        if let Some(address) = matches.opt_str(OPT_CONTROL_SOCKET) {
+            emu.start_control_server(&address)
+                .expect("Failed to start control server");
+        }
+    }
     event_loop.run(move |event, _, ctrl_flow| emu.handle_event(event, ctrl_flow));
 }
+
+/// Built without the window, only the terminal frontend is available.
+#[cfg(not(feature = "gui"))]
+fn run_window(_matches: Option<Matches>, _config_path: Option<PathBuf>, _overrides: Overrides) {
+    eprintln!("Usage: pich8 tui <rom>");
+    std::process::exit(1);
+}
@@ -0,0 +1,1199 @@
+//! Assembler for Octo, the high-level assembly language for CHIP-8, SUPER-CHIP and XO-CHIP,
+//! to run programs distributed as source, like Octo cartridges.
+//!
+//! Supports the statements of the Octo manual including control flow, macros, `:calc`,
+//! `:stringmode` and `:assert`. The program starts at `main`, unless it's the first code a jump
+//! to it is placed at 0x200.
+
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts::{E, PI};
+
+const START: usize = 0x200;
+const MEMORY_SIZE: usize = 0x10000;
+/// Limits macro expansion, to catch macros invoking themselves
+const MAX_EXPANSIONS: usize = 100_000;
+
+/// Assembled program with the addresses of its labels.
+pub struct Program {
+    pub rom: Vec<u8>,
+    #[cfg(feature = "gui")]
+    pub labels: Vec<(String, u16)>,
+    /// Address and source line of each instruction, instructions of macros belong to the invocation
+    #[cfg(feature = "gui")]
+    pub lines: Vec<(u16, usize)>,
+}
+
+#[cfg(feature = "gui")]
+impl Program {
+    /// Address of the first instruction on the given line, or the next line with instructions.
+    /// Returns the line the address belongs to as well.
+    pub fn address_of_line(&self, line: usize) -> Option<(usize, u16)> {
+        self.lines
+            .iter()
+            .filter(|(_, l)| *l >= line)
+            .min_by_key(|(_, l)| *l)
+            .map(|(addr, l)| (*l, *addr))
+    }
+
+    pub fn line_of_address(&self, addr: u16) -> Option<usize> {
+        // Code placed later with :org replaces earlier code
+        self.lines
+            .iter()
+            .rev()
+            .find(|(a, _)| *a == addr)
+            .map(|(_, line)| *line)
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Program, String> {
+    Assembler::new(tokenize(source)?).run()
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    /// Quoted string, e.g. the alphabet of a string mode
+    string: bool,
+    line: usize,
+}
+
+fn tokenize(source: &str) -> Result<VecDeque<Token>, String> {
+    let mut tokens = VecDeque::new();
+    for (line_no, line) in source.lines().enumerate() {
+        let line_no = line_no + 1;
+        let mut chars = line.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == '#' {
+                break;
+            } else if c == '"' {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => text.push('\n'),
+                            Some('t') => text.push('\t'),
+                            Some('0') => text.push('\0'),
+                            Some(c) => text.push(c),
+                            None => text.push('\\'),
+                        },
+                        Some(c) => text.push(c),
+                        None => return Err(format!("Line {}: Missing closing quote", line_no)),
+                    }
+                }
+                tokens.push_back(Token {
+                    text,
+                    string: true,
+                    line: line_no,
+                });
+            } else {
+                let mut text = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+                tokens.push_back(Token {
+                    text,
+                    string: false,
+                    line: line_no,
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    match text.as_bytes() {
+        [b'v', digit] | [b'V', digit] => (*digit as char).to_digit(16).map(|reg| reg as u8),
+        _ => None,
+    }
+}
+
+/// How an address is written into the program once known.
+#[derive(Clone, Copy)]
+enum Fixup {
+    /// Lower 12 bits of an instruction
+    Addr12,
+    /// Two bytes, like the operand of `i := long`
+    Addr16,
+    /// Given nibble followed by the upper 4 bits of a 12 bit address
+    Unpack(u8),
+    HighByte,
+    LowByte,
+}
+
+/// Use of a label before its definition.
+struct Reference {
+    pos: usize,
+    name: String,
+    fixup: Fixup,
+    line: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Comparison {
+    Equal,
+    Key,
+    Less,
+    Greater,
+}
+
+enum Operand {
+    Register(u8),
+    Immediate(u8),
+}
+
+struct Condition {
+    x: u8,
+    comparison: Comparison,
+    operand: Operand,
+    /// `!=`, `-key`, `>=` and `<=` are the negation of `==`, `key`, `<` and `>`
+    negated: bool,
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+    calls: u32,
+}
+
+struct Loop {
+    start: usize,
+    /// Jumps out of the loop by `while`
+    breaks: Vec<usize>,
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    line: usize,
+    memory: Vec<u8>,
+    here: usize,
+    end: usize,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    /// Body for each character of a string mode, with the index of the character in the alphabet
+    string_modes: HashMap<String, HashMap<char, (usize, Vec<Token>)>>,
+    references: Vec<Reference>,
+    /// Jumps to the `else` or `end` of the open `if ... begin` blocks
+    branches: Vec<usize>,
+    loops: Vec<Loop>,
+    expansions: usize,
+    /// The program starts with a jump to main
+    jump_to_main: bool,
+    lines: Vec<(u16, usize)>,
+}
+
+impl Assembler {
+    fn new(tokens: VecDeque<Token>) -> Self {
+        Self {
+            tokens,
+            line: 1,
+            memory: vec![0; MEMORY_SIZE],
+            here: START,
+            end: START,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            string_modes: HashMap::new(),
+            references: Vec::new(),
+            branches: Vec::new(),
+            loops: Vec::new(),
+            expansions: 0,
+            jump_to_main: false,
+            lines: Vec::new(),
+        }
+    }
+
+    fn run(mut self) -> Result<Program, String> {
+        self.assemble()
+            .map_err(|msg| format!("Line {}: {}", self.line, msg))?;
+
+        Ok(Program {
+            rom: self.memory[START..self.end].to_vec(),
+            #[cfg(feature = "gui")]
+            labels: {
+                let mut labels: Vec<(String, u16)> = self.labels.into_iter().collect();
+                labels.sort_by_key(|(name, addr)| (*addr, name.clone()));
+                labels
+            },
+            #[cfg(feature = "gui")]
+            lines: self.lines,
+        })
+    }
+
+    fn assemble(&mut self) -> Result<(), String> {
+        // Leave room for a jump to main, which is dropped if main comes first
+        self.inst(0x1000)?;
+        self.jump_to_main = true;
+        self.lines.clear();
+
+        while let Some(token) = self.tokens.pop_front() {
+            self.line = token.line;
+            self.statement(token)?;
+        }
+
+        if !self.branches.is_empty() {
+            return Err(String::from("Missing 'end' of an 'if ... begin' block"));
+        }
+        if !self.loops.is_empty() {
+            return Err(String::from("Missing 'again' of a 'loop'"));
+        }
+        let main = *self
+            .labels
+            .get("main")
+            .ok_or_else(|| String::from("The program is missing a 'main' label"))?;
+        if self.jump_to_main {
+            self.patch(START, Fixup::Addr12, main)?;
+        }
+        for reference in std::mem::take(&mut self.references) {
+            self.line = reference.line;
+            let addr = *self
+                .labels
+                .get(&reference.name)
+                .ok_or_else(|| format!("Undefined name '{}'", reference.name))?;
+            self.patch(reference.pos, reference.fixup, addr)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), String> {
+        if token.string {
+            return Err(format!("Unexpected string \"{}\"", token.text));
+        }
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                if name == "main" && self.jump_to_main && self.end == START + 2 {
+                    self.here = START;
+                    self.end = START;
+                    self.jump_to_main = false;
+                }
+                self.define_label(name, self.here)?;
+            }
+            ":next" => {
+                let name = self.name()?;
+                self.define_label(name, self.here + 1)?;
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let reg = self.next()?;
+                let reg = match self.register_of(&reg) {
+                    Some(reg) => reg,
+                    None => self
+                        .value_of(&reg)?
+                        .filter(|reg| (0..16).contains(reg))
+                        .ok_or_else(|| format!("'{}' is not a register", reg.text))?
+                        as u8,
+                };
+                self.aliases.insert(name, reg);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let token = self.next()?;
+                let value = self
+                    .value_of(&token)?
+                    .ok_or_else(|| format!("Undefined name '{}'", token.text))?;
+                self.define_constant(name, value as f64)?;
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let block = self.block()?;
+                let value = self.calc(&block)?;
+                // Unlike constants, calculated values may be redefined
+                self.constants.insert(name, value);
+            }
+            ":macro" => {
+                let name = self.name()?;
+                let mut args = Vec::new();
+                while self.peek() != Some("{") {
+                    args.push(self.name()?);
+                }
+                let body = self.block()?;
+                self.macros.insert(
+                    name,
+                    Macro {
+                        args,
+                        body,
+                        calls: 0,
+                    },
+                );
+            }
+            ":stringmode" => {
+                let name = self.name()?;
+                let alphabet = self.next()?;
+                if !alphabet.string {
+                    return Err(String::from("Expected the alphabet of the string mode"));
+                }
+                let body = self.block()?;
+                let mode = self.string_modes.entry(name).or_default();
+                for (index, c) in alphabet.text.chars().enumerate() {
+                    mode.insert(c, (index, body.clone()));
+                }
+            }
+            ":unpack" => {
+                let kind = self.next()?;
+                let target = self.next()?;
+                if kind.text == "long" {
+                    self.emit(&[0x60, 0x00, 0x61, 0x00])?;
+                    self.reference(self.here - 3, Fixup::HighByte, &target)?;
+                } else {
+                    let nibble = self.value_of(&kind)?.filter(|n| (0..16).contains(n));
+                    let nibble = nibble.ok_or_else(|| String::from("Expected a nibble"))?;
+                    self.emit(&[0x60, 0x00, 0x61, 0x00])?;
+                    self.reference(self.here - 3, Fixup::Unpack(nibble as u8), &target)?;
+                }
+                self.reference(self.here - 1, Fixup::LowByte, &target)?;
+            }
+            ":org" => {
+                let addr = self.immediate(0, 0xFFFF)? as usize;
+                if addr < START {
+                    return Err(format!("Can't place code below 0x{:X}", START));
+                }
+                self.here = addr;
+            }
+            ":byte" => {
+                let value = self.immediate(-128, 255)?;
+                self.emit(&[value as u8])?;
+            }
+            ":pointer" => {
+                let target = self.next()?;
+                self.emit(&[0, 0])?;
+                self.reference(self.here - 2, Fixup::Addr16, &target)?;
+            }
+            ":call" => self.address_inst(0x2000)?,
+            ":assert" => {
+                let message = match self.tokens.front() {
+                    Some(token) if token.string => Some(self.next()?.text),
+                    _ => None,
+                };
+                if self.immediate(i64::MIN, i64::MAX)? == 0 {
+                    return Err(format!(
+                        "Assertion failed{}",
+                        message.map(|msg| format!(": {}", msg)).unwrap_or_default()
+                    ));
+                }
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "clear" => self.inst(0x00E0)?,
+            "return" | ";" => self.inst(0x00EE)?,
+            "hires" => self.inst(0x00FF)?,
+            "lores" => self.inst(0x00FE)?,
+            "exit" => self.inst(0x00FD)?,
+            "scroll-left" => self.inst(0x00FC)?,
+            "scroll-right" => self.inst(0x00FB)?,
+            "scroll-down" => {
+                let n = self.immediate(0, 15)? as u16;
+                self.inst(0x00C0 | n)?;
+            }
+            "scroll-up" => {
+                let n = self.immediate(0, 15)? as u16;
+                self.inst(0x00D0 | n)?;
+            }
+            "audio" => self.inst(0xF002)?,
+            "plane" => {
+                let n = self.immediate(0, 15)? as u16;
+                self.inst(0xF001 | n << 8)?;
+            }
+            "bcd" => self.register_inst(0xF033)?,
+            "saveflags" => self.register_inst(0xF075)?,
+            "loadflags" => self.register_inst(0xF085)?,
+            "save" | "load" => {
+                let x = self.register()? as u16;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()? as u16;
+                    let op = if token.text == "save" { 0x5002 } else { 0x5003 };
+                    self.inst(op | x << 8 | y << 4)?;
+                } else {
+                    let op = if token.text == "save" { 0xF055 } else { 0xF065 };
+                    self.inst(op | x << 8)?;
+                }
+            }
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let n = self.immediate(0, 15)? as u16;
+                self.inst(0xD000 | x << 8 | y << 4 | n)?;
+            }
+            "jump" => self.address_inst(0x1000)?,
+            "jump0" => self.address_inst(0xB000)?,
+            "native" => self.address_inst(0x0000)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let op = match token.text.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.register_inst(op)?;
+            }
+            "i" => self.index_statement()?,
+            "if" => {
+                let condition = self.condition()?;
+                match self.next()?.text.as_str() {
+                    "then" => self.skip(&condition, false)?,
+                    "begin" => {
+                        self.skip(&condition, true)?;
+                        self.branches.push(self.here);
+                        self.inst(0x1000)?;
+                    }
+                    other => return Err(format!("Expected 'then' or 'begin', found '{}'", other)),
+                }
+            }
+            "else" => {
+                let branch = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| String::from("'else' without 'if ... begin'"))?;
+                self.branches.push(self.here);
+                self.inst(0x1000)?;
+                self.patch(branch, Fixup::Addr12, self.here as u16)?;
+            }
+            "end" => {
+                let branch = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| String::from("'end' without 'if ... begin'"))?;
+                self.patch(branch, Fixup::Addr12, self.here as u16)?;
+            }
+            "loop" => self.loops.push(Loop {
+                start: self.here,
+                breaks: Vec::new(),
+            }),
+            "while" => {
+                let condition = self.condition()?;
+                self.skip(&condition, true)?;
+                let here = self.here;
+                self.loops
+                    .last_mut()
+                    .ok_or_else(|| String::from("'while' outside of a loop"))?
+                    .breaks
+                    .push(here);
+                self.inst(0x1000)?;
+            }
+            "again" => {
+                let lp = self
+                    .loops
+                    .pop()
+                    .ok_or_else(|| String::from("'again' without 'loop'"))?;
+                self.inst(0x1000)?;
+                self.patch(self.here - 2, Fixup::Addr12, lp.start as u16)?;
+                for pos in lp.breaks {
+                    self.patch(pos, Fixup::Addr12, self.here as u16)?;
+                }
+            }
+            _ => {
+                if let Some(x) = self.register_of(&token) {
+                    self.register_statement(x)?;
+                } else if let Some(value) = parse_number(&token.text) {
+                    self.emit(&[Self::check_range(value, -128, 255)? as u8])?;
+                } else if self.macros.contains_key(&token.text) {
+                    self.expand_macro(&token)?;
+                } else if self.string_modes.contains_key(&token.text) {
+                    self.expand_string_mode(&token)?;
+                } else if Self::is_name(&token.text) {
+                    // Subroutine call
+                    self.inst(0x2000)?;
+                    self.reference(self.here - 2, Fixup::Addr12, &token)?;
+                } else {
+                    return Err(format!("Unexpected '{}'", token.text));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Statements starting with `i`.
+    fn index_statement(&mut self) -> Result<(), String> {
+        match self.next()?.text.as_str() {
+            ":=" => match self.peek() {
+                Some("long") => {
+                    self.next()?;
+                    let target = self.next()?;
+                    self.emit(&[0xF0, 0x00, 0x00, 0x00])?;
+                    self.reference(self.here - 2, Fixup::Addr16, &target)
+                }
+                Some("hex") => {
+                    self.next()?;
+                    self.register_inst(0xF029)
+                }
+                Some("bighex") => {
+                    self.next()?;
+                    self.register_inst(0xF030)
+                }
+                _ => self.address_inst(0xA000),
+            },
+            "+=" => self.register_inst(0xF01E),
+            other => Err(format!("Unknown operator 'i {}'", other)),
+        }
+    }
+
+    /// Statements starting with a register.
+    fn register_statement(&mut self, x: u8) -> Result<(), String> {
+        let x16 = (x as u16) << 8;
+        let op = self.next()?;
+        let rhs = self.next()?;
+        let y = self.register_of(&rhs).map(|y| x16 | (y as u16) << 4);
+        match (op.text.as_str(), y) {
+            (":=", Some(xy)) => self.inst(0x8000 | xy),
+            ("+=", Some(xy)) => self.inst(0x8004 | xy),
+            ("-=", Some(xy)) => self.inst(0x8005 | xy),
+            ("=-", Some(xy)) => self.inst(0x8007 | xy),
+            ("|=", Some(xy)) => self.inst(0x8001 | xy),
+            ("&=", Some(xy)) => self.inst(0x8002 | xy),
+            ("^=", Some(xy)) => self.inst(0x8003 | xy),
+            (">>=", Some(xy)) => self.inst(0x8006 | xy),
+            ("<<=", Some(xy)) => self.inst(0x800E | xy),
+            (":=", None) => match rhs.text.as_str() {
+                "key" => self.inst(0xF00A | x16),
+                "delay" => self.inst(0xF007 | x16),
+                "random" => {
+                    let mask = self.immediate(-128, 255)? as u8;
+                    self.inst(0xC000 | x16 | mask as u16)
+                }
+                _ => {
+                    let value = self.immediate_of(&rhs, -128, 255)? as u8;
+                    self.inst(0x6000 | x16 | value as u16)
+                }
+            },
+            ("+=", None) => {
+                let value = self.immediate_of(&rhs, -128, 255)? as u8;
+                self.inst(0x7000 | x16 | value as u16)
+            }
+            ("-=", None) => {
+                let value = self.immediate_of(&rhs, -128, 255)? as u8;
+                self.inst(0x7000 | x16 | value.wrapping_neg() as u16)
+            }
+            _ => Err(format!(
+                "Unknown operator 'v{:X} {} {}'",
+                x, op.text, rhs.text
+            )),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        let x = self.register()?;
+        let op = self.next()?;
+        let (comparison, negated) = match op.text.as_str() {
+            "key" => (Comparison::Key, false),
+            "-key" => (Comparison::Key, true),
+            "==" => (Comparison::Equal, false),
+            "!=" => (Comparison::Equal, true),
+            "<" => (Comparison::Less, false),
+            ">=" => (Comparison::Less, true),
+            ">" => (Comparison::Greater, false),
+            "<=" => (Comparison::Greater, true),
+            other => return Err(format!("Unknown comparison '{}'", other)),
+        };
+        let operand = if comparison == Comparison::Key {
+            Operand::Register(0)
+        } else {
+            let rhs = self.next()?;
+            match self.register_of(&rhs) {
+                Some(y) => Operand::Register(y),
+                None => Operand::Immediate(self.immediate_of(&rhs, -128, 255)? as u8),
+            }
+        };
+        Ok(Condition {
+            x,
+            comparison,
+            operand,
+            negated,
+        })
+    }
+
+    /// Emits instructions which skip the next one if the condition has the given result.
+    fn skip(&mut self, condition: &Condition, when: bool) -> Result<(), String> {
+        let when = when != condition.negated;
+        let x = (condition.x as u16) << 8;
+        match (condition.comparison, &condition.operand) {
+            (Comparison::Key, _) => self.inst(if when { 0xE09E } else { 0xE0A1 } | x),
+            (Comparison::Equal, Operand::Immediate(nn)) => {
+                self.inst(if when { 0x3000 } else { 0x4000 } | x | *nn as u16)
+            }
+            (Comparison::Equal, Operand::Register(y)) => {
+                self.inst(if when { 0x5000 } else { 0x9000 } | x | (*y as u16) << 4)
+            }
+            (comparison, operand) => {
+                // VF is 0 exactly if the comparison holds
+                let less = comparison == Comparison::Less;
+                match operand {
+                    Operand::Register(y) => {
+                        let (a, b) = if less {
+                            (x >> 8, *y as u16)
+                        } else {
+                            (*y as u16, x >> 8)
+                        };
+                        self.inst(0x8F00 | a << 4)?;
+                        self.inst(0x8F05 | b << 4)?;
+                    }
+                    Operand::Immediate(nn) => {
+                        self.inst(0x6F00 | *nn as u16)?;
+                        self.inst(if less { 0x8F07 } else { 0x8F05 } | x >> 4)?;
+                    }
+                }
+                self.inst(if when { 0x3F00 } else { 0x4F00 })
+            }
+        }
+    }
+
+    fn expand_macro(&mut self, token: &Token) -> Result<(), String> {
+        let arg_count = self.macros[&token.text].args.len();
+        let mut values = Vec::with_capacity(arg_count);
+        for _ in 0..arg_count {
+            values.push(self.next()?);
+        }
+
+        let mac = self.macros.get_mut(&token.text).unwrap();
+        let calls = Token {
+            text: mac.calls.to_string(),
+            string: false,
+            line: token.line,
+        };
+        mac.calls += 1;
+        let args: HashMap<&str, &Token> = mac
+            .args
+            .iter()
+            .map(String::as_str)
+            .zip(values.iter())
+            .chain(std::iter::once(("CALLS", &calls)))
+            .collect();
+        let expanded = Self::substitute(&mac.body, &args, token.line);
+        self.push_expansion(expanded)
+    }
+
+    fn expand_string_mode(&mut self, token: &Token) -> Result<(), String> {
+        let text = self.next()?;
+        if !text.string {
+            return Err(format!(
+                "Expected a string for the string mode '{}'",
+                token.text
+            ));
+        }
+
+        let mode = &self.string_modes[&token.text];
+        let mut expanded = Vec::new();
+        for (index, c) in text.text.chars().enumerate() {
+            let (value, body) = mode.get(&c).ok_or_else(|| {
+                format!("The string mode '{}' has no character '{}'", token.text, c)
+            })?;
+            let number = |n: usize| Token {
+                text: n.to_string(),
+                string: false,
+                line: token.line,
+            };
+            let (char_token, index_token, value_token) =
+                (number(c as usize), number(index), number(*value));
+            let args: HashMap<&str, &Token> = vec![
+                ("CHAR", &char_token),
+                ("INDEX", &index_token),
+                ("VALUE", &value_token),
+            ]
+            .into_iter()
+            .collect();
+            expanded.extend(Self::substitute(body, &args, token.line));
+        }
+        self.push_expansion(expanded)
+    }
+
+    fn substitute(body: &[Token], args: &HashMap<&str, &Token>, line: usize) -> Vec<Token> {
+        body.iter()
+            .map(|t| match args.get(t.text.as_str()) {
+                Some(arg) if !t.string => Token {
+                    line,
+                    ..(*arg).clone()
+                },
+                _ => Token { line, ..t.clone() },
+            })
+            .collect()
+    }
+
+    fn push_expansion(&mut self, tokens: Vec<Token>) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(String::from("Too many macro expansions"));
+        }
+        for token in tokens.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        self.tokens
+            .pop_front()
+            .ok_or_else(|| String::from("Unexpected end of the program"))
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens
+            .front()
+            .filter(|t| !t.string)
+            .map(|t| t.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token.string || token.text != text {
+            return Err(format!("Expected '{}', found '{}'", text, token.text));
+        }
+        Ok(())
+    }
+
+    fn is_name(text: &str) -> bool {
+        text.starts_with(|c: char| c.is_alphabetic() || c == '_')
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let token = self.next()?;
+        if token.string || !Self::is_name(&token.text) {
+            return Err(format!("'{}' is not a valid name", token.text));
+        }
+        Ok(token.text)
+    }
+
+    /// Tokens between braces, which may be nested.
+    fn block(&mut self) -> Result<Vec<Token>, String> {
+        self.expect("{")?;
+        let mut depth = 0;
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next()?;
+            if !token.string {
+                match token.text.as_str() {
+                    "{" => depth += 1,
+                    "}" if depth == 0 => return Ok(tokens),
+                    "}" => depth -= 1,
+                    _ => (),
+                }
+            }
+            tokens.push(token);
+        }
+    }
+
+    fn define_label(&mut self, name: String, addr: usize) -> Result<(), String> {
+        if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+            return Err(format!("The name '{}' is already defined", name));
+        }
+        self.labels.insert(name, addr as u16);
+        Ok(())
+    }
+
+    fn define_constant(&mut self, name: String, value: f64) -> Result<(), String> {
+        if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+            return Err(format!("The name '{}' is already defined", name));
+        }
+        self.constants.insert(name, value);
+        Ok(())
+    }
+
+    fn register_of(&self, token: &Token) -> Option<u8> {
+        if token.string {
+            return None;
+        }
+        parse_register(&token.text).or_else(|| self.aliases.get(&token.text).copied())
+    }
+
+    fn register(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        self.register_of(&token)
+            .ok_or_else(|| format!("'{}' is not a register", token.text))
+    }
+
+    /// Value of a number, constant or defined label, `None` for unknown names.
+    fn value_of(&self, token: &Token) -> Result<Option<i64>, String> {
+        if token.string {
+            return Err(format!("Unexpected string \"{}\"", token.text));
+        }
+        Ok(parse_number(&token.text)
+            .or_else(|| {
+                self.constants
+                    .get(&token.text)
+                    .map(|value| value.floor() as i64)
+            })
+            .or_else(|| self.labels.get(&token.text).map(|&addr| addr as i64)))
+    }
+
+    fn check_range(value: i64, min: i64, max: i64) -> Result<i64, String> {
+        if value < min || value > max {
+            return Err(format!("The value {} is out of range", value));
+        }
+        Ok(value)
+    }
+
+    /// Reads a value which has to be known, either a single token or a calculation in braces.
+    fn immediate(&mut self, min: i64, max: i64) -> Result<i64, String> {
+        let token = self.next()?;
+        self.immediate_of(&token, min, max)
+    }
+
+    fn immediate_of(&mut self, token: &Token, min: i64, max: i64) -> Result<i64, String> {
+        let value = if !token.string && token.text == "{" {
+            self.tokens.push_front(token.clone());
+            let block = self.block()?;
+            self.calc(&block)?.floor() as i64
+        } else {
+            self.value_of(token)?
+                .ok_or_else(|| format!("Undefined name '{}'", token.text))?
+        };
+        Self::check_range(value, min, max)
+    }
+
+    /// Emits an instruction with an address operand, which may be defined later.
+    fn address_inst(&mut self, op: u16) -> Result<(), String> {
+        let target = self.next()?;
+        self.inst(op)?;
+        self.reference(self.here - 2, Fixup::Addr12, &target)
+    }
+
+    fn register_inst(&mut self, op: u16) -> Result<(), String> {
+        let x = self.register()? as u16;
+        self.inst(op | x << 8)
+    }
+
+    /// Writes the address of the target, or remembers to do so once it's defined.
+    fn reference(&mut self, pos: usize, fixup: Fixup, target: &Token) -> Result<(), String> {
+        match self.value_of(target)? {
+            Some(addr) => self.patch(pos, fixup, Self::check_range(addr, 0, 0xFFFF)? as u16),
+            None if Self::is_name(&target.text) => {
+                self.references.push(Reference {
+                    pos,
+                    name: target.text.clone(),
+                    fixup,
+                    line: target.line,
+                });
+                Ok(())
+            }
+            None => Err(format!("'{}' is not a valid address", target.text)),
+        }
+    }
+
+    fn patch(&mut self, pos: usize, fixup: Fixup, addr: u16) -> Result<(), String> {
+        let short = || {
+            if addr > 0xFFF {
+                Err(format!(
+                    "The address 0x{:X} is out of range, use 'i := long'",
+                    addr
+                ))
+            } else {
+                Ok(())
+            }
+        };
+        match fixup {
+            Fixup::Addr12 => {
+                short()?;
+                self.memory[pos] = self.memory[pos] & 0xF0 | (addr >> 8) as u8;
+                self.memory[pos + 1] = addr as u8;
+            }
+            Fixup::Addr16 => {
+                self.memory[pos] = (addr >> 8) as u8;
+                self.memory[pos + 1] = addr as u8;
+            }
+            Fixup::Unpack(nibble) => {
+                short()?;
+                self.memory[pos] = nibble << 4 | (addr >> 8) as u8;
+            }
+            Fixup::HighByte => self.memory[pos] = (addr >> 8) as u8,
+            Fixup::LowByte => self.memory[pos] = addr as u8,
+        }
+        Ok(())
+    }
+
+    fn inst(&mut self, op: u16) -> Result<(), String> {
+        self.lines.push((self.here as u16, self.line));
+        self.emit(&op.to_be_bytes())
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if self.here + bytes.len() > MEMORY_SIZE {
+            return Err(String::from("The program doesn't fit into memory"));
+        }
+        self.memory[self.here..self.here + bytes.len()].copy_from_slice(bytes);
+        self.here += bytes.len();
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    /// Evaluates a calculation, which like Octo has no operator precedence and is evaluated
+    /// from right to left unless grouped by parentheses.
+    fn calc(&self, tokens: &[Token]) -> Result<f64, String> {
+        let mut pos = 0;
+        let value = self.calc_expr(tokens, &mut pos)?;
+        match tokens.get(pos) {
+            Some(token) => Err(format!("Unexpected '{}' in calculation", token.text)),
+            None => Ok(value),
+        }
+    }
+
+    fn calc_expr(&self, tokens: &[Token], pos: &mut usize) -> Result<f64, String> {
+        let left = self.calc_term(tokens, pos)?;
+        let op = match tokens.get(*pos) {
+            Some(token) if !token.string && token.text != ")" => token.text.as_str(),
+            _ => return Ok(left),
+        };
+        *pos += 1;
+        let right = self.calc_expr(tokens, pos)?;
+        let (a, b) = (left as i64, right as i64);
+        Ok(match op {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => a.checked_shl(b as u32).unwrap_or(0) as f64,
+            ">>" => a.checked_shr(b as u32).unwrap_or(0) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => return Err(format!("Unknown operator '{}' in calculation", op)),
+        })
+    }
+
+    fn calc_term(&self, tokens: &[Token], pos: &mut usize) -> Result<f64, String> {
+        let token = tokens
+            .get(*pos)
+            .ok_or_else(|| String::from("Incomplete calculation"))?;
+        *pos += 1;
+        if token.string {
+            return Err(format!(
+                "Unexpected string \"{}\" in calculation",
+                token.text
+            ));
+        }
+
+        let unary: Option<fn(f64) -> f64> = match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expr(tokens, pos)?;
+                return match tokens.get(*pos) {
+                    Some(token) if token.text == ")" => {
+                        *pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err(String::from("Missing ')' in calculation")),
+                };
+            }
+            "strlen" => {
+                return match tokens.get(*pos) {
+                    Some(token) if token.string => {
+                        *pos += 1;
+                        Ok(token.text.chars().count() as f64)
+                    }
+                    _ => Err(String::from("Expected a string after 'strlen'")),
+                };
+            }
+            "@" => {
+                let addr = self.calc_term(tokens, pos)? as i64;
+                return match self.memory.get(addr as usize) {
+                    Some(&byte) if addr >= 0 => Ok(byte as f64),
+                    _ => Err(format!("The address {} is out of range", addr)),
+                };
+            }
+            "-" => Some(|v| -v),
+            "~" => Some(|v| !(v as i64) as f64),
+            "!" => Some(|v| (v == 0.0) as i64 as f64),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(|v| if v == 0.0 { 0.0 } else { v.signum() }),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(unary) = unary {
+            return Ok(unary(self.calc_term(tokens, pos)?));
+        }
+
+        match token.text.as_str() {
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(PI),
+            "E" => Ok(E),
+            name => parse_number(name)
+                .map(|value| value as f64)
+                .or_else(|| self.constants.get(name).copied())
+                .or_else(|| self.labels.get(name).map(|&addr| addr as f64))
+                .or_else(|| self.register_of(token).map(|reg| reg as f64))
+                .ok_or_else(|| format!("Undefined name '{}' in calculation", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod octo_assembler_test {
+    use super::*;
+
+    #[test]
+    fn test_instructions() {
+        let program = assemble(
+            ": main
+                v0 := 5
+                loop
+                    v0 += -1  # count down
+                    if v0 != 0 then
+                again
+                i := sprite
+                sprite v0 v1 4
+                jump main
+            : sprite 0xF0 0x90 0x90 0xF0",
+        )
+        .unwrap();
+        assert_eq!(
+            program.rom,
+            [
+                0x60, 0x05, 0x70, 0xFF, 0x30, 0x00, 0x12, 0x02, 0xA2, 0x0E, 0xD0, 0x14, 0x12, 0x00,
+                0xF0, 0x90, 0x90, 0xF0
+            ]
+        );
+        #[cfg(feature = "gui")]
+        assert_eq!(
+            program.labels,
+            [
+                (String::from("main"), 0x200),
+                (String::from("sprite"), 0x20E)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jump_to_main() {
+        let program = assemble(
+            ":const SPEED 3
+            :macro add-speed reg { reg += SPEED }
+            :alias score v2
+            : data 1 2
+            :calc double { SPEED * 2 }
+            : main
+                add-speed v1
+                if v1 > score begin
+                    v3 := 1
+                else
+                    v3 := 2
+                end
+                :byte { double + 1 }",
+        )
+        .unwrap();
+        assert_eq!(
+            program.rom,
+            [
+                0x12, 0x04, 0x01, 0x02, 0x71, 0x03, 0x8F, 0x20, 0x8F, 0x15, 0x3F, 0x00, 0x12, 0x12,
+                0x63, 0x01, 0x12, 0x14, 0x63, 0x02, 0x07
+            ]
+        );
+    }
+
+    #[cfg(feature = "gui")]
+    #[test]
+    fn test_lines() {
+        let program = assemble(
+            ": main
+                v0 := 1
+
+                loop
+                    v0 += 1
+                again",
+        )
+        .unwrap();
+        assert_eq!(program.lines, [(0x200, 2), (0x202, 5), (0x204, 6)]);
+        assert_eq!(program.address_of_line(2), Some((2, 0x200)));
+        assert_eq!(program.address_of_line(3), Some((5, 0x202)));
+        assert_eq!(program.address_of_line(7), None);
+        assert_eq!(program.line_of_address(0x204), Some(6));
+        assert_eq!(program.line_of_address(0x206), None);
+    }
+
+    #[test]
+    fn test_data() {
+        let program = assemble(
+            ": main
+                :unpack 0xA target
+                i := long target
+                :stringmode text \"AB\" { :byte { VALUE + 1 } }
+                text \"BA\"
+                :pointer main
+            :org 0x300
+            : target",
+        )
+        .unwrap();
+        assert_eq!(
+            program.rom,
+            [0x60, 0xA3, 0x61, 0x00, 0xF0, 0x00, 0x03, 0x00, 0x02, 0x01, 0x02, 0x00]
+        );
+        #[cfg(feature = "gui")]
+        assert_eq!(program.labels[1], (String::from("target"), 0x300));
+    }
+
+    #[test]
+    fn test_bootrom() {
+        // Assembled by Octo
+        let program = assemble(include_str!("../data/bootrom/pich8-logo.8o")).unwrap();
+        assert_eq!(
+            program.rom,
+            &include_bytes!("../data/bootrom/pich8-logo.ch8")[..]
+        );
+        assert!(assemble(include_str!("../data/debugrom/debug_example.8o")).is_ok());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            assemble(": main\n  jump nowhere").err().unwrap(),
+            "Line 2: Undefined name 'nowhere'"
+        );
+        assert_eq!(
+            assemble("v0 := 1").err().unwrap(),
+            "Line 1: The program is missing a 'main' label"
+        );
+        assert!(assemble(": main v0 := 256").is_err());
+        assert!(assemble(": main loop").is_err());
+        assert!(assemble(": main :assert \"too big\" { HERE > 0x300 }")
+            .err()
+            .unwrap()
+            .contains("too big"));
+        assert!(assemble(":macro m { m } : main m").is_err());
+    }
+}
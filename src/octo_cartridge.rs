@@ -0,0 +1,114 @@
+//! Octo cartridges: GIF images with the source and options of a program hidden in the pixels.
+//!
+//! The lowest two bits of each palette index carry the data, four pixels per byte starting with the
+//! most significant bits, continuing through all frames. The data is the length of the payload as
+//! big endian `u32` followed by the payload, JSON with the source as `program` and the `options`.
+
+use crate::octo_options::{self, OctoOptions};
+use gif::{ColorOutput, DecodeOptions};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct Cartridge {
+    /// Octo source of the program
+    pub program: String,
+    #[serde(default, deserialize_with = "octo_options::deserialize")]
+    pub options: OctoOptions,
+}
+
+impl Cartridge {
+    const MAGIC: &'static [u8] = b"GIF8";
+
+    pub fn is_cartridge(data: &[u8]) -> bool {
+        data.starts_with(Self::MAGIC)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let payload = Self::decode(data)?;
+        serde_json::from_slice(&payload).map_err(|e| format!("Invalid cartridge: {}", e))
+    }
+
+    /// Extracts the payload from the pixels of the image.
+    fn decode(data: &[u8]) -> Result<Vec<u8>, String> {
+        let mut options = DecodeOptions::new();
+        options.set_color_output(ColorOutput::Indexed);
+        let mut decoder = options
+            .read_info(data)
+            .map_err(|e| format!("Invalid cartridge: {}", e))?;
+
+        let mut crumbs = Vec::new();
+        while let Some(frame) = decoder
+            .read_next_frame()
+            .map_err(|e| format!("Invalid cartridge: {}", e))?
+        {
+            crumbs.extend(frame.buffer.iter().map(|index| index & 0x3));
+        }
+        let bytes: Vec<u8> = crumbs
+            .chunks_exact(4)
+            .map(|crumbs| crumbs[0] << 6 | crumbs[1] << 4 | crumbs[2] << 2 | crumbs[3])
+            .collect();
+
+        let invalid = || String::from("The image is not an Octo cartridge!");
+        let length = bytes.get(..4).ok_or_else(invalid)?;
+        let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+        bytes
+            .get(4..4 + length)
+            .map(|payload| payload.to_vec())
+            .ok_or_else(invalid)
+    }
+}
+
+#[cfg(test)]
+mod octo_cartridge_test {
+    use super::*;
+    use gif::{Encoder, Frame};
+
+    /// Builds a cartridge like Octo, with the data spread over two frames.
+    fn build(payload: &str) -> Vec<u8> {
+        let (width, height) = (16, 16);
+        let mut data = (payload.len() as u32).to_be_bytes().to_vec();
+        data.extend(payload.bytes());
+        let mut pixels: Vec<u8> = data
+            .iter()
+            .flat_map(|byte| vec![byte >> 6, byte >> 4 & 0x3, byte >> 2 & 0x3, byte & 0x3])
+            // Visible image in the upper bits
+            .map(|crumb| crumb | 0x4)
+            .collect();
+        pixels.resize(width * height * 2, 0);
+
+        let palette: Vec<u8> = (0..8).flat_map(|i| vec![i * 32; 3]).collect();
+        let mut gif = Vec::new();
+        {
+            let mut encoder =
+                Encoder::new(&mut gif, width as u16, height as u16, &palette).unwrap();
+            for frame in pixels.chunks(width * height) {
+                let frame = Frame::from_indexed_pixels(width as u16, height as u16, frame, None);
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+        gif
+    }
+
+    #[test]
+    fn test_parse() {
+        let gif = build(
+            r#"{"program": ": main\n  jump main", "options": {"tickrate": 20, "shiftQuirks": true, "screenRotation": 90}}"#,
+        );
+        assert!(Cartridge::is_cartridge(&gif));
+        let cartridge = Cartridge::parse(&gif).unwrap();
+        assert_eq!(cartridge.program, ": main\n  jump main");
+        assert_eq!(cartridge.options.tickrate, Some(20));
+        assert_eq!(cartridge.options.shift_quirks, Some(true));
+        assert_eq!(cartridge.options.unsupported, ["screenRotation"]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Cartridge::parse(b"GIF89a").is_err());
+        assert!(Cartridge::parse(&build("no json")).is_err());
+        // Length exceeding the image
+        let mut gif = build("{}");
+        gif.truncate(gif.len() / 2);
+        assert!(Cartridge::parse(&gif).is_err());
+    }
+}
@@ -0,0 +1,279 @@
+use crate::config::{self, Config};
+use crate::emulator_core::Speed;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// Options of a ROM as exported by Octo, expected next to the ROM with the extension `.json`,
+/// or as the standalone HTML page exported by Octo with the extension `.html`.
+/// Unknown keys are ignored, options without counterpart in pich8 like `screenRotation` are
+/// collected to tell the user about them.
+#[derive(Deserialize, Default, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OctoOptions {
+    /// Instructions per 60 Hz frame
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub tickrate: Option<u32>,
+    /// Hex colors of background, first plane, second plane and both planes
+    pub background_color: Option<String>,
+    pub fill_color: Option<String>,
+    pub fill_color2: Option<String>,
+    pub blend_color: Option<String>,
+    /// 8XY6/8XYE shift VX instead of VY
+    pub shift_quirks: Option<bool>,
+    /// FX55/FX65 leave I unchanged
+    pub load_store_quirks: Option<bool>,
+    /// VF is written before the result of arithmetic
+    pub vf_order_quirks: Option<bool>,
+    /// Sprites are clipped instead of wrapping around the screen edges
+    pub clip_quirks: Option<bool>,
+    /// BNNN jumps to XNN + VX
+    pub jump_quirks: Option<bool>,
+    /// Names of the options pich8 can't apply, if they differ from Octo's defaults
+    #[serde(skip)]
+    pub unsupported: Vec<String>,
+}
+
+impl OctoOptions {
+    const EXTENSIONS: &'static [&'static str] = &["json", "html", "htm"];
+    /// Options without counterpart in pich8 and their default value in Octo
+    const UNSUPPORTED: &'static [(&'static str, &'static str)] = &[
+        ("buzzColor", "#FFAA00"),
+        ("quietColor", "#000000"),
+        ("screenRotation", "0"),
+        ("maxSize", "3584"),
+        ("logicQuirks", "false"),
+        ("vBlankQuirks", "false"),
+        ("fontStyle", "octo"),
+    ];
+
+    /// Parses the options, either on their own or as `options` next to the program like in
+    /// Octo's cartridges.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let value =
+            serde_json::from_str(text).map_err(|e| format!("Invalid options file: {}", e))?;
+        Self::from_value(value)
+    }
+
+    fn from_value(mut value: Value) -> Result<Self, String> {
+        if let Some(options) = value
+            .get_mut("options")
+            .filter(|options| options.is_object())
+        {
+            value = options.take();
+        }
+        let unsupported = Self::UNSUPPORTED
+            .iter()
+            .filter(|(key, default)| match value.get(key) {
+                None | Some(Value::Null) => false,
+                Some(Value::String(text)) => !text.eq_ignore_ascii_case(default),
+                Some(other) => other.to_string().as_str() != *default,
+            })
+            .map(|(key, _)| key.to_string())
+            .collect();
+        let options =
+            serde_json::from_value(value).map_err(|e| format!("Invalid options file: {}", e))?;
+        Ok(Self {
+            unsupported,
+            ..options
+        })
+    }
+
+    /// Extracts the options from a standalone HTML page exported by Octo, in which they're
+    /// assigned to `options` as a JSON object.
+    pub fn parse_html(text: &str) -> Result<Self, String> {
+        let value = text
+            .match_indices("options")
+            .find_map(|(index, name)| Self::assigned_object(&text[index + name.len()..]))
+            .ok_or_else(|| String::from("No Octo options found in the HTML file!"))?;
+        Self::from_value(value)
+    }
+
+    /// Parses the JSON object assigned after a name, as in `options = {...}` or `"options": {...}`.
+    fn assigned_object(text: &str) -> Option<Value> {
+        let text = text.strip_prefix('"').unwrap_or(text).trim_start();
+        let text = text.strip_prefix(|c| c == '=' || c == ':')?.trim_start();
+        if !text.starts_with('{') {
+            return None;
+        }
+        // Only the object is parsed, the script continues after it
+        serde_json::Deserializer::from_str(text)
+            .into_iter::<Value>()
+            .next()?
+            .ok()
+    }
+
+    /// Uses the speed, quirks and colors of the options.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(tickrate) = self.tickrate {
+            config.set_speed(Speed::InstructionsPerFrame(tickrate));
+        }
+
+        // Octo's VF order and clipping quirks are the opposite of pich8's ones
+        let quirks = &mut config.quirks;
+        let wrap = self.clip_quirks.map(|clip| !clip);
+        for (quirk, value) in [
+            (&mut quirks.shift, self.shift_quirks),
+            (&mut quirks.load_store, self.load_store_quirks),
+            (&mut quirks.jump, self.jump_quirks),
+            (
+                &mut quirks.vf_order,
+                self.vf_order_quirks.map(|quirk| !quirk),
+            ),
+            (&mut quirks.partial_wrap_h, wrap),
+            (&mut quirks.partial_wrap_v, wrap),
+        ] {
+            if let Some(value) = value {
+                *quirk = value;
+            }
+        }
+
+        let colors = &mut config.colors;
+        for (color, value) in [
+            (&mut colors.background, &self.background_color),
+            (&mut colors.plane_1, &self.fill_color),
+            (&mut colors.plane_2, &self.fill_color2),
+            (&mut colors.plane_both, &self.blend_color),
+        ] {
+            if let Some(value) = value.as_deref().and_then(config::parse_hex_color) {
+                *color = value;
+            }
+        }
+    }
+
+    /// Looks for an options file belonging to the given ROM file and loads it.
+    /// Returns `None` if there is no such file.
+    pub fn load_for_rom(rom_path: &Path) -> Option<Result<Self, String>> {
+        let path = Self::EXTENSIONS
+            .iter()
+            .map(|extension| rom_path.with_extension(extension))
+            .find(|path| path != rom_path && path.is_file())?;
+        let html = path.extension() != Some("json".as_ref());
+        Some(
+            fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read options file: {}", e))
+                .and_then(|text| {
+                    if html {
+                        Self::parse_html(&text)
+                    } else {
+                        Self::parse(&text)
+                    }
+                }),
+        )
+    }
+}
+
+/// Deserializes options embedded in other JSON, collecting the unsupported options like `parse`.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<OctoOptions, D::Error> {
+    use serde::de::Error;
+    OctoOptions::from_value(Value::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+/// Octo writes some numbers as strings, depending on where they were entered.
+fn number_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    use serde::de::Error;
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Number(number)) => number
+            .as_u64()
+            .map(|number| Some(number as u32))
+            .ok_or_else(|| D::Error::custom(format!("invalid number: {}", number))),
+        Some(Value::String(text)) => text
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| D::Error::custom(format!("invalid number: {}", text))),
+        Some(other) => Err(D::Error::custom(format!("invalid number: {}", other))),
+    }
+}
+
+#[cfg(test)]
+mod octo_options_test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let options = OctoOptions::parse(
+            r##"{"tickrate": 30, "fillColor": "#FFCC00", "shiftQuirks": true}"##,
+        )
+        .unwrap();
+        assert_eq!(options.tickrate, Some(30));
+        assert_eq!(options.fill_color.as_deref(), Some("#FFCC00"));
+        assert_eq!(options.shift_quirks, Some(true));
+        assert_eq!(options.clip_quirks, None);
+        assert_eq!(OctoOptions::parse("{}").unwrap(), OctoOptions::default());
+        assert!(OctoOptions::parse(r#"{"tickrate": "fast"}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_wrapped() {
+        let options =
+            OctoOptions::parse(r#"{"program": "", "options": {"tickrate": "20"}}"#).unwrap();
+        assert_eq!(options.tickrate, Some(20));
+    }
+
+    #[test]
+    fn test_parse_html() {
+        let html = r##"<html><head><script>
+            var program = "0012";
+            // Set the options } of the player
+            var options = {"tickrate":500,"fillColor":"#FFCC00","clipQuirks":true,
+                "touchInputMode":"none","fontStyle":"octo","meta":{"title":"}"}};
+            run(program, options);
+        </script></head></html>"##;
+        let options = OctoOptions::parse_html(html).unwrap();
+        assert_eq!(options.tickrate, Some(500));
+        assert_eq!(options.fill_color.as_deref(), Some("#FFCC00"));
+        assert_eq!(options.clip_quirks, Some(true));
+        assert!(OctoOptions::parse_html("<html></html>").is_err());
+        assert!(OctoOptions::parse_html("<script>run(options);</script>").is_err());
+
+        // Embedded along with the program
+        let html =
+            r#"<script>var cartridge = {"program": "{", "options": {"tickrate": 7}};</script>"#;
+        assert_eq!(OctoOptions::parse_html(html).unwrap().tickrate, Some(7));
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let options = OctoOptions::parse(
+            r##"{"tickrate": 20, "buzzColor": "#FFAA00", "quietColor": "#112233",
+                "screenRotation": 90, "maxSize": "3584", "logicQuirks": false,
+                "vBlankQuirks": true, "fontStyle": "octo"}"##,
+        )
+        .unwrap();
+        assert_eq!(
+            options.unsupported,
+            ["quietColor", "screenRotation", "vBlankQuirks"]
+        );
+
+        let options = OctoOptions::parse(r#"{"logicQuirks": true, "fontStyle": "schip"}"#).unwrap();
+        assert_eq!(options.unsupported, ["logicQuirks", "fontStyle"]);
+    }
+
+    #[test]
+    fn test_load_for_rom() {
+        let dir = std::env::temp_dir().join(format!("pich8-octo-options-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("game.ch8");
+        assert_eq!(OctoOptions::load_for_rom(&rom), None);
+
+        fs::write(
+            dir.join("game.html"),
+            r#"<script>var options = {"tickrate":7};</script>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            OctoOptions::load_for_rom(&rom).unwrap().unwrap().tickrate,
+            Some(7)
+        );
+        // JSON takes precedence over HTML
+        fs::write(dir.join("game.json"), r#"{"tickrate": 8}"#).unwrap();
+        assert_eq!(
+            OctoOptions::load_for_rom(&rom).unwrap().unwrap().tickrate,
+            Some(8)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
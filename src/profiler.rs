@@ -0,0 +1,324 @@
+use crate::symbols::SymbolTable;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct AddressStats {
+    pub address: u16,
+    pub label: Option<String>,
+    pub hits: u64,
+    pub key_wait: u64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RoutineStats {
+    pub address: u16,
+    pub label: Option<String>,
+    pub calls: u64,
+    pub self_cycles: u64,
+    pub total_cycles: u64,
+}
+
+#[derive(Serialize)]
+struct Report {
+    cycles: u64,
+    key_wait_cycles: u64,
+    addresses: Vec<AddressStats>,
+    routines: Vec<RoutineStats>,
+}
+
+#[derive(Clone, Default)]
+struct Routine {
+    calls: u64,
+    self_cycles: u64,
+    total_cycles: u64,
+}
+
+/// Counts executed cycles per address and per subroutine.
+/// Subroutines are tracked by following `2NNN` and `00EE`, code outside of any call
+/// is accounted to the routine where profiling started.
+#[derive(Clone)]
+pub struct Profiler {
+    pub enabled: bool,
+    cycles: u64,
+    key_wait_cycles: u64,
+    hits: BTreeMap<u16, u64>,
+    key_wait: BTreeMap<u16, u64>,
+    routines: BTreeMap<u16, Routine>,
+    call_stack: Vec<u16>,
+    last_pc: u16,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            cycles: 0,
+            key_wait_cycles: 0,
+            hits: BTreeMap::new(),
+            key_wait: BTreeMap::new(),
+            routines: BTreeMap::new(),
+            call_stack: Vec::new(),
+            last_pc: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self {
+            enabled: self.enabled,
+            ..Self::new()
+        };
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn key_wait_cycles(&self) -> u64 {
+        self.key_wait_cycles
+    }
+
+    /// Records the execution of the opcode at the given address.
+    pub fn record(&mut self, pc: u16, opcode: u16) {
+        if self.call_stack.is_empty() {
+            self.call_stack.push(pc);
+            self.routines.entry(pc).or_default().calls += 1;
+        }
+
+        self.cycles += 1;
+        self.last_pc = pc;
+        *self.hits.entry(pc).or_insert(0) += 1;
+        self.account_routines();
+
+        if opcode & 0xF000 == 0x2000 {
+            let target = opcode & 0x0FFF;
+            self.call_stack.push(target);
+            self.routines.entry(target).or_default().calls += 1;
+        } else if opcode == 0x00EE && self.call_stack.len() > 1 {
+            self.call_stack.pop();
+        }
+    }
+
+    /// Records a cycle spent waiting for a key press (`FX0A`).
+    pub fn record_key_wait(&mut self) {
+        self.cycles += 1;
+        self.key_wait_cycles += 1;
+        *self.key_wait.entry(self.last_pc).or_insert(0) += 1;
+        self.account_routines();
+    }
+
+    fn account_routines(&mut self) {
+        for (idx, addr) in self.call_stack.iter().enumerate() {
+            // Recursive calls must only count once
+            if self.call_stack[..idx].contains(addr) {
+                continue;
+            }
+            if let Some(routine) = self.routines.get_mut(addr) {
+                routine.total_cycles += 1;
+            }
+        }
+        if let Some(addr) = self.call_stack.last() {
+            if let Some(routine) = self.routines.get_mut(addr) {
+                routine.self_cycles += 1;
+            }
+        }
+    }
+
+    /// Returns all executed addresses, the most executed first.
+    pub fn hotspots(&self, symbols: &SymbolTable) -> Vec<AddressStats> {
+        let mut addresses: Vec<u16> = self
+            .hits
+            .keys()
+            .chain(self.key_wait.keys())
+            .copied()
+            .collect();
+        addresses.sort_unstable();
+        addresses.dedup();
+
+        let mut hotspots: Vec<AddressStats> = addresses
+            .into_iter()
+            .map(|address| AddressStats {
+                address,
+                label: symbols.format_routine(address),
+                hits: self.hits.get(&address).copied().unwrap_or(0),
+                key_wait: self.key_wait.get(&address).copied().unwrap_or(0),
+            })
+            .collect();
+        hotspots.sort_by(|a, b| {
+            (b.hits + b.key_wait)
+                .cmp(&(a.hits + a.key_wait))
+                .then(a.address.cmp(&b.address))
+        });
+        hotspots
+    }
+
+    /// Returns all entered subroutines, the one with the most cycles first.
+    pub fn routines(&self, symbols: &SymbolTable) -> Vec<RoutineStats> {
+        let mut routines: Vec<RoutineStats> = self
+            .routines
+            .iter()
+            .map(|(address, routine)| RoutineStats {
+                address: *address,
+                label: symbols.label(*address).map(String::from),
+                calls: routine.calls,
+                self_cycles: routine.self_cycles,
+                total_cycles: routine.total_cycles,
+            })
+            .collect();
+        routines.sort_by(|a, b| {
+            b.total_cycles
+                .cmp(&a.total_cycles)
+                .then(a.address.cmp(&b.address))
+        });
+        routines
+    }
+
+    pub fn to_json(&self, symbols: &SymbolTable) -> Result<String, String> {
+        let report = Report {
+            cycles: self.cycles,
+            key_wait_cycles: self.key_wait_cycles,
+            addresses: self.hotspots(symbols),
+            routines: self.routines(symbols),
+        };
+        serde_json::to_string_pretty(&report)
+            .map_err(|e| format!("Failed to export profile: {}", e))
+    }
+
+    /// Exports addresses and subroutines as a single table, distinguished by the first column.
+    pub fn to_csv(&self, symbols: &SymbolTable) -> String {
+        let mut csv =
+            String::from("type,address,label,hits,key_wait,calls,self_cycles,total_cycles\n");
+        for stats in self.hotspots(symbols) {
+            csv.push_str(&format!(
+                "address,{:04X},{},{},{},,,\n",
+                stats.address,
+                stats.label.unwrap_or_default(),
+                stats.hits,
+                stats.key_wait
+            ));
+        }
+        for stats in self.routines(symbols) {
+            csv.push_str(&format!(
+                "routine,{:04X},{},,,{},{},{}\n",
+                stats.address,
+                stats.label.unwrap_or_default(),
+                stats.calls,
+                stats.self_cycles,
+                stats.total_cycles
+            ));
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod profiler_test {
+    use super::*;
+
+    fn run(profiler: &mut Profiler, program: &[(u16, u16)]) {
+        for (pc, opcode) in program {
+            profiler.record(*pc, *opcode);
+        }
+    }
+
+    #[test]
+    fn test_hotspots() {
+        let mut profiler = Profiler::new();
+        run(
+            &mut profiler,
+            &[
+                (0x200, 0x6000),
+                (0x202, 0x7001),
+                (0x204, 0x1202),
+                (0x202, 0x7001),
+            ],
+        );
+        let hotspots = profiler.hotspots(&SymbolTable::new());
+        assert_eq!(profiler.cycles(), 4);
+        assert_eq!(hotspots[0].address, 0x202);
+        assert_eq!(hotspots[0].hits, 2);
+        assert_eq!(hotspots.len(), 3);
+    }
+
+    #[test]
+    fn test_routines() {
+        let mut profiler = Profiler::new();
+        run(
+            &mut profiler,
+            &[
+                (0x200, 0x2300), // call 300
+                (0x300, 0x2400), // call 400
+                (0x400, 0x7001),
+                (0x402, 0x00EE),
+                (0x302, 0x00EE),
+                (0x202, 0x2400), // call 400
+                (0x400, 0x7001),
+                (0x402, 0x00EE),
+                (0x204, 0x1204),
+            ],
+        );
+        let symbols = SymbolTable::parse("main 0x200\nadd 0x400").unwrap();
+        let routines = profiler.routines(&symbols);
+        assert_eq!(
+            routines[0],
+            RoutineStats {
+                address: 0x200,
+                label: Some(String::from("main")),
+                calls: 1,
+                self_cycles: 3,
+                total_cycles: 9,
+            }
+        );
+        assert_eq!(
+            routines[1],
+            RoutineStats {
+                address: 0x300,
+                label: None,
+                calls: 1,
+                self_cycles: 2,
+                total_cycles: 4,
+            }
+        );
+        assert_eq!(
+            routines[2],
+            RoutineStats {
+                address: 0x400,
+                label: Some(String::from("add")),
+                calls: 2,
+                self_cycles: 4,
+                total_cycles: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn test_key_wait() {
+        let mut profiler = Profiler::new();
+        run(&mut profiler, &[(0x200, 0x6000), (0x202, 0xF00A)]);
+        profiler.record_key_wait();
+        profiler.record_key_wait();
+        assert_eq!(profiler.cycles(), 4);
+        assert_eq!(profiler.key_wait_cycles(), 2);
+        let hotspots = profiler.hotspots(&SymbolTable::new());
+        assert_eq!(hotspots[0].address, 0x202);
+        assert_eq!(hotspots[0].key_wait, 2);
+    }
+
+    #[test]
+    fn test_export() {
+        let mut profiler = Profiler::new();
+        run(&mut profiler, &[(0x200, 0x2300), (0x300, 0x00EE)]);
+        let symbols = SymbolTable::parse("draw 0x300").unwrap();
+        assert_eq!(
+            profiler.to_csv(&symbols),
+            "type,address,label,hits,key_wait,calls,self_cycles,total_cycles\n\
+             address,0200,,1,0,,,\n\
+             address,0300,draw,1,0,,,\n\
+             routine,0200,,,,1,1,2\n\
+             routine,0300,draw,,,1,1,1\n"
+        );
+        let json = profiler.to_json(&symbols).unwrap();
+        assert!(json.contains("\"key_wait_cycles\": 0"));
+        assert!(json.contains("\"label\": \"draw\""));
+    }
+}
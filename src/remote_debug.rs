@@ -0,0 +1,114 @@
+use crate::cpu::{Breakpoint, Register, CPU};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+pub enum DebugRequest {
+    ReadRegisters(Vec<Register>),
+    WriteRegister(Register, u16),
+    /// Writes all registers or none of them
+    WriteRegisters(Vec<(Register, u16)>),
+    ReadMemory(u16, usize),
+    WriteMemory(u16, Vec<u8>),
+    SetBreakpoint(Breakpoint),
+    RemoveBreakpoint(Breakpoint),
+    Evaluate(String),
+    StackTrace,
+    LoadRom(String),
+    Halt,
+    Step,
+    Continue,
+    Detach,
+}
+
+/// The current position or a call site, named after the closest label if available.
+pub struct StackFrame {
+    pub address: u16,
+    pub name: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StopReason {
+    Breakpoint,
+    Step,
+    Halt,
+}
+
+pub enum DebugResponse {
+    Ok,
+    Registers(Vec<u16>),
+    Memory(Vec<u8>),
+    Value(u16),
+    StackTrace(Vec<StackFrame>),
+    Stopped(StopReason),
+    Error(String),
+}
+
+/// A request together with the channel the response is sent to.
+pub type RemoteRequest = (DebugRequest, Sender<DebugResponse>);
+
+/// Collects requests of remote debuggers (GDB stub and DAP server), which run in their own threads.
+/// The requests are processed by the emulator, which also owns the breakpoints set remotely.
+pub struct RemoteDebug {
+    chan_tx: Sender<RemoteRequest>,
+    chan_rx: Receiver<RemoteRequest>,
+    breakpoints: Vec<Breakpoint>,
+    pending_stop: Option<Sender<DebugResponse>>,
+}
+
+impl RemoteDebug {
+    pub fn new() -> Self {
+        let (chan_tx, chan_rx) = channel();
+        Self {
+            chan_tx,
+            chan_rx,
+            breakpoints: Vec::new(),
+            pending_stop: None,
+        }
+    }
+
+    pub fn sender(&self) -> Sender<RemoteRequest> {
+        self.chan_tx.clone()
+    }
+
+    pub fn try_recv(&self) -> Option<RemoteRequest> {
+        self.chan_rx.try_recv().ok()
+    }
+
+    pub fn set_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) {
+        self.breakpoints.retain(|bp| bp != breakpoint);
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn check_breakpoints(&self, cpu: &CPU) -> bool {
+        self.breakpoints.iter().any(|bp| cpu.check_breakpoint(bp))
+    }
+
+    /// Stores the channel to notify once the emulation stops again.
+    pub fn wait_for_stop(&mut self, response_tx: Sender<DebugResponse>) {
+        self.pending_stop = Some(response_tx);
+    }
+
+    pub fn is_waiting_for_stop(&self) -> bool {
+        self.pending_stop.is_some()
+    }
+
+    pub fn notify_stop(&mut self, reason: StopReason) {
+        if let Some(response_tx) = self.pending_stop.take() {
+            let _ = response_tx.send(DebugResponse::Stopped(reason));
+        }
+    }
+
+    /// Removes all breakpoints and pending notifications, e.g. when the debugger detaches.
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.pending_stop = None;
+    }
+}
@@ -0,0 +1,337 @@
+//! Settings of known ROMs, identified by their SHA-1, in the format of the community
+//! [CHIP-8 database](https://github.com/chip-8/chip-8-database).
+//!
+//! A small database is bundled. The files `programs.json` and `platforms.json` in the user's
+//! database directory are loaded on top of it, so the full community database can be placed there
+//! and entries for the same ROM or platform override the bundled ones.
+
+use crate::config;
+use crate::emulator_core::Speed;
+use crate::state_file;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PROGRAMS_FILE: &str = "programs.json";
+const PLATFORMS_FILE: &str = "platforms.json";
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    description: Option<String>,
+    #[serde(default)]
+    authors: Vec<String>,
+    release: Option<String>,
+    roms: HashMap<String, RomEntry>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct RomEntry {
+    /// Platforms the ROM runs on, the preferred one first
+    platforms: Vec<String>,
+    /// Quirks differing from those of the platform
+    quirky_platforms: HashMap<String, PlatformQuirks>,
+    tickrate: Option<u32>,
+    colors: Option<RomColors>,
+    /// CHIP-8 key of each input, e.g. "up" or "a"
+    keys: BTreeMap<String, u8>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RomColors {
+    /// Hex colors of background, first plane, second plane and both planes
+    pixels: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Platform {
+    id: String,
+    name: String,
+    default_tickrate: Option<u32>,
+    quirks: PlatformQuirks,
+}
+
+/// Quirks of a platform as named in the database.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PlatformQuirks {
+    /// 8XY6/8XYE shift VX instead of VY
+    pub shift: bool,
+    /// FX55/FX65 increment I by X instead of X + 1
+    pub memory_increment_by_x: bool,
+    /// FX55/FX65 leave I unchanged
+    pub memory_leave_i_unchanged: bool,
+    /// Sprites wrap around the screen edges instead of being clipped
+    pub wrap: bool,
+    /// BNNN jumps to XNN + VX
+    pub jump: bool,
+    /// Drawing waits for the vertical blank interrupt
+    pub vblank: bool,
+    /// 8XY1/8XY2/8XY3 reset VF
+    pub logic: bool,
+}
+
+/// Everything known about a ROM, resolved against its preferred platform.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub release: Option<String>,
+    pub description: Option<String>,
+    /// Name of the preferred platform
+    pub platform: Option<String>,
+    pub quirks: Option<PlatformQuirks>,
+    pub speed: Option<Speed>,
+    /// Colors of background, first plane, second plane and both planes, as many as given
+    pub colors: Vec<[u8; 3]>,
+    /// Inputs with the CHIP-8 key they are mapped to
+    pub keys: Vec<(String, u8)>,
+}
+
+pub struct RomDatabase {
+    programs: Vec<Program>,
+    /// Index into the programs by SHA-1 of the ROM
+    hashes: HashMap<String, usize>,
+    platforms: HashMap<String, Platform>,
+}
+
+impl RomDatabase {
+    fn new() -> Self {
+        Self {
+            programs: Vec::new(),
+            hashes: HashMap::new(),
+            platforms: HashMap::new(),
+        }
+    }
+
+    /// The database shipped with pich8.
+    pub fn bundled() -> Self {
+        let mut database = Self::new();
+        database
+            .add(
+                include_str!("../data/database/programs.json"),
+                include_str!("../data/database/platforms.json"),
+            )
+            .expect("Invalid bundled ROM database");
+        database
+    }
+
+    /// Directory of the user's database, `None` if the platform has no config directory.
+    pub fn user_dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("pich8").join("database"))
+    }
+
+    /// Adds programs and platforms, replacing existing entries for the same ROM or platform.
+    pub fn add(&mut self, programs: &str, platforms: &str) -> Result<(), String> {
+        let programs: Vec<Program> = serde_json::from_str(programs)
+            .map_err(|e| format!("Invalid ROM database ({}): {}", PROGRAMS_FILE, e))?;
+        let platforms: Vec<Platform> = serde_json::from_str(platforms)
+            .map_err(|e| format!("Invalid ROM database ({}): {}", PLATFORMS_FILE, e))?;
+
+        for program in programs {
+            for hash in program.roms.keys() {
+                self.hashes.insert(hash.to_lowercase(), self.programs.len());
+            }
+            self.programs.push(program);
+        }
+        for platform in platforms {
+            self.platforms.insert(platform.id.clone(), platform);
+        }
+        Ok(())
+    }
+
+    /// Adds the database files found in the given directory, missing files are treated as empty.
+    pub fn add_dir(&mut self, dir: &Path) -> Result<(), String> {
+        let read = |name: &str| {
+            let path = dir.join(name);
+            if !path.is_file() {
+                return Ok(String::from("[]"));
+            }
+            fs::read_to_string(path).map_err(|e| format!("Failed to read ROM database: {}", e))
+        };
+        self.add(&read(PROGRAMS_FILE)?, &read(PLATFORMS_FILE)?)
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomInfo> {
+        let hash = state_file::rom_hash(rom);
+        let program = &self.programs[*self.hashes.get(&hash)?];
+        let entry = program
+            .roms
+            .iter()
+            .find(|(key, _)| key.to_lowercase() == hash)
+            .map(|(_, entry)| entry)?;
+
+        let platform_id = entry.platforms.first();
+        let platform = platform_id.and_then(|id| self.platforms.get(id));
+        let quirks = platform_id
+            .and_then(|id| entry.quirky_platforms.get(id))
+            .or_else(|| platform.map(|platform| &platform.quirks))
+            .copied();
+        let tickrate = entry
+            .tickrate
+            .or_else(|| platform.and_then(|platform| platform.default_tickrate));
+
+        Some(RomInfo {
+            title: program.title.clone(),
+            authors: program.authors.clone(),
+            release: program.release.clone(),
+            description: program.description.clone(),
+            platform: platform
+                .map(|platform| platform.name.clone())
+                .or_else(|| platform_id.cloned()),
+            quirks,
+            speed: tickrate.map(Speed::InstructionsPerFrame),
+            colors: entry
+                .colors
+                .iter()
+                .flat_map(|colors| &colors.pixels)
+                .take(4)
+                .map_while(|color| config::parse_hex_color(color))
+                .collect(),
+            keys: entry
+                .keys
+                .iter()
+                .map(|(input, &key)| (input.clone(), key))
+                .collect(),
+        })
+    }
+}
+
+impl RomInfo {
+    /// Uses the quirks, speed and colors of the ROM, as far as they're known.
+    pub fn apply(&self, config: &mut config::Config) {
+        if let Some(quirks) = self.quirks {
+            config.quirks.load_store = quirks.memory_leave_i_unchanged;
+            config.quirks.shift = quirks.shift;
+            config.quirks.jump = quirks.jump;
+            config.quirks.partial_wrap_h = quirks.wrap;
+            config.quirks.partial_wrap_v = quirks.wrap;
+        }
+        if let Some(speed) = self.speed {
+            config.set_speed(speed);
+        }
+        let colors = &mut config.colors;
+        let mut targets = [
+            &mut colors.background,
+            &mut colors.plane_1,
+            &mut colors.plane_2,
+            &mut colors.plane_both,
+        ];
+        for (target, color) in targets.iter_mut().zip(&self.colors) {
+            **target = *color;
+        }
+    }
+}
+
+/// Keyboard key a CHIP-8 key is mapped to, in the QWERTY layout.
+#[cfg(feature = "gui")]
+pub fn keyboard_key(key: u8) -> Option<char> {
+    "X123QWEASDZC4RFV".chars().nth(key as usize)
+}
+
+#[cfg(test)]
+mod rom_database_test {
+    use super::*;
+
+    const PLATFORMS: &str = r#"[
+        {"id": "superchip", "name": "SUPER-CHIP 1.1", "defaultTickrate": 30,
+         "quirks": {"shift": true, "memoryLeaveIUnchanged": true, "jump": true}}
+    ]"#;
+
+    #[test]
+    fn test_lookup() {
+        let rom = [0x12, 0x00];
+        let programs = format!(
+            r##"[{{
+                "title": "Loop",
+                "authors": ["Someone"],
+                "roms": {{
+                    "{}": {{
+                        "platforms": ["superchip"],
+                        "colors": {{"pixels": ["#000000", "#FFCC00", "invalid"]}},
+                        "keys": {{"up": 5, "a": 6}}
+                    }}
+                }}
+            }}]"##,
+            state_file::rom_hash(&rom).to_uppercase()
+        );
+        let mut database = RomDatabase::new();
+        database.add(&programs, PLATFORMS).unwrap();
+
+        let info = database.lookup(&rom).unwrap();
+        assert_eq!(info.title, "Loop");
+        assert_eq!(info.platform.as_deref(), Some("SUPER-CHIP 1.1"));
+        assert_eq!(info.speed, Some(Speed::InstructionsPerFrame(30)));
+        assert!(info.quirks.unwrap().shift);
+        assert!(!info.quirks.unwrap().wrap);
+        assert_eq!(info.colors, vec![[0x00, 0x00, 0x00], [0xFF, 0xCC, 0x00]]);
+        assert_eq!(
+            info.keys,
+            vec![(String::from("a"), 6), (String::from("up"), 5)]
+        );
+        assert_eq!(database.lookup(&[0x00, 0xE0]), None);
+    }
+
+    #[test]
+    fn test_override() {
+        let rom = [0x12, 0x00];
+        let hash = state_file::rom_hash(&rom);
+        let mut database = RomDatabase::new();
+        database
+            .add(
+                &format!(
+                    r#"[{{"title": "Loop", "roms": {{"{}": {{"platforms": ["superchip"]}}}}}}]"#,
+                    hash
+                ),
+                PLATFORMS,
+            )
+            .unwrap();
+        database
+            .add(
+                &format!(
+                    r#"[{{"title": "My Loop", "roms": {{"{}": {{
+                        "platforms": ["superchip"],
+                        "tickrate": 100,
+                        "quirkyPlatforms": {{"superchip": {{"wrap": true}}}}
+                    }}}}}}]"#,
+                    hash
+                ),
+                "[]",
+            )
+            .unwrap();
+
+        let info = database.lookup(&rom).unwrap();
+        assert_eq!(info.title, "My Loop");
+        assert_eq!(info.speed, Some(Speed::InstructionsPerFrame(100)));
+        assert_eq!(
+            info.quirks,
+            Some(PlatformQuirks {
+                wrap: true,
+                ..PlatformQuirks::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_bundled() {
+        let database = RomDatabase::bundled();
+        let info = database
+            .lookup(include_bytes!("../data/bootrom/pich8-logo.ch8"))
+            .unwrap();
+        assert_eq!(info.platform.as_deref(), Some("SUPER-CHIP 1.1"));
+        assert!(RomDatabase::new().add("{}", "[]").is_err());
+    }
+
+    #[cfg(feature = "gui")]
+    #[test]
+    fn test_keyboard_key() {
+        assert_eq!(keyboard_key(0x5), Some('W'));
+        assert_eq!(keyboard_key(0x0), Some('X'));
+        assert_eq!(keyboard_key(0xF), Some('V'));
+        assert_eq!(keyboard_key(0x10), None);
+    }
+}
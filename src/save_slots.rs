@@ -0,0 +1,240 @@
+use crate::video_memory::{Plane, VideoMemory};
+use image::{codecs::png::PngEncoder, ColorType, ImageEncoder, ImageFormat};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const SLOT_COUNT: usize = 8;
+
+/// Contents of a slot file, the state in the same format as a saved state file.
+#[derive(Serialize, Deserialize)]
+struct SlotFile {
+    /// Seconds since the Unix epoch
+    timestamp: u64,
+    /// PNG image of the screen
+    thumbnail: Vec<u8>,
+    state: Vec<u8>,
+}
+
+/// Decoded screen image of a slot.
+pub struct Thumbnail {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 3]>,
+}
+
+pub struct SlotInfo {
+    pub timestamp: SystemTime,
+    pub thumbnail: Option<Thumbnail>,
+}
+
+/// Numbered quick-save slots of a ROM, stored in a directory per ROM in the user's data directory.
+pub struct SaveSlots {
+    dir: Option<PathBuf>,
+    slots: Vec<Option<SlotInfo>>,
+}
+
+impl SaveSlots {
+    const EXTENSION: &'static str = "p8slot";
+
+    /// Slots which can't be used, e.g. if no ROM is loaded.
+    pub fn none() -> Self {
+        Self {
+            dir: None,
+            slots: (0..SLOT_COUNT).map(|_| None).collect(),
+        }
+    }
+
+    /// Slots stored in the given directory, reading the existing ones.
+    pub fn new(dir: PathBuf) -> Self {
+        let mut slots = Self::none();
+        slots.slots = (0..SLOT_COUNT)
+            .map(|slot| {
+                let file = fs::read(Self::path(&dir, slot)).ok()?;
+                let file: SlotFile = rmp_serde::from_slice(&file).ok()?;
+                Some(SlotInfo::from(&file))
+            })
+            .collect();
+        slots.dir = Some(dir);
+        slots
+    }
+
+    /// Slots of the given ROM, identified by its content.
+    pub fn for_rom(rom: &[u8]) -> Self {
+        match dirs::data_dir() {
+            Some(dir) => Self::new(
+                dir.join("pich8")
+                    .join("slots")
+                    .join(format!("{:016x}", Self::hash(rom))),
+            ),
+            None => Self::none(),
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// Information on each slot, `None` if empty.
+    pub fn slots(&self) -> &[Option<SlotInfo>] {
+        &self.slots
+    }
+
+    pub fn save(&mut self, slot: usize, state: Vec<u8>, thumbnail: Vec<u8>) -> Result<(), String> {
+        let dir = self.dir()?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let file = SlotFile {
+            timestamp,
+            thumbnail,
+            state,
+        };
+        let data = rmp_serde::to_vec(&file).map_err(|e| format!("Failed to save slot: {}", e))?;
+        fs::create_dir_all(&dir)
+            .and_then(|_| fs::write(Self::path(&dir, slot), data))
+            .map_err(|e| format!("Failed to save slot: {}", e))?;
+        self.slots[slot] = Some(SlotInfo::from(&file));
+        Ok(())
+    }
+
+    /// Returns the state saved in the slot.
+    pub fn load(&self, slot: usize) -> Result<Vec<u8>, String> {
+        let dir = self.dir()?;
+        if self.slots[slot].is_none() {
+            return Err(format!("Slot {} is empty!", slot + 1));
+        }
+        let file =
+            fs::read(Self::path(&dir, slot)).map_err(|e| format!("Failed to load slot: {}", e))?;
+        let file: SlotFile =
+            rmp_serde::from_slice(&file).map_err(|e| format!("Failed to load slot: {}", e))?;
+        Ok(file.state)
+    }
+
+    fn dir(&self) -> Result<PathBuf, String> {
+        self.dir
+            .clone()
+            .ok_or_else(|| String::from("Slots are only available for ROMs!"))
+    }
+
+    fn path(dir: &Path, slot: usize) -> PathBuf {
+        dir.join(format!("slot{}.{}", slot + 1, Self::EXTENSION))
+    }
+
+    /// FNV-1a, stable across platforms and versions unlike the hasher of the standard library.
+    fn hash(data: &[u8]) -> u64 {
+        data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+}
+
+impl From<&SlotFile> for SlotInfo {
+    fn from(file: &SlotFile) -> Self {
+        let thumbnail = image::load_from_memory_with_format(&file.thumbnail, ImageFormat::Png)
+            .ok()
+            .map(|image| {
+                let image = image.to_rgb8();
+                Thumbnail {
+                    width: image.width() as usize,
+                    height: image.height() as usize,
+                    pixels: image.pixels().map(|pixel| pixel.0).collect(),
+                }
+            });
+        Self {
+            timestamp: UNIX_EPOCH + Duration::from_secs(file.timestamp),
+            thumbnail,
+        }
+    }
+}
+
+/// Encodes the screen as PNG with the colors of background, first plane, second plane and both planes.
+pub fn thumbnail_png(vmem: &VideoMemory, palette: [[u8; 3]; 4]) -> Result<Vec<u8>, String> {
+    let (width, height) = (vmem.render_width(), vmem.render_height());
+    let pixels: Vec<u8> = (0..width * height)
+        .flat_map(|idx| {
+            palette[vmem.get_index_plane(Plane::First, idx) as usize
+                | (vmem.get_index_plane(Plane::Second, idx) as usize) << 1]
+        })
+        .collect();
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .write_image(&pixels, width as u32, height as u32, ColorType::Rgb8)
+        .map_err(|e| format!("Failed to create thumbnail: {}", e))?;
+    Ok(png)
+}
+
+/// Describes how long ago the given time was, e.g. "5 minutes ago".
+pub fn format_age(timestamp: SystemTime, now: SystemTime) -> String {
+    let seconds = now.duration_since(timestamp).unwrap_or_default().as_secs();
+    let (value, unit) = match seconds {
+        0..=59 => return String::from("just now"),
+        60..=3599 => (seconds / 60, "minute"),
+        3600..=86399 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+    format!(
+        "{} {}{} ago",
+        value,
+        unit,
+        if value == 1 { "" } else { "s" }
+    )
+}
+
+#[cfg(test)]
+mod save_slots_test {
+    use super::*;
+
+    #[test]
+    fn test_save_load() {
+        let dir = std::env::temp_dir().join(format!("pich8-slots-{}", std::process::id()));
+        let mut vmem = VideoMemory::new();
+        vmem.set_plane(Plane::First, 1, 0, true);
+        let palette = [[0, 0, 0], [255, 255, 255], [85, 85, 85], [170, 170, 170]];
+        let thumbnail = thumbnail_png(&vmem, palette).unwrap();
+
+        let mut slots = SaveSlots::new(dir.clone());
+        assert!(slots.load(2).is_err());
+        slots.save(2, b"p8s123".to_vec(), thumbnail).unwrap();
+        assert_eq!(slots.load(2).unwrap(), b"p8s123");
+
+        // Slots are found again
+        let slots = SaveSlots::new(dir.clone());
+        assert!(slots.slots()[0].is_none());
+        let thumbnail = slots.slots()[2]
+            .as_ref()
+            .unwrap()
+            .thumbnail
+            .as_ref()
+            .unwrap();
+        assert_eq!(thumbnail.width, vmem.render_width());
+        assert_eq!(thumbnail.pixels[0], [0, 0, 0]);
+        assert_eq!(
+            thumbnail.pixels[vmem.to_index(vmem.render_width() / vmem.width(), 0)],
+            [255, 255, 255]
+        );
+        fs::remove_dir_all(dir).unwrap();
+
+        assert!(SaveSlots::none().load(0).is_err());
+    }
+
+    #[test]
+    fn test_format_age() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        assert_eq!(format_age(now, now), "just now");
+        assert_eq!(
+            format_age(now - Duration::from_secs(60), now),
+            "1 minute ago"
+        );
+        assert_eq!(
+            format_age(now - Duration::from_secs(7300), now),
+            "2 hours ago"
+        );
+        assert_eq!(
+            format_age(now - Duration::from_secs(86400 * 3), now),
+            "3 days ago"
+        );
+    }
+}
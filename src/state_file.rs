@@ -0,0 +1,190 @@
+//! The `.p8s` state file: a versioned header with metadata, followed by the serialized CPU.
+//!
+//! Version 0 files are the magic followed directly by the CPU, as written by pich8 up to 1.0.1.
+//! Since version 1, the magic is followed by a zero byte, which can't start a serialized CPU,
+//! the format version as little endian `u16`, the header length as little endian `u32`, the header
+//! and the CPU, both serialized with field names, so fields can be added without breaking old files.
+
+use crate::cpu::CPU;
+use crate::emulator_core::Speed;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const MAGIC: &[u8] = b"p8s";
+pub const VERSION: u16 = 1;
+
+/// Metadata stored in front of the CPU state. All fields are optional to read older and foreign files.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct StateHeader {
+    /// Version of pich8 which saved the state
+    pub pich8_version: String,
+    /// SHA-1 of the ROM the state was saved for, as lowercase hex
+    pub rom_hash: Option<String>,
+    pub rom_name: Option<String>,
+    /// Platform or quirks preset the ROM was running with
+    pub platform: Option<String>,
+    pub speed: Option<Speed>,
+    /// Colors of background, first plane, second plane and both planes
+    pub palette: Option<[[u8; 3]; 4]>,
+    /// Seconds since the Unix epoch
+    pub created: u64,
+}
+
+impl StateHeader {
+    /// Header for a state saved now by this version of pich8.
+    pub fn new() -> Self {
+        Self {
+            pich8_version: String::from(env!("CARGO_PKG_VERSION")),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            ..Self::default()
+        }
+    }
+
+    /// Returns a warning if the state was saved for a different ROM than the given one.
+    /// States without ROM hash are accepted silently.
+    pub fn check_rom(&self, rom: &[u8]) -> Option<String> {
+        match &self.rom_hash {
+            Some(hash) if *hash != rom_hash(rom) => Some(format!(
+                "This state was saved for a different ROM{}, it may not work correctly!",
+                self.rom_name
+                    .as_ref()
+                    .map(|name| format!(" ({})", name))
+                    .unwrap_or_default()
+            )),
+            _ => None,
+        }
+    }
+}
+
+pub struct StateFile {
+    /// Version the file was saved with, older ones are migrated when loading.
+    pub version: u16,
+    pub header: StateHeader,
+    pub cpu: CPU,
+}
+
+impl StateFile {
+    pub fn is_state(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let data = data
+            .strip_prefix(MAGIC)
+            .ok_or_else(|| String::from("Not a state file!"))?;
+        if data.first() != Some(&0) {
+            return Self::migrate_v0(data);
+        }
+
+        let invalid = || String::from("Invalid state file header!");
+        let version = u16::from_le_bytes(data.get(1..3).ok_or_else(invalid)?.try_into().unwrap());
+        if version > VERSION {
+            return Err(format!(
+                "The state was saved by a newer version of pich8 (format version {})!",
+                version
+            ));
+        }
+        let header_len =
+            u32::from_le_bytes(data.get(3..7).ok_or_else(invalid)?.try_into().unwrap()) as usize;
+        let header = data.get(7..7 + header_len).ok_or_else(invalid)?;
+        let header = rmp_serde::from_slice(header).map_err(|_| invalid())?;
+        Ok(Self {
+            version,
+            header,
+            cpu: CPU::from_state(&data[7 + header_len..])?,
+        })
+    }
+
+    /// Version 0 had no header, the CPU was serialized without field names.
+    fn migrate_v0(data: &[u8]) -> Result<Self, String> {
+        Ok(Self {
+            version: 0,
+            header: StateHeader::default(),
+            cpu: CPU::from_state(data)?,
+        })
+    }
+
+    /// Serializes the state in the current format version.
+    pub fn to_bytes(header: &StateHeader, cpu: &CPU) -> Result<Vec<u8>, String> {
+        let header =
+            rmp_serde::to_vec_named(header).map_err(|_| "Failed to serialize state header!")?;
+        let mut data = MAGIC.to_vec();
+        data.push(0);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        data.extend(header);
+        data.extend(cpu.save_state()?);
+        Ok(data)
+    }
+}
+
+/// SHA-1 of a ROM as lowercase hex, as used by ROM databases.
+pub fn rom_hash(rom: &[u8]) -> String {
+    Sha1::digest(rom)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod state_file_test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let rom = [0x12, 0x00];
+        let mut cpu = CPU::new();
+        cpu.load_rom(&rom).unwrap();
+        cpu.set_DT(42);
+        let header = StateHeader {
+            rom_hash: Some(rom_hash(&rom)),
+            rom_name: Some(String::from("loop.ch8")),
+            speed: Some(Speed::InstructionsPerFrame(30)),
+            ..StateHeader::new()
+        };
+
+        let data = StateFile::to_bytes(&header, &cpu).unwrap();
+        assert!(StateFile::is_state(&data));
+        let state = StateFile::parse(&data).unwrap();
+        assert_eq!(state.version, VERSION);
+        assert_eq!(state.header, header);
+        assert_eq!(state.cpu.DT(), 42);
+
+        assert_eq!(state.header.check_rom(&rom), None);
+        let warning = state.header.check_rom(&[0x00, 0xE0]).unwrap();
+        assert!(warning.contains("loop.ch8"));
+    }
+
+    #[test]
+    fn test_migrate_v0() {
+        let mut cpu = CPU::new();
+        cpu.set_DT(42);
+        let mut data = MAGIC.to_vec();
+        data.extend(rmp_serde::to_vec(&cpu).unwrap());
+
+        let state = StateFile::parse(&data).unwrap();
+        assert_eq!(state.version, 0);
+        assert_eq!(state.header, StateHeader::default());
+        assert_eq!(state.cpu.DT(), 42);
+        assert_eq!(state.header.check_rom(&[0x12, 0x00]), None);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(StateFile::parse(b"ch8").is_err());
+        assert!(StateFile::parse(b"p8s").is_err());
+        assert!(StateFile::parse(b"p8s\x00\x01").is_err());
+        assert!(StateFile::parse(b"p8s\x00\xFF\xFF\x00\x00\x00\x00").is_err());
+    }
+
+    #[test]
+    fn test_rom_hash() {
+        assert_eq!(rom_hash(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    }
+}
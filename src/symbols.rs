@@ -0,0 +1,203 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Label map for a ROM, e.g. the symbol table exported by Octo.
+/// The file is expected next to the ROM with the extension `.sym`, one label per line.
+/// Name and address may be separated by whitespace, `=` or `:` and appear in either order,
+/// addresses are given as hex (`0x2A4`, `$2A4`) or decimal. Lines starting with `#` or `;` are ignored.
+pub struct SymbolTable {
+    by_addr: BTreeMap<u16, String>,
+    by_name: HashMap<String, u16>,
+}
+
+impl SymbolTable {
+    const EXTENSION: &'static str = "sym";
+
+    pub fn new() -> Self {
+        Self {
+            by_addr: BTreeMap::new(),
+            by_name: HashMap::new(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut symbols = Self::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            let tokens: Vec<&str> = line
+                .split(|c: char| c.is_whitespace() || c == '=' || c == ':')
+                .filter(|t| !t.is_empty())
+                .collect();
+            let symbol = match tokens[..] {
+                [first, second] => {
+                    match (Self::parse_address(first), Self::parse_address(second)) {
+                        (Some(addr), _) => Some((second, addr)),
+                        (None, Some(addr)) => Some((first, addr)),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            match symbol {
+                Some((name, addr)) => symbols.insert(name, addr),
+                None => return Err(format!("Invalid symbol in line {}: {}", line_no + 1, line)),
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    /// Looks for a symbol file belonging to the given ROM file and loads it.
+    /// Returns `None` if there is no such file.
+    pub fn load_for_rom(rom_path: &Path) -> Option<Result<Self, String>> {
+        let mut appended = rom_path.as_os_str().to_owned();
+        appended.push(".");
+        appended.push(Self::EXTENSION);
+        let candidates = [
+            rom_path.with_extension(Self::EXTENSION),
+            PathBuf::from(appended),
+        ];
+
+        let path = candidates
+            .iter()
+            .find(|p| p.as_path() != rom_path && p.is_file())?;
+        Some(
+            fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read symbol file: {}", e))
+                .and_then(|text| Self::parse(&text)),
+        )
+    }
+
+    pub fn insert(&mut self, name: &str, addr: u16) {
+        // The first label wins if several labels share an address
+        self.by_addr.entry(addr).or_insert_with(|| name.to_string());
+        self.by_name.insert(name.to_string(), addr);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    /// Returns the label pointing exactly at the given address.
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.by_addr.get(&addr).map(|s| s.as_str())
+    }
+
+    /// Returns the address of the given label.
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+
+    /// Returns the closest label at or before the given address and the offset from it.
+    pub fn routine(&self, addr: u16) -> Option<(&str, u16)> {
+        self.by_addr
+            .range(..=addr)
+            .next_back()
+            .map(|(label_addr, name)| (name.as_str(), addr - label_addr))
+    }
+
+    /// Formats an address relative to the closest label, e.g. `main-loop+6`.
+    pub fn format_routine(&self, addr: u16) -> Option<String> {
+        self.routine(addr).map(|(name, offset)| {
+            if offset == 0 {
+                name.to_string()
+            } else {
+                format!("{}+{}", name, offset)
+            }
+        })
+    }
+
+    /// Appends the label of the target address to the description of jump, call and load index opcodes.
+    pub fn annotate_opcode(&self, opcode: u16, description: &str) -> String {
+        let target = match opcode & 0xF000 {
+            0x1000 | 0x2000 | 0xA000 | 0xB000 => self.label(opcode & 0x0FFF),
+            _ => None,
+        };
+        match target {
+            Some(label) => format!("{} <{}>", description, label),
+            None => description.to_string(),
+        }
+    }
+
+    /// Resolves a breakpoint value, which can either be a label or a hex address.
+    pub fn resolve(&self, value: &str) -> Option<u16> {
+        self.address(value)
+            .or_else(|| u16::from_str_radix(value, 16).ok())
+    }
+
+    fn parse_address(token: &str) -> Option<u16> {
+        if let Some(hex) = token
+            .strip_prefix("0x")
+            .or_else(|| token.strip_prefix("0X"))
+            .or_else(|| token.strip_prefix('$'))
+        {
+            u16::from_str_radix(hex, 16).ok()
+        } else if token.chars().all(|c| c.is_ascii_digit()) {
+            token.parse().ok()
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod symbols_test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let symbols = SymbolTable::parse(
+            "# Octo symbols\nmain 0x200\nmain-loop = 0x2A4\n0x300 draw-player\nscore: 800\n\n",
+        )
+        .unwrap();
+        assert_eq!(symbols.address("main"), Some(0x200));
+        assert_eq!(symbols.address("main-loop"), Some(0x2A4));
+        assert_eq!(symbols.address("draw-player"), Some(0x300));
+        assert_eq!(symbols.address("score"), Some(800));
+        assert_eq!(symbols.label(0x2A4), Some("main-loop"));
+        assert_eq!(symbols.label(0x2A6), None);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(SymbolTable::parse("main").is_err());
+        assert!(SymbolTable::parse("main loop 0x200").is_err());
+        assert!(SymbolTable::parse("main 0xZZZ").is_err());
+    }
+
+    #[test]
+    fn test_routine() {
+        let symbols = SymbolTable::parse("main 0x200\nmain-loop 0x2A4").unwrap();
+        assert_eq!(symbols.routine(0x100), None);
+        assert_eq!(symbols.routine(0x200), Some(("main", 0)));
+        assert_eq!(symbols.routine(0x2A2), Some(("main", 0xA2)));
+        assert_eq!(symbols.routine(0x2AA), Some(("main-loop", 6)));
+        assert_eq!(symbols.format_routine(0x2A4).unwrap(), "main-loop");
+        assert_eq!(symbols.format_routine(0x2AA).unwrap(), "main-loop+6");
+    }
+
+    #[test]
+    fn test_annotate_and_resolve() {
+        let symbols = SymbolTable::parse("main-loop 0x2A4\nadd 0x300").unwrap();
+        assert_eq!(
+            symbols.annotate_opcode(0x22A4, "CALL 2A4"),
+            "CALL 2A4 <main-loop>"
+        );
+        assert_eq!(symbols.annotate_opcode(0x62A4, "LD V2, A4"), "LD V2, A4");
+        assert_eq!(symbols.resolve("main-loop"), Some(0x2A4));
+        assert_eq!(symbols.resolve("add"), Some(0x300));
+        assert_eq!(symbols.resolve("2A6"), Some(0x2A6));
+        assert_eq!(symbols.resolve("nope"), None);
+    }
+}
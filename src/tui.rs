@@ -0,0 +1,435 @@
+//! Terminal frontend rendering the screen with Unicode half blocks, usable without a GPU e.g. over SSH.
+
+use crate::config::{Config, Quirks};
+use crate::cpu::CPU;
+use crate::emulator_core::{EmulatorCore, Speed, TIMER_FREQUENCY};
+use crate::file_type::{self, FileType, Platform};
+use crate::octo_assembler;
+use crate::octo_cartridge::Cartridge;
+use crate::octo_options::OctoOptions;
+use crate::rom_database::RomDatabase;
+use crate::state_file::StateFile;
+use crate::video_memory::Plane;
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{
+        self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    execute, queue,
+    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use std::{
+    io::{self, Stdout, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// Frames a key stays pressed if the terminal doesn't report key releases.
+/// Long enough to bridge the delay until the key repeat of the terminal kicks in.
+const KEY_HOLD_FRAMES: u8 = 10;
+/// Restores the terminal when dropped, also if the frontend panics.
+struct TerminalGuard {
+    enhanced_keyboard: bool,
+}
+
+impl TerminalGuard {
+    fn new(out: &mut Stdout) -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(out, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+        let enhanced_keyboard = matches!(terminal::supports_keyboard_enhancement(), Ok(true));
+        if enhanced_keyboard {
+            execute!(
+                out,
+                PushKeyboardEnhancementFlags(
+                    KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                        | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                )
+            )?;
+        }
+        Ok(Self { enhanced_keyboard })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        if self.enhanced_keyboard {
+            let _ = execute!(out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(out, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+pub struct Tui {
+    core: EmulatorCore,
+    file: Vec<u8>,
+    quirks: Quirks,
+    /// Background, first plane, second plane and both planes
+    palette: [Color; 4],
+    out: Stdout,
+    /// Remaining frames per key, keys with enhanced keyboard support are held until released.
+    keys: [u8; 16],
+    enhanced_keyboard: bool,
+    error: Option<String>,
+    quit: bool,
+    width: usize,
+    force_redraw: bool,
+}
+
+impl Tui {
+    /// Runs the ROM or state file until Escape is pressed, with the settings of the ROM applied
+    /// to the user's ones. A speed given on the command line takes precedence over both.
+    pub fn run(file_path: &str, config: &Config, speed: Option<Speed>) -> Result<(), String> {
+        let mut database = RomDatabase::bundled();
+        if let Some(dir) = RomDatabase::user_dir() {
+            database.add_dir(&dir)?;
+        }
+        let (file, config) = Self::open(Path::new(file_path), config, &database)?;
+        let mut core = EmulatorCore::new();
+        core.set_cpu(Self::create_cpu(&file, &config.quirks)?);
+        core.set_speed(speed.unwrap_or_else(|| config.speed()));
+        let colors = &config.colors;
+        let palette = [
+            colors.background,
+            colors.plane_1,
+            colors.plane_2,
+            colors.plane_both,
+        ]
+        .map(|[r, g, b]| Color::Rgb { r, g, b });
+        let mut tui = Self {
+            core,
+            file,
+            quirks: config.quirks,
+            palette,
+            out: io::stdout(),
+            keys: [0; 16],
+            enhanced_keyboard: false,
+            error: None,
+            quit: false,
+            width: 0,
+            force_redraw: true,
+        };
+
+        let guard = TerminalGuard::new(&mut tui.out).map_err(|err| format!("Error: {}", err))?;
+        tui.enhanced_keyboard = guard.enhanced_keyboard;
+        tui.run_loop().map_err(|err| format!("Error: {}", err))
+    }
+
+    /// Reads a ROM or state file, assembling Octo programs and extracting ZIP archives.
+    /// ROMs run with the same settings as in the window: those of the platform, the ROM database
+    /// and Octo options applied to the user's ones.
+    fn open(
+        path: &Path,
+        user: &Config,
+        database: &RomDatabase,
+    ) -> Result<(Vec<u8>, Config), String> {
+        let (file_type, path, file) = file_type::read(path)?;
+        let assemble = |source: &str| {
+            octo_assembler::assemble(source)
+                .map(|program| program.rom)
+                .map_err(|msg| format!("Failed to assemble the program: {}", msg))
+        };
+        let (rom, platform, options) = match file_type {
+            FileType::State => return Ok((file, user.clone())),
+            FileType::Rom(platform) => (
+                file,
+                platform,
+                OctoOptions::load_for_rom(&path).transpose()?,
+            ),
+            FileType::OctoSource => (
+                assemble(&String::from_utf8_lossy(&file))?,
+                Some(Platform::Octo),
+                OctoOptions::load_for_rom(&path).transpose()?,
+            ),
+            FileType::Cartridge => {
+                let cartridge = Cartridge::parse(&file)?;
+                (
+                    assemble(&cartridge.program)?,
+                    Some(Platform::Octo),
+                    Some(cartridge.options),
+                )
+            }
+            FileType::Zip => unreachable!("ZIP archives are extracted when read"),
+        };
+        let info = database.lookup(&rom);
+        let config = user.for_rom(platform, info.as_ref(), options.as_ref());
+        Ok((rom, config))
+    }
+
+    fn create_cpu(file: &[u8], quirks: &Quirks) -> Result<CPU, String> {
+        // Check if it's a p8s state file, otherwise expect ROM
+        let mut cpu = if StateFile::is_state(file) {
+            StateFile::parse(file)?.cpu
+        } else {
+            let mut cpu = CPU::new();
+            cpu.load_rom(file)?;
+            cpu
+        };
+        quirks.apply(&mut cpu);
+        Ok(cpu)
+    }
+
+    fn run_loop(&mut self) -> io::Result<()> {
+        let frame_duration = Duration::from_nanos(1_000_000_000 / TIMER_FREQUENCY as u64);
+        let mut next_frame = Instant::now();
+        while !self.quit {
+            // Handle input until the next frame is due
+            while let Some(timeout) = next_frame.checked_duration_since(Instant::now()) {
+                if event::poll(timeout)? {
+                    self.handle_event(event::read()?);
+                }
+            }
+
+            // Don't try to catch up if the terminal was too slow
+            next_frame += frame_duration;
+            if Instant::now() > next_frame + frame_duration * 4 {
+                next_frame = Instant::now() + frame_duration;
+            }
+
+            if !self.core.paused() && self.error.is_none() {
+                self.run_frame();
+            }
+            self.draw()?;
+        }
+        Ok(())
+    }
+
+    fn run_frame(&mut self) {
+        self.core.input = std::array::from_fn(|key| self.keys[key] > 0);
+        if let Err(e) = self.core.run_frame(&mut ()) {
+            self.error = Some(format!("Error: {}", e));
+            return;
+        }
+
+        if !self.enhanced_keyboard {
+            for frames in self.keys.iter_mut() {
+                *frames = frames.saturating_sub(1);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        match Self::create_cpu(&self.file, &self.quirks) {
+            Ok(cpu) => {
+                self.core.set_cpu(cpu);
+                self.error = None;
+            }
+            Err(e) => self.error = Some(e),
+        }
+        self.keys = [0; 16];
+        self.force_redraw = true;
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Key(key) => self.handle_key(key),
+            Event::Resize(..) => self.force_redraw = true,
+            _ => (),
+        }
+    }
+
+    fn handle_key(
+        &mut self,
+        KeyEvent {
+            code,
+            modifiers,
+            kind,
+            ..
+        }: KeyEvent,
+    ) {
+        let pressed = kind != KeyEventKind::Release;
+        match code {
+            // Command keys
+            KeyCode::Esc if pressed => self.quit = true,
+            KeyCode::Char('c') if pressed && modifiers.contains(KeyModifiers::CONTROL) => {
+                self.quit = true
+            }
+            KeyCode::F(5) if pressed => self.reset(),
+            KeyCode::Char('p') if kind == KeyEventKind::Press => {
+                self.core.set_paused(!self.core.paused())
+            }
+
+            // Chip8 keys - terminals only report characters, so the layout can't be accounted for
+            KeyCode::Char(c) => {
+                let key = match c.to_ascii_lowercase() {
+                    '1' => 0x1,
+                    '2' => 0x2,
+                    '3' => 0x3,
+                    '4' => 0xC,
+                    'q' => 0x4,
+                    'w' => 0x5,
+                    'e' => 0x6,
+                    'r' => 0xD,
+                    'a' => 0x7,
+                    's' => 0x8,
+                    'd' => 0x9,
+                    'f' => 0xE,
+                    'z' => 0xA,
+                    'x' => 0x0,
+                    'c' => 0xB,
+                    'v' => 0xF,
+                    _ => return,
+                };
+                self.keys[key] = match (pressed, self.enhanced_keyboard) {
+                    (false, _) => 0,
+                    (true, true) => u8::MAX,
+                    (true, false) => KEY_HOLD_FRAMES,
+                };
+            }
+            _ => (),
+        }
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        let vmem = self.core.cpu.vmem();
+        let (width, height) = (vmem.width(), vmem.height());
+        if width != self.width {
+            // Resolution change moves the side panel
+            self.width = width;
+            self.force_redraw = true;
+        }
+        if self.force_redraw {
+            queue!(self.out, ResetColor, Clear(ClearType::All))?;
+        }
+
+        // Each character cell shows two pixels, the upper one as foreground of the half block
+        if self.core.cpu.draw || self.force_redraw {
+            self.core.cpu.draw = false;
+            let vmem = self.core.cpu.vmem();
+            let palette = self.palette;
+            let pixel = |x, y| {
+                palette[vmem.get_plane(Plane::First, x, y) as usize
+                    | (vmem.get_plane(Plane::Second, x, y) as usize) << 1]
+            };
+            let mut colors = None;
+            for row in 0..height / 2 {
+                queue!(self.out, MoveTo(0, row as u16))?;
+                for x in 0..width {
+                    let cell = (pixel(x, row * 2), pixel(x, row * 2 + 1));
+                    if colors != Some(cell) {
+                        queue!(
+                            self.out,
+                            SetForegroundColor(cell.0),
+                            SetBackgroundColor(cell.1)
+                        )?;
+                        colors = Some(cell);
+                    }
+                    queue!(self.out, Print('▀'))?;
+                }
+            }
+            queue!(self.out, ResetColor)?;
+        }
+
+        // Side panel
+        let column = width as u16 + 2;
+        let v = self.core.cpu.V();
+        let mut lines = (0..8)
+            .map(|i| format!("V{:X}: {:02X}   V{:X}: {:02X}", i, v[i], i + 8, v[i + 8]))
+            .collect::<Vec<_>>();
+        lines.push(String::new());
+        lines.push(format!(
+            "I:  {:04X}  PC: {:04X}",
+            self.core.cpu.I(),
+            self.core.cpu.PC()
+        ));
+        lines.push(format!(
+            "SP: {:02X}    DT: {:02X}",
+            self.core.cpu.sp(),
+            self.core.cpu.DT()
+        ));
+        lines.push(format!("ST: {:02X}", self.core.cpu.ST()));
+        lines.push(String::new());
+        lines.push(format!(
+            "{:04X} {}",
+            self.core.cpu.opcode(),
+            self.core.cpu.opcode_description()
+        ));
+        lines.push(format!("Speed: {}", self.core.speed()));
+        lines.push(String::from(match (&self.error, self.core.paused()) {
+            (Some(e), _) => e.as_str(),
+            (None, true) => "Paused",
+            (None, false) => "",
+        }));
+        lines.push(String::from("P pause, F5 reset, Esc quit"));
+        for (row, line) in lines.iter().enumerate() {
+            queue!(
+                self.out,
+                MoveTo(column, row as u16),
+                Clear(ClearType::UntilNewLine),
+                Print(line)
+            )?;
+        }
+
+        self.force_redraw = false;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tui_test {
+    use super::*;
+    use crate::state_file;
+    use std::fs;
+
+    #[test]
+    fn test_open() {
+        let dir = std::env::temp_dir().join(format!("pich8-tui-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut user = Config {
+            quirks: Quirks::octo(),
+            ..Config::default()
+        };
+        user.set_speed(Speed::Frequency(500));
+
+        let rom = [0x12, 0x00];
+        let mut database = RomDatabase::bundled();
+        database
+            .add(
+                &format!(
+                    r#"[{{"title": "Loop", "roms": {{"{}": {{"platforms": ["superchip"]}}}}}}]"#,
+                    state_file::rom_hash(&rom)
+                ),
+                r#"[{"id": "superchip", "name": "SUPER-CHIP 1.1", "defaultTickrate": 30,
+                     "quirks": {"shift": true, "memoryLeaveIUnchanged": true, "jump": true}}]"#,
+            )
+            .unwrap();
+
+        // Unknown ROMs run with the user's settings
+        fs::write(dir.join("other.ch8"), [0x00, 0xE0, 0x12, 0x02]).unwrap();
+        let (_, config) = Tui::open(&dir.join("other.ch8"), &user, &database).unwrap();
+        assert_eq!(config, user);
+
+        // The ROM database applies on top of the user's settings
+        fs::write(dir.join("loop.ch8"), rom).unwrap();
+        let (file, config) = Tui::open(&dir.join("loop.ch8"), &user, &database).unwrap();
+        assert_eq!(file, rom);
+        assert_eq!(config.speed(), Speed::InstructionsPerFrame(30));
+        assert!(config.quirks.shift && config.quirks.load_store && config.quirks.jump);
+        assert!(!config.quirks.partial_wrap_h && config.quirks.vf_order);
+        let cpu = Tui::create_cpu(&file, &config.quirks).unwrap();
+        assert!(cpu.quirk_shift && !cpu.quirk_partialwrap_v);
+
+        // Octo options next to the ROM take precedence over the database
+        fs::write(
+            dir.join("loop.json"),
+            r##"{"tickrate": 7, "fillColor": "#FF0000", "shiftQuirks": false}"##,
+        )
+        .unwrap();
+        let (_, config) = Tui::open(&dir.join("loop.ch8"), &user, &database).unwrap();
+        assert_eq!(config.speed(), Speed::InstructionsPerFrame(7));
+        assert_eq!(config.colors.plane_1, [0xFF, 0x00, 0x00]);
+        assert!(!config.quirks.shift && config.quirks.jump);
+
+        // Octo sources run with the quirks and speed of Octo
+        fs::write(dir.join("game.8o"), ": main\n  clear\n  jump main\n").unwrap();
+        let (file, config) =
+            Tui::open(&dir.join("game.8o"), &Config::default(), &database).unwrap();
+        assert_eq!(file, [0x00, 0xE0, 0x12, 0x00]);
+        assert_eq!(config.quirks, Quirks::octo());
+        assert_eq!(config.speed(), Platform::Octo.speed());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -15,7 +15,7 @@ pub enum Plane {
     Both,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct VideoMemory {
     vmem1: Box<[bool]>,
     vmem2: Box<[bool]>,
@@ -40,6 +40,11 @@ impl VideoMemory {
         }
     }
 
+    /// Whether both planes have the full size, which deserialized memory might not.
+    pub fn is_valid(&self) -> bool {
+        self.vmem1.len() == 128 * 64 && self.vmem2.len() == 128 * 64
+    }
+
     pub fn select_plane(&mut self, plane: Plane) {
         self.plane = plane;
     }
@@ -127,6 +132,15 @@ impl VideoMemory {
         }
     }
 
+    /// Translates render coordinates to screen coordinates, which differ in Default mode due to the upscaling.
+    pub fn render_to_logical(&self, x: usize, y: usize) -> (usize, usize) {
+        if self.video_mode == VideoMode::Default {
+            (x / 2, y / 2)
+        } else {
+            (x, y)
+        }
+    }
+
     pub fn to_index(&self, x: usize, y: usize) -> usize {
         y * self.render_width() + x
     }
@@ -245,6 +259,12 @@ impl VideoMemory {
     }
 }
 
+impl Default for VideoMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod video_memory_test {
     use super::*;
@@ -281,6 +301,17 @@ mod video_memory_test {
         assert_eq!(vmem.render_height(), 64);
     }
 
+    #[test]
+    fn test_render_to_logical() {
+        let mut vmem = VideoMemory::new();
+        assert_eq!(vmem.render_to_logical(127, 63), (63, 31));
+        assert_eq!(vmem.render_to_logical(10, 11), (5, 5));
+        vmem.video_mode = VideoMode::HiRes;
+        assert_eq!(vmem.render_to_logical(63, 63), (63, 63));
+        vmem.video_mode = VideoMode::Extended;
+        assert_eq!(vmem.render_to_logical(127, 63), (127, 63));
+    }
+
     #[test]
     #[should_panic]
     fn test_get_index_out_of_bounds_hires() {